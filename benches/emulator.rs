@@ -0,0 +1,156 @@
+// Emulator throughput on a few workloads, in millions of Hack instructions a
+// second. Runs without libtest, like the golden suite, and has no
+// dependencies beyond the crate itself.
+//
+//     cargo bench --bench emulator
+//     cargo bench --bench emulator -- fibonacci    # only matching workloads
+use hack_vm::assembler::assemble;
+use hack_vm::compiler::{read_numbered_commands, translate_to_string};
+use hack_vm::emulator::Emulator;
+use std::time::{Duration, Instant};
+
+// each workload runs at least this long, over as many repetitions as fit
+const MIN_TIME: Duration = Duration::from_secs(1);
+
+// sum 1..30000 into RAM[17] a hundred times: tight A/D/M traffic
+const SUM_LOOP: &str = "
+    @100
+    D=A
+    @outer
+    M=D
+(OUTER)
+    @30000
+    D=A
+    @i
+    M=D
+    @sum
+    M=0
+(LOOP)
+    @i
+    D=M
+    @sum
+    M=D+M
+    @i
+    MD=M-1
+    @LOOP
+    D;JGT
+    @outer
+    MD=M-1
+    @OUTER
+    D;JGT
+(END)
+    @END
+    0;JMP
+";
+
+// recursive fibonacci, mostly call and return
+const FIBONACCI: &str = "
+function Sys.init 0
+push constant 22
+call Main.fibonacci 1
+pop temp 0
+label END
+goto END
+function Main.fibonacci 0
+push argument 0
+push constant 2
+lt
+if-goto BASE
+push argument 0
+push constant 2
+sub
+call Main.fibonacci 1
+push argument 0
+push constant 1
+sub
+call Main.fibonacci 1
+add
+return
+label BASE
+push argument 0
+return
+";
+
+// fill the screen black and white again, 20 times: pointer and that
+// segment access
+const FILL_SCREEN: &str = "
+function Sys.init 2
+push constant 40
+pop local 0
+label FRAME
+push constant 8192
+pop local 1
+label PIXELS
+push constant 16384
+push local 1
+add
+pop pointer 1
+push local 0
+push constant 2
+and
+push constant 0
+eq
+pop that 0
+push local 1
+push constant 1
+sub
+pop local 1
+push local 1
+push constant 0
+gt
+if-goto PIXELS
+push local 0
+push constant 1
+sub
+pop local 0
+push local 0
+push constant 0
+gt
+if-goto FRAME
+label END
+goto END
+";
+
+fn vm_rom(source: &str) -> Vec<u16> {
+    let commands = read_numbered_commands("Sys.vm", source, false).unwrap();
+    let commands = commands.into_iter().map(|(_, command)| command).collect();
+    let asm = translate_to_string(&[("Sys".to_string(), commands)], true, false).unwrap();
+    assemble("Sys.asm", &asm).unwrap().rom
+}
+
+// instructions per second over repeated runs to the halt
+fn bench(rom: &[u16]) -> (u64, f64) {
+    let start = Instant::now();
+    let mut cycles = 0;
+    while start.elapsed() < MIN_TIME {
+        let mut emulator = Emulator::new(rom.to_vec());
+        assert!(emulator.run(u64::MAX), "the workload did not halt");
+        cycles += emulator.cycles;
+    }
+    (cycles, cycles as f64 / start.elapsed().as_secs_f64())
+}
+
+fn main() {
+    // cargo bench passes `--bench`; anything else filters by name
+    let filter: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let workloads = [
+        ("sum_loop", assemble("Sum.asm", SUM_LOOP).unwrap().rom),
+        ("fibonacci", vm_rom(FIBONACCI)),
+        ("fill_screen", vm_rom(FILL_SCREEN)),
+    ];
+    for (name, rom) in &workloads {
+        if !filter.is_empty() && !filter.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        let (cycles, rate) = bench(rom);
+        println!(
+            "bench {:<12} {:>7.1}M instructions/s ({} instructions)",
+            name,
+            rate / 1e6,
+            cycles
+        );
+    }
+}
@@ -0,0 +1,485 @@
+use crate::parser::Command;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+// the stack runs from RAM[256] to RAM[2047]
+pub const STACK_SIZE: usize = 1792;
+
+// saved return address, LCL, ARG, THIS and THAT pushed by every call
+const FRAME_SIZE: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub file: String,
+    pub nvars: i16,
+    pub calls: Vec<String>,
+    pub callers: Vec<String>,
+    pub recursive: bool,
+    // words of stack used by the function and everything it calls, from its
+    // locals upwards; None when recursion makes it unbounded or the working
+    // stack could not be followed
+    pub max_stack_depth: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StaticUsage {
+    pub file: String,
+    pub indices: Vec<i16>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Analysis {
+    pub functions: Vec<FunctionInfo>,
+    pub recursive_groups: Vec<Vec<String>>,
+    pub entry: Vec<String>,
+    pub max_stack_depth: Option<usize>,
+    pub statics: Vec<StaticUsage>,
+    // called but never defined in the program, counted as a bare call frame
+    pub undefined: Vec<String>,
+}
+
+struct Function<'a> {
+    name: &'a str,
+    file: &'a str,
+    nvars: i16,
+    body: &'a [Command],
+}
+
+pub fn analyze(files: &[(String, Vec<Command>)]) -> Analysis {
+    let functions = split_functions(files);
+    let index: HashMap<&str, usize> = functions
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.name, i))
+        .collect();
+
+    // call graph, callees in order of first call
+    let mut calls: Vec<Vec<String>> = Vec::new();
+    let mut undefined = BTreeSet::new();
+    for function in &functions {
+        let mut callees: Vec<String> = Vec::new();
+        for command in function.body {
+            if let Command::Call(name, _) = command {
+                if !callees.contains(name) {
+                    callees.push(name.to_string());
+                }
+                if !index.contains_key(name.as_str()) {
+                    undefined.insert(name.to_string());
+                }
+            }
+        }
+        calls.push(callees);
+    }
+    let edges: Vec<Vec<usize>> = calls
+        .iter()
+        .map(|callees| {
+            callees
+                .iter()
+                .filter_map(|name| index.get(name.as_str()).copied())
+                .collect()
+        })
+        .collect();
+
+    let recursive_groups = recursive_groups(&edges);
+    let mut recursive = vec![false; functions.len()];
+    for group in &recursive_groups {
+        for &i in group {
+            recursive[i] = true;
+        }
+    }
+
+    let mut depths: Vec<Option<Option<usize>>> = vec![None; functions.len()];
+    for i in 0..functions.len() {
+        stack_depth(i, &functions, &index, &recursive, &mut depths);
+    }
+
+    let mut callers: Vec<Vec<String>> = vec![Vec::new(); functions.len()];
+    for (i, targets) in edges.iter().enumerate() {
+        for &target in targets {
+            if !callers[target].contains(&functions[i].name.to_string()) {
+                callers[target].push(functions[i].name.to_string());
+            }
+        }
+    }
+
+    // the bootstrap calls Sys.init, otherwise any function nobody calls is an entry point
+    let entry: Vec<usize> = match index.get("Sys.init") {
+        Some(&i) => vec![i],
+        None => (0..functions.len())
+            .filter(|&i| callers[i].is_empty())
+            .collect(),
+    };
+    let bootstrap_frame = if index.contains_key("Sys.init") {
+        FRAME_SIZE
+    } else {
+        0
+    };
+    let max_stack_depth = entry.iter().try_fold(0, |max, &i| {
+        depths[i]
+            .flatten()
+            .map(|depth| max.max(depth + bootstrap_frame))
+    });
+
+    Analysis {
+        functions: functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| FunctionInfo {
+                name: f.name.to_string(),
+                file: f.file.to_string(),
+                nvars: f.nvars,
+                calls: calls[i].clone(),
+                callers: callers[i].clone(),
+                recursive: recursive[i],
+                max_stack_depth: depths[i].flatten(),
+            })
+            .collect(),
+        recursive_groups: recursive_groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|&i| functions[i].name.to_string())
+                    .collect()
+            })
+            .collect(),
+        entry: entry
+            .iter()
+            .map(|&i| functions[i].name.to_string())
+            .collect(),
+        max_stack_depth,
+        statics: static_usage(files),
+        undefined: undefined.into_iter().collect(),
+    }
+}
+
+// the `function` command and the end of each function in a file, a function
+// runs up to the next one
+pub fn function_spans<'a>(commands: impl IntoIterator<Item = &'a Command>) -> Vec<(usize, usize)> {
+    let mut starts = Vec::new();
+    let mut len = 0;
+    for (i, command) in commands.into_iter().enumerate() {
+        if matches!(command, Command::Function(_, _)) {
+            starts.push(i);
+        }
+        len = i + 1;
+    }
+    let ends = starts.iter().skip(1).copied().chain([len]);
+    starts.iter().copied().zip(ends).collect()
+}
+
+fn split_functions(files: &[(String, Vec<Command>)]) -> Vec<Function<'_>> {
+    let mut functions = Vec::new();
+    for (file, commands) in files {
+        for (start, end) in function_spans(commands) {
+            if let Command::Function(name, nvars) = &commands[start] {
+                functions.push(Function {
+                    name,
+                    file,
+                    nvars: *nvars,
+                    body: &commands[start + 1..end],
+                });
+            }
+        }
+    }
+    functions
+}
+
+fn static_usage(files: &[(String, Vec<Command>)]) -> Vec<StaticUsage> {
+    files
+        .iter()
+        .map(|(file, commands)| {
+            let indices: BTreeSet<i16> = commands
+                .iter()
+                .filter_map(|c| match c {
+                    Command::Push(segment, index) | Command::Pop(segment, index)
+                        if segment == "static" =>
+                    {
+                        Some(*index)
+                    }
+                    _ => None,
+                })
+                .collect();
+            StaticUsage {
+                file: file.to_string(),
+                indices: indices.into_iter().collect(),
+            }
+        })
+        .filter(|usage| !usage.indices.is_empty())
+        .collect()
+}
+
+// strongly connected components that contain a cycle (Tarjan's algorithm)
+fn recursive_groups(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    struct Tarjan<'a> {
+        edges: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        lowlink: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        next: usize,
+        groups: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.next);
+            self.lowlink[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+
+            for &w in &self.edges[v] {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.lowlink[v] = self.lowlink[v].min(self.lowlink[w]);
+                    }
+                    Some(index) if self.on_stack[w] => {
+                        self.lowlink[v] = self.lowlink[v].min(index);
+                    }
+                    _ => {}
+                }
+            }
+
+            if Some(self.lowlink[v]) == self.index[v] {
+                let mut group = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    group.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                if group.len() > 1 || self.edges[v].contains(&v) {
+                    group.sort();
+                    self.groups.push(group);
+                }
+            }
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        edges,
+        index: vec![None; edges.len()],
+        lowlink: vec![0; edges.len()],
+        on_stack: vec![false; edges.len()],
+        stack: Vec::new(),
+        next: 0,
+        groups: Vec::new(),
+    };
+    for v in 0..edges.len() {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.groups.sort();
+    tarjan.groups
+}
+
+// memoised in `depths`: None = not computed yet, Some(None) = unbounded
+fn stack_depth(
+    i: usize,
+    functions: &[Function],
+    index: &HashMap<&str, usize>,
+    recursive: &[bool],
+    depths: &mut [Option<Option<usize>>],
+) -> Option<usize> {
+    if let Some(depth) = depths[i] {
+        return depth;
+    }
+    if recursive[i] {
+        depths[i] = Some(None);
+        return None;
+    }
+
+    let function = &functions[i];
+    let depth = working_stack(function.body).and_then(|sites| {
+        let mut max = 0;
+        for (depth, callee) in sites {
+            let callee_depth = match callee.and_then(|name| index.get(name)) {
+                Some(&callee) => stack_depth(callee, functions, index, recursive, depths)?,
+                None => 0,
+            };
+            let total = match callee {
+                Some(_) => depth + FRAME_SIZE + callee_depth,
+                None => depth,
+            };
+            max = max.max(total);
+        }
+        Some(function.nvars as usize + max)
+    });
+    depths[i] = Some(depth);
+    depth
+}
+
+// words a command leaves on the stack, less the ones it takes off; a call
+// replaces its arguments with the return value
+pub fn stack_effect(command: &Command) -> i32 {
+    match command {
+        Command::Push(_, _) => 1,
+        Command::Pop(_, _) | Command::IfGoto(_) => -1,
+        Command::Arithmetic(op) if op == "neg" || op == "not" => 0,
+        Command::Arithmetic(_) => -1,
+        Command::Call(_, nargs) => 1 - i32::from(*nargs),
+        _ => 0,
+    }
+}
+
+// working stack depth before every command a function body can reach, following
+// its gotos; index body.len() means a path falls off the end. None if the depth
+// at a label differs between paths or a label is missing
+pub fn stack_depths(body: &[Command]) -> Option<Vec<(usize, i32)>> {
+    let labels: HashMap<&str, usize> = body
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| match c {
+            Command::Label(label) => Some((label.as_str(), i)),
+            _ => None,
+        })
+        .collect();
+    let mut depths: HashMap<usize, i32> = HashMap::new();
+    let mut reached = Vec::new();
+    let mut pending = vec![(0, 0)];
+
+    while let Some((i, depth)) = pending.pop() {
+        match depths.get(&i) {
+            Some(&seen) if seen == depth => continue,
+            Some(_) => return None,
+            None => {
+                depths.insert(i, depth);
+            }
+        }
+        reached.push((i, depth));
+        let Some(command) = body.get(i) else {
+            continue;
+        };
+        let next = depth + stack_effect(command);
+        match command {
+            Command::Return => {}
+            Command::Goto(label) | Command::IfGoto(label) => {
+                pending.push((*labels.get(label.as_str())?, next));
+                if matches!(command, Command::IfGoto(_)) {
+                    pending.push((i + 1, next));
+                }
+            }
+            _ => pending.push((i + 1, next)),
+        }
+    }
+    Some(reached)
+}
+
+// working stack depth at every command, paired with the function called there;
+// None if the depth at a label differs between paths or a label is missing
+fn working_stack(body: &[Command]) -> Option<Vec<(usize, Option<&str>)>> {
+    let mut sites = Vec::new();
+    for (i, depth) in stack_depths(body)? {
+        let depth = depth.max(0) as usize;
+        match body.get(i) {
+            Some(Command::Call(name, _)) => {
+                sites.push((depth, None));
+                sites.push((depth, Some(name.as_str())));
+            }
+            Some(_) => sites.push((depth, None)),
+            None => {}
+        }
+    }
+    Some(sites)
+}
+
+impl Analysis {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "functions: {}", self.functions.len()).unwrap();
+        for function in &self.functions {
+            writeln!(
+                out,
+                "  {} ({}.vm, {} locals){}",
+                function.name,
+                function.file,
+                function.nvars,
+                if function.recursive { " recursive" } else { "" }
+            )
+            .unwrap();
+            if !function.calls.is_empty() {
+                writeln!(out, "    calls: {}", function.calls.join(", ")).unwrap();
+            }
+            if !function.callers.is_empty() {
+                writeln!(out, "    called by: {}", function.callers.join(", ")).unwrap();
+            }
+            match function.max_stack_depth {
+                Some(depth) => writeln!(out, "    max stack depth: {}", depth).unwrap(),
+                None => writeln!(out, "    max stack depth: unbounded").unwrap(),
+            }
+        }
+
+        if self.recursive_groups.is_empty() {
+            writeln!(out, "recursion: none").unwrap();
+        } else {
+            writeln!(out, "recursion:").unwrap();
+            for group in &self.recursive_groups {
+                writeln!(out, "  {}", group.join(", ")).unwrap();
+            }
+        }
+
+        match self.max_stack_depth {
+            Some(depth) => writeln!(
+                out,
+                "max stack depth from {}: {} of {} words{}",
+                self.entry.join(", "),
+                depth,
+                STACK_SIZE,
+                if depth > STACK_SIZE {
+                    " (overflows)"
+                } else {
+                    ""
+                }
+            )
+            .unwrap(),
+            None => writeln!(out, "max stack depth: unbounded").unwrap(),
+        }
+
+        if self.statics.is_empty() {
+            writeln!(out, "statics: none").unwrap();
+        } else {
+            writeln!(out, "statics:").unwrap();
+        }
+        for usage in &self.statics {
+            let indices: Vec<String> = usage.indices.iter().map(|i| i.to_string()).collect();
+            writeln!(out, "  {}: {}", usage.file, indices.join(", ")).unwrap();
+        }
+
+        if !self.undefined.is_empty() {
+            writeln!(out, "undefined: {}", self.undefined.join(", ")).unwrap();
+        }
+        out
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph calls {\n");
+        for function in &self.functions {
+            let style = if function.recursive {
+                " [color=red]"
+            } else {
+                ""
+            };
+            writeln!(out, "    \"{}\"{};", function.name, style).unwrap();
+        }
+        for name in &self.undefined {
+            writeln!(out, "    \"{}\" [style=dashed];", name).unwrap();
+        }
+        for function in &self.functions {
+            for callee in &function.calls {
+                writeln!(out, "    \"{}\" -> \"{}\";", function.name, callee).unwrap();
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
@@ -0,0 +1,217 @@
+// Hack assembler: turns the .asm we write into the 16-bit machine code the
+// emulator runs, following the course's two-pass symbol resolution
+use crate::diagnostics::Diagnostic;
+use std::collections::HashMap;
+
+// first RAM address handed out to variables
+const VARIABLE_BASE: u16 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub rom: Vec<u16>,
+    // labels and variables with the address they resolved to
+    pub symbols: HashMap<String, u16>,
+    // the labels alone with their ROM address, in the order they appear
+    pub labels: Vec<(String, u16)>,
+    // `//@ File: command` source map comments and the ROM address of the
+    // code that follows each
+    pub source_map: Vec<(u16, String)>,
+}
+
+impl Program {
+    // the last label at or before `address` that passes `filter`
+    pub fn label_before(&self, address: u16, filter: impl Fn(&str) -> bool) -> Option<&str> {
+        self.labels
+            .iter()
+            .take_while(|(_, label_address)| *label_address <= address)
+            .filter(|(label, _)| filter(label))
+            .last()
+            .map(|(label, _)| label.as_str())
+    }
+}
+
+fn predefined_symbols() -> HashMap<String, u16> {
+    let mut symbols = HashMap::from([
+        ("SP".to_string(), 0),
+        ("LCL".to_string(), 1),
+        ("ARG".to_string(), 2),
+        ("THIS".to_string(), 3),
+        ("THAT".to_string(), 4),
+        ("SCREEN".to_string(), 16384),
+        ("KBD".to_string(), 24576),
+    ]);
+    for i in 0..16 {
+        symbols.insert(format!("R{}", i), i);
+    }
+    symbols
+}
+
+fn comp_bits(comp: &str) -> Option<u16> {
+    // a=0 computations, the a=1 ones read M in place of A
+    let bits = match comp.replace('M', "A").as_str() {
+        "0" => 0b101010,
+        "1" => 0b111111,
+        "-1" => 0b111010,
+        "D" => 0b001100,
+        "A" => 0b110000,
+        "!D" => 0b001101,
+        "!A" => 0b110001,
+        "-D" => 0b001111,
+        "-A" => 0b110011,
+        "D+1" | "1+D" => 0b011111,
+        "A+1" | "1+A" => 0b110111,
+        "D-1" => 0b001110,
+        "A-1" => 0b110010,
+        "D+A" | "A+D" => 0b000010,
+        "D-A" => 0b010011,
+        "A-D" => 0b000111,
+        "D&A" | "A&D" => 0b000000,
+        "D|A" | "A|D" => 0b010101,
+        _ => return None,
+    };
+    let a = u16::from(comp.contains('M'));
+    Some(a << 12 | bits << 6)
+}
+
+// machine code back to assembly, for traces. Computations the assembler
+// doesn't know are shown as their bits
+pub fn instruction_text(word: u16) -> String {
+    if word & 0x8000 == 0 {
+        return format!("@{}", word);
+    }
+    let a = word & 0x1000 != 0;
+    let bits = (word >> 6) & 0b11_1111;
+    let computations = [
+        "0", "1", "-1", "D", "A", "!D", "!A", "-D", "-A", "D+1", "A+1", "D-1", "A-1", "D+A", "D-A",
+        "A-D", "D&A", "D|A",
+    ];
+    let comp = computations
+        .iter()
+        .find(|comp| comp_bits(comp) == Some(bits << 6))
+        .map(|comp| {
+            if a {
+                comp.replace('A', "M")
+            } else {
+                comp.to_string()
+            }
+        })
+        .unwrap_or_else(|| format!("{:07b}", (word >> 6) & 0b111_1111));
+    let dest: String = [('A', 0b100), ('D', 0b010), ('M', 0b001)]
+        .iter()
+        .filter(|(_, bit)| (word >> 3) & bit != 0)
+        .map(|(name, _)| name)
+        .collect();
+    let jumps = ["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+    let mut text = String::new();
+    if !dest.is_empty() {
+        text += &format!("{}=", dest);
+    }
+    text += &comp;
+    if word & 0b111 != 0 {
+        text += &format!(";{}", jumps[usize::from(word & 0b111)]);
+    }
+    text
+}
+
+fn dest_bits(dest: &str) -> Option<u16> {
+    let mut bits = 0;
+    for c in dest.chars() {
+        let bit = match c {
+            'A' => 0b100,
+            'D' => 0b010,
+            'M' => 0b001,
+            _ => return None,
+        };
+        if bits & bit != 0 {
+            return None;
+        }
+        bits |= bit;
+    }
+    Some(bits << 3)
+}
+
+fn jump_bits(jump: &str) -> Option<u16> {
+    let jumps = ["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+    jumps
+        .iter()
+        .position(|j| *j == jump)
+        .map(|bits| bits as u16)
+}
+
+// dest=comp;jump with either part optional
+fn encode_c_instruction(instruction: &str) -> Option<u16> {
+    let (dest, rest) = instruction.split_once('=').unwrap_or(("", instruction));
+    let (comp, jump) = rest.split_once(';').unwrap_or((rest, ""));
+    Some(0b111 << 13 | comp_bits(comp)? | dest_bits(dest)? | jump_bits(jump)?)
+}
+
+fn is_symbol(name: &str) -> bool {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "_.$:".contains(c);
+    !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(valid)
+}
+
+pub fn assemble(filename: &str, source: &str) -> Result<Program, Diagnostic> {
+    let error = |line: usize, message: String| Diagnostic::new(filename, line, 1, &message);
+
+    // first pass: strip comments and whitespace, bind labels to ROM addresses
+    let mut symbols = predefined_symbols();
+    let mut labels = Vec::new();
+    let mut source_map = Vec::new();
+    let mut instructions = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        if let Some(command) = line.trim().strip_prefix("//@ ") {
+            source_map.push((instructions.len() as u16, command.to_string()));
+            continue;
+        }
+        let line: String = line
+            .split("//")
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        if line.is_empty() {
+            continue;
+        }
+        match line.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+            Some(label) if is_symbol(label) => {
+                if symbols.contains_key(label) {
+                    return Err(error(i + 1, format!("label {} is already defined", label)));
+                }
+                symbols.insert(label.to_string(), instructions.len() as u16);
+                labels.push((label.to_string(), instructions.len() as u16));
+            }
+            Some(label) => return Err(error(i + 1, format!("invalid label '{}'", label))),
+            None => instructions.push((i + 1, line)),
+        }
+    }
+
+    // second pass: encode, handing out variables from RAM[16] upwards
+    let mut next_variable = VARIABLE_BASE;
+    let mut rom = Vec::with_capacity(instructions.len());
+    for (line, instruction) in instructions {
+        let word = match instruction.strip_prefix('@') {
+            Some(value) if value.starts_with(|c: char| c.is_ascii_digit()) => {
+                match value.parse::<u16>() {
+                    Ok(value) if value < 0x8000 => value,
+                    _ => return Err(error(line, format!("invalid address '{}'", value))),
+                }
+            }
+            Some(symbol) if is_symbol(symbol) => {
+                *symbols.entry(symbol.to_string()).or_insert_with(|| {
+                    next_variable += 1;
+                    next_variable - 1
+                })
+            }
+            Some(symbol) => return Err(error(line, format!("invalid symbol '{}'", symbol))),
+            None => encode_c_instruction(&instruction)
+                .ok_or_else(|| error(line, format!("invalid instruction '{}'", instruction)))?,
+        };
+        rom.push(word);
+    }
+    Ok(Program {
+        rom,
+        symbols,
+        labels,
+        source_map,
+    })
+}
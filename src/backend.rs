@@ -0,0 +1,71 @@
+// A code generation target for VM programs. The driver in compiler.rs hands
+// a backend each file's name and then its commands one at a time, so a new
+// target only has to implement these; CodeWriter is the Hack assembly one.
+// Backends set up the bootstrap, or the test-mode pointers, themselves
+use crate::parser::Command;
+use std::io;
+
+pub trait Backend {
+    // the file the following commands come from, statics are per file
+    fn set_file_name(&mut self, filename: &str);
+
+    // set SP and the segment pointers for test programs, which run without
+    // the bootstrap
+    fn init_stack(&mut self) -> io::Result<()>;
+
+    fn write_push(&mut self, segment: &str, index: i16) -> io::Result<()>;
+
+    fn write_pop(&mut self, segment: &str, index: i16) -> io::Result<()>;
+
+    // add, sub, neg, eq, gt, lt, and, or, not and the extended commands
+    fn write_arithmetic(&mut self, command: &str) -> io::Result<()>;
+
+    // labels are scoped to the function they appear in
+    fn write_label(&mut self, label: &str) -> io::Result<()>;
+
+    fn write_goto(&mut self, label: &str) -> io::Result<()>;
+
+    fn write_ifgoto(&mut self, label: &str) -> io::Result<()>;
+
+    fn write_function(&mut self, function_name: &str, nvars: i16) -> io::Result<()>;
+
+    fn write_call(&mut self, function_name: &str, nargs: i16) -> io::Result<()>;
+
+    fn write_return(&mut self) -> io::Result<()>;
+
+    // finish the output, it only appears at its destination once closed
+    fn close(self) -> io::Result<()>
+    where
+        Self: Sized;
+
+    // whether `call f n` directly followed by `return` goes to
+    // write_tail_call instead
+    fn tail_calls(&self) -> bool {
+        false
+    }
+
+    fn write_tail_call(&mut self, function_name: &str, nargs: i16) -> io::Result<()> {
+        self.write_call(function_name, nargs)?;
+        self.write_return()
+    }
+
+    // called before each command, for backends that mark where the code of
+    // each VM command starts
+    fn write_source_map(&mut self, _command: &Command) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_command(&mut self, command: &Command) -> io::Result<()> {
+        match command {
+            Command::Push(segment, index) => self.write_push(segment, *index),
+            Command::Pop(segment, index) => self.write_pop(segment, *index),
+            Command::Arithmetic(command) => self.write_arithmetic(command),
+            Command::Label(label) => self.write_label(label),
+            Command::Goto(label) => self.write_goto(label),
+            Command::IfGoto(label) => self.write_ifgoto(label),
+            Command::Function(name, nvars) => self.write_function(name, *nvars),
+            Command::Call(name, nargs) => self.write_call(name, *nargs),
+            Command::Return => self.write_return(),
+        }
+    }
+}
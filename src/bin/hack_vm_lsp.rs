@@ -0,0 +1,27 @@
+// hack_vm_lsp: language server for .vm files, speaking LSP over stdin/stdout
+use hack_vm::lsp::{read_message, write_message, Server};
+use std::io::{self, BufReader};
+
+fn main() {
+    let mut reader = BufReader::new(io::stdin().lock());
+    let mut writer = io::stdout().lock();
+    let mut server = Server::new();
+    loop {
+        let message = match read_message(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => std::process::exit(1),
+            Err(err) => {
+                eprintln!("hack_vm_lsp: {}", err);
+                continue;
+            }
+        };
+        for reply in server.handle(&message) {
+            if write_message(&mut writer, &reply).is_err() {
+                std::process::exit(1);
+            }
+        }
+        if let Some(code) = server.exit_code() {
+            std::process::exit(code);
+        }
+    }
+}
@@ -0,0 +1,377 @@
+// C backend: a VM program as one portable C file, for a native-speed
+// reference run of programs too long to emulate. RAM is an int16_t array
+// laid out as on the Hack machine, statics numbered from RAM[16] by first
+// use as the assembler does. All functions are label blocks in main(): a
+// call pushes a return point number in place of the return address and
+// jumps to the function, return jumps to a switch over the return points.
+//
+// The program counts VM commands the way the reference interpreter does,
+// stops after the count given as its first argument, if any, and stops at
+// `label X` `goto X` or when Sys.init returns. It then reports on stderr
+// and writes the nonzero lines of RAM to stdout as a RAM file (ram_file.rs)
+use crate::backend::Backend;
+use crate::compiler::VmFile;
+use crate::parser::EXTENDED_COMMANDS;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind, Write};
+
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+static int16_t ram[32768];
+
+#define AT(address) ram[(uint16_t)(address) & 0x7FFF]
+#define PUSH(value) (AT(ram[0]) = (int16_t)(value), ram[0]++)
+#define POP() (ram[0]--, AT(ram[0]))
+#define TOP AT(ram[0] - 1)
+#define WRAP(value) ((int16_t)(uint16_t)(value))
+#define STEP if (count == limit) goto stopped; count++
+
+static void dump(void) {
+    for (int start = 0; start < 32768; start += 16) {
+        int nonzero = 0;
+        for (int i = start; i < start + 16; i++) {
+            nonzero |= ram[i];
+        }
+        if (!nonzero) {
+            continue;
+        }
+        printf("RAM[%d..%d] =", start, start + 16);
+        for (int i = start; i < start + 16; i++) {
+            printf(" %d", ram[i]);
+        }
+        printf("\n");
+    }
+}
+
+int main(int argc, char **argv) {
+    long long limit = argc > 1 ? atoll(argv[1]) : -1;
+    long long count = 0;
+"#;
+
+// VM names as C identifiers: letters and digits stay, `_` becomes `__` and
+// anything else `_` and its hex code, so different names stay different
+fn mangle(prefix: &str, name: &str) -> String {
+    let mut mangled = prefix.to_string();
+    for c in name.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => mangled.push(c),
+            '_' => mangled += "__",
+            c => mangled += &format!("_{:x}", u32::from(c)),
+        }
+    }
+    mangled
+}
+
+pub struct CWriter {
+    output_file: VmFile,
+    // the body of main(), written out with the return switch on close
+    body: String,
+    filename: Option<String>,
+    function_name: Option<String>,
+    extended: bool,
+    statics: HashMap<String, i16>,
+    // return points 1.., 0 is the bootstrap's call of Sys.init
+    return_points: u32,
+    functions: HashSet<String>,
+    calls: Vec<String>,
+    // the label just written, `goto` to it is the halt loop
+    last_label: Option<String>,
+    // labels written and the ones jumped to, the rest are left out so cc
+    // doesn't warn about them
+    labels: Vec<String>,
+    jumps: HashSet<String>,
+    returns: bool,
+}
+
+impl CWriter {
+    // like CodeWriter::new, the bootstrap is left out for test programs
+    pub fn new(file: VmFile, is_test: bool) -> io::Result<Self> {
+        let mut c_writer = CWriter {
+            output_file: file,
+            body: String::new(),
+            filename: None,
+            function_name: None,
+            extended: false,
+            statics: HashMap::new(),
+            return_points: 0,
+            functions: HashSet::new(),
+            calls: Vec::new(),
+            last_label: None,
+            labels: Vec::new(),
+            jumps: HashSet::new(),
+            returns: false,
+        };
+        if !is_test {
+            // SP = 256 and call Sys.init, without counting it as a command
+            c_writer.line("ram[0] = 256;");
+            c_writer.write_frame("Sys.init", 0, 0);
+        }
+        Ok(c_writer)
+    }
+
+    // allow the extended instruction set (mul, div, mod, shl, shr, xor)
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
+    }
+
+    fn line(&mut self, line: &str) {
+        self.body += "    ";
+        self.body += line;
+        self.body += "\n";
+    }
+
+    // a command's code, after its VM text as a comment
+    fn command(&mut self, command: &str, code: &str) {
+        self.line(&format!("/* {} */", command));
+        self.line(format!("STEP; {}", code).trim_end());
+        self.last_label = None;
+    }
+
+    // the RAM cell of `segment index`
+    fn address(&mut self, segment: &str, index: i16) -> io::Result<String> {
+        let pointer = |register: usize| format!("AT(ram[{}] + {})", register, index);
+        Ok(match segment {
+            "local" => pointer(1),
+            "argument" => pointer(2),
+            "this" => pointer(3),
+            "that" => pointer(4),
+            "pointer" if (0..2).contains(&index) => format!("ram[{}]", 3 + index),
+            "temp" if (0..8).contains(&index) => format!("ram[{}]", 5 + index),
+            "static" => {
+                let name = format!("{}.{}", self.filename.as_deref().unwrap_or(""), index);
+                let next = 16 + self.statics.len() as i16;
+                format!("ram[{}]", self.statics.entry(name).or_insert(next))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid segment {} {}", segment, index),
+                ))
+            }
+        })
+    }
+
+    // `function$label`, as CodeWriter scopes labels
+    fn label_name(&self, label: &str) -> String {
+        match &self.function_name {
+            Some(function_name) => mangle("l_", &format!("{}${}", function_name, label)),
+            None => mangle("l_", label),
+        }
+    }
+
+    // push the return point and the caller's frame, then jump
+    fn write_frame(&mut self, function_name: &str, nargs: i16, return_point: u32) {
+        self.line(&format!(
+            "PUSH({}); PUSH(ram[1]); PUSH(ram[2]); PUSH(ram[3]); PUSH(ram[4]);",
+            return_point
+        ));
+        self.line(&format!(
+            "ram[2] = WRAP(ram[0] - {}); ram[1] = ram[0];",
+            5 + i32::from(nargs)
+        ));
+        self.line(&format!("goto {};", mangle("f_", function_name)));
+        self.calls.push(function_name.to_string());
+    }
+}
+
+impl Backend for CWriter {
+    fn set_file_name(&mut self, filename: &str) {
+        self.filename = Some(filename.to_string());
+    }
+
+    // the pointers CodeWriter sets up for test programs
+    fn init_stack(&mut self) -> io::Result<()> {
+        self.line("ram[0] = 256; ram[1] = 456; ram[2] = 756; ram[3] = 1056; ram[4] = 1356;");
+        Ok(())
+    }
+
+    fn write_push(&mut self, segment: &str, index: i16) -> io::Result<()> {
+        let value = match segment {
+            "constant" => index.to_string(),
+            segment => self.address(segment, index)?,
+        };
+        self.command(
+            &format!("push {} {}", segment, index),
+            &format!("PUSH({});", value),
+        );
+        Ok(())
+    }
+
+    fn write_pop(&mut self, segment: &str, index: i16) -> io::Result<()> {
+        let address = self.address(segment, index)?;
+        self.command(
+            &format!("pop {} {}", segment, index),
+            &format!("{{ int16_t value = POP(); {} = value; }}", address),
+        );
+        Ok(())
+    }
+
+    fn write_arithmetic(&mut self, command: &str) -> io::Result<()> {
+        let unary = match command {
+            "neg" => Some("TOP = WRAP(-TOP);"),
+            "not" => Some("TOP = ~TOP;"),
+            _ => None,
+        };
+        if let Some(code) = unary {
+            self.command(command, code);
+            return Ok(());
+        }
+        // x is TOP once y is popped, both promoted to int
+        let value = match command {
+            "add" => "WRAP(TOP + y)",
+            "sub" => "WRAP(TOP - y)",
+            "eq" => "-(TOP == y)",
+            "gt" => "-(TOP > y)",
+            "lt" => "-(TOP < y)",
+            "and" => "TOP & y",
+            "or" => "TOP | y",
+            command if !self.extended || !EXTENDED_COMMANDS.contains(&command) => {
+                return Err(io::Error::new(ErrorKind::InvalidInput, command.to_string()))
+            }
+            "mul" => "WRAP((int32_t)TOP * y)",
+            "div" => "WRAP(TOP / y)",
+            "mod" => "WRAP(TOP % y)",
+            "shl" => "WRAP((uint16_t)TOP << (y & 15))",
+            // >> of a negative int is implementation-defined, shift ~TOP instead
+            "shr" => "WRAP(TOP < 0 ? ~(~TOP >> (y & 15)) : TOP >> (y & 15))",
+            _ => "TOP ^ y",
+        };
+        let check = match command {
+            "div" | "mod" => {
+                "if (y == 0) { fprintf(stderr, \"division by zero\\n\"); dump(); return 1; } "
+            }
+            _ => "",
+        };
+        self.command(
+            command,
+            &format!("{{ int y = POP(); {}TOP = {}; }}", check, value),
+        );
+        Ok(())
+    }
+
+    fn write_label(&mut self, label: &str) -> io::Result<()> {
+        let name = self.label_name(label);
+        self.line(&format!("{}:;", name));
+        self.command(&format!("label {}", label), "");
+        self.labels.push(name.clone());
+        self.last_label = Some(name);
+        Ok(())
+    }
+
+    fn write_goto(&mut self, label: &str) -> io::Result<()> {
+        let name = self.label_name(label);
+        if self.last_label.as_ref() == Some(&name) {
+            self.line(&format!("/* goto {} */", label));
+            self.line("goto halted;");
+            self.last_label = None;
+            return Ok(());
+        }
+        self.command(&format!("goto {}", label), &format!("goto {};", name));
+        self.jumps.insert(name);
+        Ok(())
+    }
+
+    fn write_ifgoto(&mut self, label: &str) -> io::Result<()> {
+        let name = self.label_name(label);
+        self.command(
+            &format!("if-goto {}", label),
+            &format!("if (POP() != 0) goto {};", name),
+        );
+        self.jumps.insert(name);
+        Ok(())
+    }
+
+    fn write_function(&mut self, function_name: &str, nvars: i16) -> io::Result<()> {
+        self.function_name = Some(function_name.to_string());
+        self.functions.insert(function_name.to_string());
+        self.body += "\n";
+        self.line(&format!("{}:;", mangle("f_", function_name)));
+        let locals = "PUSH(0); ".repeat(nvars.max(0) as usize);
+        self.command(
+            &format!("function {} {}", function_name, nvars),
+            locals.trim_end(),
+        );
+        Ok(())
+    }
+
+    fn write_call(&mut self, function_name: &str, nargs: i16) -> io::Result<()> {
+        self.return_points += 1;
+        let return_point = self.return_points;
+        self.command(&format!("call {} {}", function_name, nargs), "");
+        self.write_frame(function_name, nargs, return_point);
+        self.line(&format!("r_{}:;", return_point));
+        Ok(())
+    }
+
+    fn write_return(&mut self) -> io::Result<()> {
+        self.returns = true;
+        self.command("return", "{");
+        for line in [
+            "    int16_t frame = ram[1];",
+            "    ret = AT(frame - 5);",
+            "    AT(ram[2]) = POP();",
+            "    ram[0] = WRAP(ram[2] + 1);",
+            "    ram[4] = AT(frame - 1); ram[3] = AT(frame - 2);",
+            "    ram[2] = AT(frame - 3); ram[1] = AT(frame - 4);",
+            "    goto dispatch;",
+            "}",
+        ] {
+            self.line(line);
+        }
+        Ok(())
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        if let Some(missing) = self
+            .calls
+            .iter()
+            .find(|name| !self.functions.contains(*name))
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("call to undefined function {}", missing),
+            ));
+        }
+        for label in self
+            .labels
+            .iter()
+            .filter(|label| !self.jumps.contains(*label))
+        {
+            self.body = self.body.replace(&format!("    {}:;\n", label), "");
+        }
+        let file = &mut self.output_file.file;
+        write!(file, "{}", PRELUDE)?;
+        if self.returns {
+            writeln!(file, "    int ret = 0;")?;
+        }
+        write!(file, "{}", self.body)?;
+        writeln!(file, "    goto halted;\n")?;
+        if self.returns {
+            writeln!(
+                file,
+                "dispatch:\n    switch (ret) {{\n    case 0: goto halted;"
+            )?;
+            for return_point in 1..=self.return_points {
+                writeln!(file, "    case {}: goto r_{};", return_point, return_point)?;
+            }
+            writeln!(file, "    }}")?;
+            writeln!(
+                file,
+                "    fprintf(stderr, \"bad return point %d\\n\", ret);\n    dump();\n    return 1;"
+            )?;
+        }
+        writeln!(file, "stopped:")?;
+        writeln!(
+            file,
+            "    fprintf(stderr, \"stopped after %lld commands\\n\", count);\n    dump();\n    return 2;"
+        )?;
+        writeln!(file, "halted:")?;
+        writeln!(
+            file,
+            "    fprintf(stderr, \"halted after %lld commands\\n\", count);\n    dump();\n    return 0;\n}}"
+        )?;
+        self.output_file.commit()
+    }
+}
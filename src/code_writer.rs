@@ -1,8 +1,7 @@
-use serde::de::value::Error;
-
+use crate::backend::Backend;
 use crate::compiler::VmFile;
+use crate::parser::{validate, Command, EXTENDED_COMMANDS};
 use std::collections::HashMap;
-use std::io;
 use std::io::ErrorKind;
 use std::io::Write;
 
@@ -25,10 +24,15 @@ pub struct CodeWriter {
     label_number: i16,
     mem_offset_map: Option<HashMap<MemoryLocation, i16>>,
     pub state: i16,
+    extended: bool,
+    tail_calls: bool,
+    // VM labels are scoped to the function they appear in
+    function_name: Option<String>,
+    source_map: bool,
 }
 
 impl CodeWriter {
-    pub fn new(file: VmFile, is_test: bool) -> Self {
+    pub fn new(file: VmFile, is_test: bool) -> std::io::Result<Self> {
         if is_test {
             let mem_offset_map: Option<HashMap<MemoryLocation, i16>> = Some(HashMap::from([
                 (MemoryLocation::Constant, 0),
@@ -41,13 +45,17 @@ impl CodeWriter {
                 (MemoryLocation::Index, 6),
                 (MemoryLocation::Stack, 256),
             ]));
-            return CodeWriter {
+            Ok(CodeWriter {
                 output_file: file,
                 label_number: 0,
                 filename: None,
                 mem_offset_map,
                 state: 0,
-            };
+                extended: false,
+                tail_calls: false,
+                function_name: None,
+                source_map: false,
+            })
         } else {
             let mut code_writer = CodeWriter {
                 output_file: file,
@@ -55,15 +63,31 @@ impl CodeWriter {
                 filename: None,
                 mem_offset_map: None,
                 state: 0,
+                extended: false,
+                tail_calls: false,
+                function_name: None,
+                source_map: false,
             };
-            code_writer.write_bootstrap().unwrap();
+            code_writer.write_bootstrap()?;
 
-            return code_writer;
-        };
+            Ok(code_writer)
+        }
     }
 
-    pub fn set_file_name(&mut self, filename: &str) {
-        self.filename = Some(filename.to_string())
+    // allow the extended instruction set (mul, div, mod, shl, shr, xor)
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
+    }
+
+    // replace the current frame when a call is immediately followed by return
+    pub fn set_tail_calls(&mut self, tail_calls: bool) {
+        self.tail_calls = tail_calls;
+    }
+
+    // precede each command's code with a `//@ File: command` comment, which
+    // the assembler records so traces can show VM commands
+    pub fn set_source_map(&mut self, source_map: bool) {
+        self.source_map = source_map;
     }
 
     fn write_lines(&mut self, lines: Vec<&str>) -> std::io::Result<()> {
@@ -78,57 +102,30 @@ impl CodeWriter {
         self.write_lines(vec!["@256", "D=A", "@0", "M=D"])?;
 
         // call Sys.init function
-        self.write_call(&String::from("Sys.init"), &String::from("0"))?;
+        self.write_call("Sys.init", 0)?;
         Ok(())
     }
 
-    fn write_address(&mut self, segment: &str) {
-        let mem_location = match segment {
-            "SP" => Ok(self
-                .mem_offset_map
-                .as_ref()
-                .unwrap()
-                .get(&MemoryLocation::Stack)
-                .expect("wrong key")),
-            "LCL" => Ok(self
-                .mem_offset_map
-                .as_ref()
-                .unwrap()
-                .get(&MemoryLocation::Local)
-                .expect("wrong key")),
-            "ARG" => Ok(self
-                .mem_offset_map
-                .as_ref()
-                .unwrap()
-                .get(&MemoryLocation::Argument)
-                .expect("wrong key")),
-            "THIS" => Ok(self
-                .mem_offset_map
-                .as_ref()
-                .unwrap()
-                .get(&MemoryLocation::This)
-                .expect("wrong key")),
-            "THAT" => Ok(self
-                .mem_offset_map
-                .as_ref()
-                .unwrap()
-                .get(&MemoryLocation::That)
-                .expect("wrong key")),
-            _ => Err(()),
+    fn write_address(&mut self, segment: &str) -> std::io::Result<()> {
+        let location = match segment {
+            "SP" => MemoryLocation::Stack,
+            "LCL" => MemoryLocation::Local,
+            "ARG" => MemoryLocation::Argument,
+            "THIS" => MemoryLocation::This,
+            "THAT" => MemoryLocation::That,
+            _ => return Err(std::io::Error::from(ErrorKind::InvalidInput)),
         };
+        let mem_location = self
+            .mem_offset_map
+            .as_ref()
+            .and_then(|map| map.get(&location))
+            .ok_or_else(|| std::io::Error::from(ErrorKind::NotFound))?;
 
-        writeln!(self.output_file.file, "//setting up {} address", segment).unwrap();
-        writeln!(self.output_file.file, "@{}", mem_location.unwrap()).unwrap();
-        writeln!(self.output_file.file, "D=A").unwrap();
-        writeln!(self.output_file.file, "@{}", segment).unwrap();
-        writeln!(self.output_file.file, "M=D").unwrap();
-    }
-
-    pub fn init_stack(&mut self) {
-        let fixed_variables = vec!["SP", "LCL", "ARG", "THIS", "THAT"];
-        for var in fixed_variables {
-            self.write_address(var);
-        }
+        writeln!(self.output_file.file, "//setting up {} address", segment)?;
+        writeln!(self.output_file.file, "@{}", mem_location)?;
+        writeln!(self.output_file.file, "D=A")?;
+        writeln!(self.output_file.file, "@{}", segment)?;
+        writeln!(self.output_file.file, "M=D")
     }
 
     pub fn write_push_pop(
@@ -136,10 +133,17 @@ impl CodeWriter {
         command: &str,
         segment: &str,
         index: &i16,
-    ) -> Result<(), &str> {
+    ) -> std::io::Result<()> {
         // segment is a memory location, segment + index = actual memory location
         // stack memory is from 256 - 2047
         // stack memory is shared so we need to allocate sufficient space for each offset
+        let vm_command = match command {
+            "C_PUSH" => Command::Push(segment.to_string(), *index),
+            "C_POP" => Command::Pop(segment.to_string(), *index),
+            _ => return Ok(()),
+        };
+        validate(&vm_command)
+            .map_err(|message| std::io::Error::new(ErrorKind::InvalidInput, message))?;
         match command {
             "C_PUSH" => match segment {
                 "constant" => {
@@ -152,8 +156,7 @@ impl CodeWriter {
                         "M=D",
                         "@SP",
                         "M=M+1",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "argument" => {
@@ -170,8 +173,7 @@ impl CodeWriter {
                         // increment SP
                         "@SP",
                         "M=M+1",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "local" => {
@@ -188,14 +190,13 @@ impl CodeWriter {
                         // increment SP
                         "@SP",
                         "M=M+1",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "static" => {
                     self.write_lines(vec![
                         "//push static",
-                        &format!("@{}.{}", &self.filename.clone().unwrap(), index),
+                        &format!("@{}.{}", self.static_prefix()?, index),
                         "D=M",
                         "@SP",
                         "A=M",
@@ -203,8 +204,7 @@ impl CodeWriter {
                         // increment SP
                         "@SP",
                         "M=M+1",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "this" => {
@@ -221,8 +221,7 @@ impl CodeWriter {
                         // increment SP
                         "@SP",
                         "M=M+1",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "that" => {
@@ -239,8 +238,7 @@ impl CodeWriter {
                         // increment SP
                         "@SP",
                         "M=M+1",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "temp" => {
@@ -258,21 +256,16 @@ impl CodeWriter {
                         // increment SP
                         "@SP",
                         "M=M+1",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "pointer" => {
                     // pointer 0 is THIS, pointer 1 is THAT
-                    let address = match index {
-                        0 => Ok("THIS"),
-                        1 => Ok("THAT"),
-                        _ => Err("invalid"),
-                    };
+                    let address = if *index == 0 { "THIS" } else { "THAT" };
 
                     self.write_lines(vec![
                         "//push pointer",
-                        &format!("@{}", address?),
+                        &format!("@{}", address),
                         "D=M",
                         "@SP",
                         "A=M",
@@ -280,11 +273,10 @@ impl CodeWriter {
                         // increment SP
                         "@SP",
                         "M=M+1",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
-                _ => Err("not implemented"),
+                _ => Err(std::io::Error::from(ErrorKind::InvalidInput)),
             },
             "C_POP" => match segment {
                 "argument" => {
@@ -309,8 +301,7 @@ impl CodeWriter {
                         "@R13",
                         "A=M",
                         "M=D",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "local" => {
@@ -335,8 +326,7 @@ impl CodeWriter {
                         "@R13",
                         "A=M",
                         "M=D",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "this" => {
@@ -361,8 +351,7 @@ impl CodeWriter {
                         "@R13",
                         "A=M",
                         "M=D",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "that" => {
@@ -387,8 +376,7 @@ impl CodeWriter {
                         "@R13",
                         "A=M",
                         "M=D",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "static" => {
@@ -401,10 +389,9 @@ impl CodeWriter {
                         "@SP",
                         "A=M",
                         "D=M",
-                        &format!("@{}.{}", &self.filename.clone().unwrap(), index),
+                        &format!("@{}.{}", self.static_prefix()?, index),
                         "M=D",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "temp" => {
@@ -430,17 +417,12 @@ impl CodeWriter {
                         "@R13",
                         "A=M",
                         "M=D",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
                 "pointer" => {
                     // pointer 0 is THIS, pointer 1 is THAT
-                    let address = match index {
-                        0 => Ok("THIS"),
-                        1 => Ok("THAT"),
-                        _ => Err("invalid"),
-                    };
+                    let address = if *index == 0 { "THIS" } else { "THAT" };
 
                     self.write_lines(vec![
                         "//pop pointer",
@@ -451,32 +433,29 @@ impl CodeWriter {
                         "@SP",
                         "A=M",
                         "D=M",
-                        &format!("@{}", address?),
+                        &format!("@{}", address),
                         "M=D",
-                    ])
-                    .expect("error");
+                    ])?;
                     Ok(())
                 }
-                _ => Err("not implemented"),
+                _ => Err(std::io::Error::from(ErrorKind::InvalidInput)),
             },
             _ => Ok(()),
         }
     }
 
-    pub fn write_arithmetic(&mut self, command: &str) -> Result<(), ErrorKind> {
+    fn write_hack_arithmetic(&mut self, command: &str) -> std::io::Result<()> {
         match command {
             "add" => {
                 self.write_lines(vec![
                     "//add", "@SP", "M=M-1", "@SP", "A=M", "D=M", "@SP", "A=M-1", "D=D+M", "M=D",
-                ])
-                .expect("error");
+                ])?;
                 Ok(())
             }
             "sub" => {
                 self.write_lines(vec![
                     "@SP", "M=M-1", "@SP", "A=M", "D=M", "@SP", "A=M-1", "D=M-D", "M=D",
-                ])
-                .expect("error");
+                ])?;
                 Ok(())
             }
             "neg" => {
@@ -484,8 +463,7 @@ impl CodeWriter {
                     "@SP", "M=M-1", "@SP", "A=M", "D=M", "@SP", "A=M", "D=-D", "M=D",
                     // SP + 1
                     "@SP", "M=M+1",
-                ])
-                .expect("error");
+                ])?;
                 Ok(())
             }
             "eq" => {
@@ -517,110 +495,457 @@ impl CodeWriter {
                     // SP + 1
                     "@SP",
                     "M=M+1",
-                ])
-                .expect("error");
+                ])?;
                 // increment the state counter to keep the labels unique
                 self.state += 1;
                 Ok(())
             }
-            "gt" => {
-                self.write_lines(vec![
-                    "@SP",
-                    "M=M-1",
-                    "@SP",
-                    "A=M",
-                    "D=M",
-                    "@SP",
-                    "A=M-1",
-                    "D=M-D",
-                    &format!("@TRUE_{}", &self.state),
-                    "D;JGT",
-                    // false
-                    "@SP",
-                    "M=M-1",
-                    "A=M",
-                    "M=0",
-                    &format!("@CONTINUE_{}", &self.state),
-                    "0;JMP",
-                    &format!("(TRUE_{})", &self.state),
-                    // true
-                    "@SP",
-                    "M=M-1",
-                    "A=M",
-                    "M=-1",
-                    &format!("(CONTINUE_{})", &self.state),
-                    // SP + 1
-                    "@SP",
-                    "M=M+1",
-                ])
-                .expect("error");
-                // increment the state counter to keep the labels unique
-                self.state += 1;
-                Ok(())
-            }
-            "lt" => {
-                self.write_lines(vec![
-                    "@SP",
-                    "M=M-1",
-                    "@SP",
-                    "A=M",
-                    "D=M",
-                    "@SP",
-                    "A=M-1",
-                    "D=D-M",
-                    &format!("@TRUE_{}", &self.state),
-                    "D;JGT",
-                    // false
-                    "@SP",
-                    "M=M-1",
-                    "A=M",
-                    "M=0",
-                    &format!("@CONTINUE_{}", &self.state),
-                    "0;JMP",
-                    &format!("(TRUE_{})", &self.state),
-                    // true
-                    "@SP",
-                    "M=M-1",
-                    "A=M",
-                    "M=-1",
-                    &format!("(CONTINUE_{})", &self.state),
-                    // SP + 1
-                    "@SP",
-                    "M=M+1",
-                ])
-                .expect("error");
-                // increment the state counter to keep the labels unique
-                self.state += 1;
+            "gt" | "lt" => {
+                self.write_comparison(command)?;
                 Ok(())
             }
             "and" => {
                 self.write_lines(vec![
                     "@SP", "M=M-1", "@SP", "A=M", "D=M", "@SP", "A=M-1", "D=D&M", "M=D",
-                ])
-                .expect("error");
+                ])?;
                 Ok(())
             }
             "or" => {
                 self.write_lines(vec![
                     "@SP", "M=M-1", "@SP", "A=M", "D=M", "@SP", "A=M-1", "D=D|M", "M=D",
-                ])
-                .expect("error");
+                ])?;
                 Ok(())
             }
             "not" => {
-                self.write_lines(vec!["@SP", "A=M-1", "M=!M"])
-                    .expect("error");
+                self.write_lines(vec!["@SP", "A=M-1", "M=!M"])?;
+                Ok(())
+            }
+            cmd if self.extended && EXTENDED_COMMANDS.contains(&cmd) => {
+                self.write_extended_arithmetic(cmd)?;
                 Ok(())
             }
-            _ => Err(ErrorKind::InvalidInput),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown command '{}'", command),
+            )),
         }
     }
 
-    pub fn write_label(&mut self, label: &String) -> Result<(), std::io::Error> {
+    fn write_comparison(&mut self, command: &str) -> std::io::Result<()> {
+        // x - y overflows when the signs differ, e.g. 20000 gt -20000, so the
+        // signs are compared first and only equal signs are subtracted.
+        // R13 = y, D holds the result until it replaces x
+        let n = self.state;
+        self.state += 1;
+        let (x_greater, x_less, jump) = match command {
+            "gt" => ("D=-1", "D=0", "D;JGT"),
+            _ => ("D=0", "D=-1", "D;JLT"),
+        };
+        self.write_lines(vec![
+            &format!("//{}", command),
+            "@SP",
+            "AM=M-1",
+            "D=M",
+            "@R13",
+            "M=D",
+            "@SP",
+            "A=M-1",
+            "D=M",
+            &format!("@XNEG_{}", n),
+            "D;JLT",
+            // x >= 0
+            "@R13",
+            "D=M",
+            &format!("@SAME_SIGN_{}", n),
+            "D;JGE",
+            x_greater,
+            &format!("@CONTINUE_{}", n),
+            "0;JMP",
+            // x < 0
+            &format!("(XNEG_{})", n),
+            "@R13",
+            "D=M",
+            &format!("@SAME_SIGN_{}", n),
+            "D;JLT",
+            x_less,
+            &format!("@CONTINUE_{}", n),
+            "0;JMP",
+            &format!("(SAME_SIGN_{})", n),
+            "@R13",
+            "D=M",
+            "@SP",
+            "A=M-1",
+            "D=M-D",
+            &format!("@TRUE_{}", n),
+            jump,
+            "D=0",
+            &format!("@CONTINUE_{}", n),
+            "0;JMP",
+            &format!("(TRUE_{})", n),
+            "D=-1",
+            &format!("(CONTINUE_{})", n),
+            "@SP",
+            "A=M-1",
+            "M=D",
+        ])
+    }
+
+    fn write_extended_arithmetic(&mut self, command: &str) -> std::io::Result<()> {
+        let n = self.state;
+        self.state += 1;
+        let code = extended_arithmetic(command, &n.to_string())
+            .ok_or_else(|| std::io::Error::from(ErrorKind::InvalidInput))?;
+        self.write_lines(code.iter().map(String::as_str).collect())
+    }
+
+    // statics are named after the file they are in
+    fn static_prefix(&self) -> std::io::Result<String> {
+        self.filename
+            .clone()
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "static outside of a file"))
+    }
+
+    // `label` in function f is written as f$label
+    fn scoped_label(&self, label: &str) -> String {
+        match &self.function_name {
+            Some(function_name) => format!("{}${}", function_name, label),
+            None => label.to_string(),
+        }
+    }
+
+    fn write_raw_label(&mut self, label: &str) -> Result<(), std::io::Error> {
         self.write_lines(vec!["//label", &format!("({})", label)])
     }
 
-    pub fn write_ifgoto(&mut self, label: &String) -> Result<(), std::io::Error> {
+    fn write_raw_goto(&mut self, label: &str) -> Result<(), std::io::Error> {
+        self.write_lines(vec!["//goto", &format!("@{}", label), "0; JMP"])
+    }
+
+    fn finish_push(&mut self) -> Result<(), std::io::Error> {
+        // finishes push to stack
+        self.write_lines(vec!["@SP", "A=M", "M=D", "@SP", "M=M+1"])
+    }
+}
+
+// the instructions of an extended command, `n` numbers its labels. The
+// disassembler matches them with `{}` for `n`
+pub fn extended_arithmetic(command: &str, n: &str) -> Option<Vec<String>> {
+    // R13-R15 are free general purpose registers, RAM[SP] and RAM[SP+1] sit above
+    // the stack top once the second operand is popped and are used as scratch
+    match command {
+        "mul" => Some(lines(vec![
+            "//mul",
+            // R14 = y, R13 = x, result accumulates in place of x
+            "@SP",
+            "AM=M-1",
+            "D=M",
+            "@R14",
+            "M=D",
+            "@SP",
+            "A=M-1",
+            "D=M",
+            "@R13",
+            "M=D",
+            "@SP",
+            "A=M-1",
+            "M=0",
+            // R15 = bit mask, shifted left until it overflows to zero
+            "@R15",
+            "M=1",
+            &format!("(MUL_LOOP_{})", n),
+            "@R15",
+            "D=M",
+            &format!("@MUL_END_{}", n),
+            "D;JEQ",
+            "@R14",
+            "D=D&M",
+            &format!("@MUL_SKIP_{}", n),
+            "D;JEQ",
+            "@R13",
+            "D=M",
+            "@SP",
+            "A=M-1",
+            "M=D+M",
+            &format!("(MUL_SKIP_{})", n),
+            "@R13",
+            "D=M",
+            "M=D+M",
+            "@R15",
+            "D=M",
+            "M=D+M",
+            &format!("@MUL_LOOP_{}", n),
+            "0;JMP",
+            &format!("(MUL_END_{})", n),
+        ])),
+        "div" | "mod" => Some(divmod(command == "mod", n)),
+        "shl" => Some(lines(vec![
+            "//shl",
+            // R14 = shift count (y & 15)
+            "@SP",
+            "AM=M-1",
+            "D=M",
+            "@15",
+            "D=D&A",
+            "@R14",
+            "M=D",
+            &format!("(SHL_LOOP_{})", n),
+            "@R14",
+            "D=M",
+            &format!("@SHL_END_{}", n),
+            "D;JEQ",
+            "@R14",
+            "M=D-1",
+            "@SP",
+            "A=M-1",
+            "D=M",
+            "M=D+M",
+            &format!("@SHL_LOOP_{}", n),
+            "0;JMP",
+            &format!("(SHL_END_{})", n),
+        ])),
+        "shr" => Some(lines(vec![
+            "//shr",
+            // arithmetic shift right by k: rotate left 16-k times, then
+            // keep the low 16-k bits and fill the top k bits with the sign
+            "@SP",
+            "AM=M-1",
+            "D=M",
+            "@15",
+            "D=D&A",
+            "@R14",
+            "M=D",
+            "@16",
+            "D=A",
+            "@R14",
+            "M=D-M",
+            "@SP",
+            "A=M-1",
+            "D=M",
+            "@R13",
+            "M=D",
+            "@R15",
+            "M=1",
+            &format!("(SHR_LOOP_{})", n),
+            "@R14",
+            "D=M",
+            &format!("@SHR_END_{}", n),
+            "D;JEQ",
+            "@R14",
+            "M=D-1",
+            "@R15",
+            "D=M",
+            "M=D+M",
+            "@R13",
+            "D=M",
+            "M=D+M",
+            &format!("@SHR_LOOP_{}", n),
+            "D;JGE",
+            // carry the top bit round to bit 0
+            "@R13",
+            "M=M+1",
+            &format!("@SHR_LOOP_{}", n),
+            "0;JMP",
+            &format!("(SHR_END_{})", n),
+            "@R15",
+            "M=M-1",
+            "D=M",
+            "@R13",
+            "M=D&M",
+            "@SP",
+            "A=M-1",
+            "D=M",
+            &format!("@SHR_POS_{}", n),
+            "D;JGE",
+            "@R15",
+            "D=!M",
+            "@R13",
+            "M=D|M",
+            &format!("(SHR_POS_{})", n),
+            "@R13",
+            "D=M",
+            "@SP",
+            "A=M-1",
+            "M=D",
+        ])),
+        "xor" => Some(lines(vec![
+            "//xor", // x xor y = (x|y) & !(x&y)
+            "@SP", "AM=M-1", "D=M", "@R13", "M=D", "@SP", "A=M-1", "D=D&M", "@R14", "M=!D", "@R13",
+            "D=M", "@SP", "A=M-1", "D=D|M", "@R14", "D=D&M", "@SP", "A=M-1", "M=D",
+        ])),
+        _ => None,
+    }
+}
+
+fn divmod(remainder: bool, n: &str) -> Vec<String> {
+    // signed division truncating towards zero, the remainder takes the sign of x
+    // R13 = |x| shifted out bit by bit, R14 = |y|, R15 = running remainder,
+    // quotient accumulates in place of x, RAM[SP] = loop counter,
+    // RAM[SP+1] = sign of the result
+    let op = if remainder { "MOD" } else { "DIV" };
+    let mut code = Vec::new();
+    code.extend(lines(vec![
+        if remainder { "//mod" } else { "//div" },
+        "@SP",
+        "AM=M-1",
+        "D=M",
+        "@R14",
+        "M=D",
+        "@SP",
+        "A=M-1",
+        "D=M",
+        "@R13",
+        "M=D",
+    ]));
+    if remainder {
+        code.extend(lines(vec!["@SP", "A=M+1", "M=D"]));
+    } else {
+        // quotient is negative when the operand signs differ: sign of x xor y
+        code.extend(lines(vec![
+            "@R14", "D=D&M", "@SP", "A=M+1", "M=!D", "@R13", "D=M", "@R14", "D=D|M", "@SP",
+            "A=M+1", "M=D&M",
+        ]));
+    }
+    code.extend(lines(vec![
+        "@R13",
+        "D=M",
+        &format!("@{}_XPOS_{}", op, n),
+        "D;JGE",
+        "@R13",
+        "M=-M",
+        &format!("({}_XPOS_{})", op, n),
+        "@R14",
+        "D=M",
+        &format!("@{}_YPOS_{}", op, n),
+        "D;JGE",
+        "@R14",
+        "M=-M",
+        &format!("({}_YPOS_{})", op, n),
+        "@R15",
+        "M=0",
+        "@SP",
+        "A=M-1",
+        "M=0",
+        "@16",
+        "D=A",
+        "@SP",
+        "A=M",
+        "M=D",
+        &format!("({}_LOOP_{})", op, n),
+        // shift the quotient and remainder, bring down the next bit of |x|
+        "@SP",
+        "A=M-1",
+        "D=M",
+        "M=D+M",
+        "@R15",
+        "D=M",
+        "M=D+M",
+        "@R13",
+        "D=M",
+        &format!("@{}_NOBIT_{}", op, n),
+        "D;JGE",
+        "@R15",
+        "M=M+1",
+        &format!("({}_NOBIT_{})", op, n),
+        "@R13",
+        "D=M",
+        "M=D+M",
+        // unsigned compare of remainder and divisor
+        "@R15",
+        "D=M",
+        &format!("@{}_RNEG_{}", op, n),
+        "D;JLT",
+        "@R14",
+        "D=M",
+        &format!("@{}_NEXT_{}", op, n),
+        "D;JLT",
+        &format!("@{}_SAME_{}", op, n),
+        "0;JMP",
+        &format!("({}_RNEG_{})", op, n),
+        "@R14",
+        "D=M",
+        &format!("@{}_SUB_{}", op, n),
+        "D;JGE",
+        &format!("({}_SAME_{})", op, n),
+        "@R14",
+        "D=M",
+        "@R15",
+        "D=M-D",
+        &format!("@{}_NEXT_{}", op, n),
+        "D;JLT",
+        &format!("({}_SUB_{})", op, n),
+        "@R14",
+        "D=M",
+        "@R15",
+        "M=M-D",
+        "@SP",
+        "A=M-1",
+        "M=M+1",
+        &format!("({}_NEXT_{})", op, n),
+        "@SP",
+        "A=M",
+        "MD=M-1",
+        &format!("@{}_LOOP_{}", op, n),
+        "D;JGT",
+    ]));
+    if remainder {
+        code.extend(lines(vec!["@R15", "D=M", "@SP", "A=M-1", "M=D"]));
+    }
+    code.extend(lines(vec![
+        "@SP",
+        "A=M+1",
+        "D=M",
+        &format!("@{}_END_{}", op, n),
+        "D;JGE",
+        "@SP",
+        "A=M-1",
+        "M=-M",
+        &format!("({}_END_{})", op, n),
+    ]));
+    code
+}
+
+fn lines(lines: Vec<&str>) -> Vec<String> {
+    lines.into_iter().map(String::from).collect()
+}
+
+// Hack assembly
+impl Backend for CodeWriter {
+    fn set_file_name(&mut self, filename: &str) {
+        self.filename = Some(filename.to_string())
+    }
+
+    fn init_stack(&mut self) -> std::io::Result<()> {
+        let fixed_variables = vec!["SP", "LCL", "ARG", "THIS", "THAT"];
+        for var in fixed_variables {
+            self.write_address(var)?;
+        }
+        Ok(())
+    }
+
+    fn write_push(&mut self, segment: &str, index: i16) -> std::io::Result<()> {
+        self.write_push_pop("C_PUSH", segment, &index)
+    }
+
+    fn write_pop(&mut self, segment: &str, index: i16) -> std::io::Result<()> {
+        self.write_push_pop("C_POP", segment, &index)
+    }
+
+    fn write_arithmetic(&mut self, command: &str) -> std::io::Result<()> {
+        self.write_hack_arithmetic(command)
+    }
+
+    fn write_label(&mut self, label: &str) -> Result<(), std::io::Error> {
+        let label = self.scoped_label(label);
+        self.write_raw_label(&label)
+    }
+
+    fn write_goto(&mut self, label: &str) -> Result<(), std::io::Error> {
+        let label = self.scoped_label(label);
+        self.write_raw_goto(&label)
+    }
+
+    fn write_ifgoto(&mut self, label: &str) -> Result<(), std::io::Error> {
+        let label = self.scoped_label(label);
         self.write_lines(vec![
             "//if-goto",
             "@SP",
@@ -631,38 +956,21 @@ impl CodeWriter {
         ])
     }
 
-    pub fn write_goto(&mut self, label: &String) -> Result<(), std::io::Error> {
-        self.write_lines(vec!["//goto", &format!("@{}", label), "0; JMP"])
-    }
-
-    pub fn write_function(
-        &mut self,
-        function_name: &String,
-        nvars: &String,
-    ) -> Result<(), std::io::Error> {
+    fn write_function(&mut self, function_name: &str, nvars: i16) -> std::io::Result<()> {
         self.write_lines(vec!["//function"])?;
-        self.write_label(function_name).unwrap();
+        self.function_name = Some(function_name.to_string());
+        self.write_raw_label(function_name)?;
         let mut i = 0;
-        let j: i16 = nvars.parse().expect("error parsing");
-        while i < j {
+        while i < nvars {
             self.write_lines(vec!["//nvars"])?;
             // push 0 for local variables
-            self.write_push_pop("C_PUSH", "constant", &0).unwrap();
+            self.write_push_pop("C_PUSH", "constant", &0)?;
             i += 1;
         }
         Ok(())
     }
 
-    fn finish_push(&mut self) -> Result<(), std::io::Error> {
-        // finishes push to stack
-        self.write_lines(vec!["@SP", "A=M", "M=D", "@SP", "M=M+1"])
-    }
-
-    pub fn write_call(
-        &mut self,
-        function_name: &String,
-        nargs: &String,
-    ) -> Result<(), std::io::Error> {
+    fn write_call(&mut self, function_name: &str, nargs: i16) -> std::io::Result<()> {
         let return_address = format!("{}$ret.{}", function_name, &self.label_number);
 
         // push returnAddr, this should be functionName$ret.i
@@ -671,23 +979,23 @@ impl CodeWriter {
             &format!("@{}", &return_address),
             "D=A",
         ])?;
-        self.finish_push().expect("error finish push");
+        self.finish_push()?;
 
         // push LCL
         self.write_lines(vec!["//push lcl", "@LCL", "D=M"])?;
-        self.finish_push().expect("error finish push");
+        self.finish_push()?;
 
         // push ARG
         self.write_lines(vec!["//push arg", "@ARG", "D=M"])?;
-        self.finish_push().expect("error finish push");
+        self.finish_push()?;
 
         // push THIS
         self.write_lines(vec!["//push this", "@THIS", "D=M"])?;
-        self.finish_push().expect("error finish push");
+        self.finish_push()?;
 
         // push THAT
         self.write_lines(vec!["//push that", "@THAT", "D=M"])?;
-        self.finish_push().expect("error finish push");
+        self.finish_push()?;
 
         // ARG = SP - 5 - nArgs
         self.write_lines(vec![
@@ -706,10 +1014,10 @@ impl CodeWriter {
         self.write_lines(vec!["//lcl=sp", "@SP", "D=M", "@LCL", "M=D"])?;
 
         // goto f
-        self.write_goto(function_name).expect("error");
+        self.write_raw_goto(function_name)?;
 
         // (returnAddress)
-        self.write_label(&return_address).expect("error");
+        self.write_raw_label(&return_address)?;
 
         // increment label number
         self.label_number += 1;
@@ -717,7 +1025,7 @@ impl CodeWriter {
         Ok(())
     }
 
-    pub fn write_return(&mut self) -> Result<(), std::io::Error> {
+    fn write_return(&mut self) -> Result<(), std::io::Error> {
         // frame = LCL
         // save LCL address to SP address
         self.write_lines(vec!["//frame=LCL", "@LCL", "D=M", "@SP", "A=M", "M=D"])?;
@@ -797,7 +1105,82 @@ impl CodeWriter {
         Ok(())
     }
 
-    fn close(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.output_file.file.sync_all().map_err(|e| e.into())
+    // finish the .asm file, it only appears at its destination once closed
+    fn close(self) -> std::io::Result<()> {
+        self.output_file.commit()
+    }
+
+    fn tail_calls(&self) -> bool {
+        self.tail_calls
+    }
+
+    fn write_tail_call(&mut self, function_name: &str, n: i16) -> std::io::Result<()> {
+        // `call f n; return` reuses the current frame: the n arguments on top of the
+        // stack and the caller's saved frame are moved down to ARG, so f returns
+        // straight to our caller and the stack does not grow
+        let copy_label = format!("TAIL_COPY_{}", self.state);
+        self.state += 1;
+        // the arguments and the saved frame, too many for an i16 at nArgs 32767
+        let words = i32::from(n) + 5;
+
+        // push retAddr, LCL, ARG, THIS, THAT saved at frame-5..frame-1
+        for offset in (1..=5).rev() {
+            self.write_lines(vec![
+                &format!("//push *(frame-{})", offset),
+                "@LCL",
+                "D=M",
+                &format!("@{}", offset),
+                "A=D-A",
+                "D=M",
+            ])?;
+            self.finish_push()?;
+        }
+
+        // R13 = first word to move, R14 = destination, R15 = words left
+        self.write_lines(vec![
+            "//tail call: move args and frame to ARG",
+            "@SP",
+            "D=M",
+            &format!("@{}", words),
+            "D=D-A",
+            "@R13",
+            "M=D",
+            "@ARG",
+            "D=M",
+            "@R14",
+            "M=D",
+            &format!("@{}", words),
+            "D=A",
+            "@R15",
+            "M=D",
+            &format!("({})", copy_label),
+            "@R13",
+            "A=M",
+            "D=M",
+            "@R14",
+            "A=M",
+            "M=D",
+            "@R13",
+            "M=M+1",
+            "@R14",
+            "M=M+1",
+            "@R15",
+            "MD=M-1",
+            &format!("@{}", copy_label),
+            "D;JGT",
+        ])?;
+
+        // SP = LCL = ARG + nargs + 5, ARG stays as it is
+        self.write_lines(vec!["//lcl=sp", "@R14", "D=M", "@SP", "M=D", "@LCL", "M=D"])?;
+
+        self.write_raw_goto(function_name)
+    }
+
+    fn write_source_map(&mut self, command: &Command) -> std::io::Result<()> {
+        if !self.source_map {
+            return Ok(());
+        }
+        let filename = self.filename.clone().unwrap_or_default();
+        writeln!(self.output_file.file, "//@ {}: {}", filename, command)
     }
 }
@@ -1,31 +1,120 @@
+use crate::backend::Backend;
 use crate::code_writer::CodeWriter;
-use crate::parser::Parser;
-use std::fs::{read_to_string, File};
+use crate::diagnostics::Diagnostic;
+use crate::jack::compile_jack;
+use crate::jack::parser::parse_class;
+use crate::jack::tokenizer::tokenize;
+use crate::jack::xml::{class_xml, tokens_xml};
+use crate::parser::{validate, Command, ParseError, Parser};
+use std::ffi::OsStr;
+use std::fs::{self, read_to_string, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+// An .asm output file. Lines are written to a temporary file next to the
+// destination, which only replaces the destination once `commit` is called, so a
+// failed translation never leaves a half-written .asm behind.
 pub struct VmFile {
-    pub file: File,
+    pub file: BufWriter<File>,
     name: String,
+    path: PathBuf,
+    temp_path: PathBuf,
+    committed: bool,
 }
 
 impl VmFile {
+    // `filename` is the output path, `.asm` is added when it has no extension
     pub fn new(filename: &str) -> Result<VmFile, std::io::Error> {
-        let file = File::create(format!("{}.asm", filename))?;
+        let path = Path::new(filename);
+        if path.extension().is_some() {
+            VmFile::create(path)
+        } else {
+            VmFile::create(&path.with_extension("asm"))
+        }
+    }
+
+    pub fn create(path: &Path) -> Result<VmFile, std::io::Error> {
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+        let file = BufWriter::new(File::create(&temp_path)?);
+        let name = path.with_extension("");
         Ok(VmFile {
             file,
-            name: filename.to_string(),
+            name: name.to_str().unwrap_or_default().to_string(),
+            path: path.to_path_buf(),
+            temp_path,
+            committed: false,
         })
     }
 
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // flush everything to disk and move the file into place
+    pub fn commit(mut self) -> Result<(), std::io::Error> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
 }
 
-pub fn parse_filename(configs: &[String]) -> Result<&String, &'static str> {
-    if configs.len() == 0 {
-        return Err("missing filename argument");
+impl Drop for VmFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+// the .asm file must not replace any of the files being translated or
+// compiled, nor any other .vm or .jack source
+pub fn check_output_path(output: &Path, inputs: &[PathBuf]) -> Result<(), String> {
+    let output = output.canonicalize().unwrap_or(output.to_path_buf());
+    for input in inputs {
+        if input.canonicalize().unwrap_or(input.to_path_buf()) == output {
+            return Err(format!(
+                "refusing to overwrite input file {}",
+                input.display()
+            ));
+        }
+    }
+    if output.extension() == Some(OsStr::new("vm"))
+        || output.extension() == Some(OsStr::new("jack"))
+    {
+        return Err(format!(
+            "refusing to write assembly to {}",
+            output.display()
+        ));
     }
-    Ok(&configs[1])
+    Ok(())
+}
+
+// the first argument after the program name
+pub fn parse_filename(configs: &[String]) -> Result<&String, &'static str> {
+    configs.get(1).ok_or("missing filename argument")
+}
+
+// true when an optional `--flag` switch was passed on the command line
+pub fn has_flag(configs: &[String], flag: &str) -> bool {
+    configs.iter().skip(1).any(|config| config == flag)
+}
+
+// the value following an optional `--flag value` pair on the command line
+pub fn flag_value<'a>(configs: &'a [String], flag: &str) -> Option<&'a str> {
+    configs
+        .iter()
+        .position(|config| config == flag)
+        .and_then(|i| configs.get(i + 1))
+        .map(|value| value.as_str())
 }
 
 pub fn read_lines(filename: &str) -> Vec<String> {
@@ -37,96 +126,216 @@ pub fn read_lines(filename: &str) -> Vec<String> {
         .collect()
 }
 
-pub fn compile_vm_code(mut parser: Parser, mut code_writer: CodeWriter, test: &bool) -> CodeWriter {
+// all the files with the given extension in a directory, in a stable order
+pub fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let extension = OsStr::new(extension);
+    let mut entries = Vec::from_iter(
+        fs::read_dir(dir)
+            .unwrap()
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.extension() == Some(extension)),
+    );
+    entries.sort();
+    entries
+}
+
+// parse each vm file, keeping the file name for its statics
+pub fn read_program(
+    vm_files: &[PathBuf],
+    extended: bool,
+) -> Result<Vec<(String, Vec<Command>)>, Diagnostic> {
+    vm_files
+        .iter()
+        .map(|vm_file| {
+            let name = vm_file.display().to_string();
+            let source = read_to_string(vm_file)
+                .map_err(|err| Diagnostic::new(&name, 1, 1, &err.to_string()))?;
+            let commands = read_numbered_commands(&name, &source, extended)?
+                .into_iter()
+                .map(|(line, command)| match validate(&command) {
+                    Ok(()) => Ok(command),
+                    Err(message) => Err(Diagnostic::new(
+                        &name,
+                        line,
+                        1,
+                        &format!("{} in '{}'", message, command),
+                    )),
+                })
+                .collect::<Result<_, _>>()?;
+            let filename = vm_file.as_path().file_stem().unwrap().to_str().unwrap();
+            Ok((filename.to_string(), commands))
+        })
+        .collect()
+}
+
+// first line of a .vm file compiled from Foo.jack
+fn compiled_from(jack_file: &Path) -> String {
+    let name = jack_file.file_name().unwrap_or_default().to_string_lossy();
+    format!("// compiled from {}", name)
+}
+
+// compile each .jack file to a .vm file next to it, returning the .vm paths.
+// An existing Foo.vm is only replaced when it was compiled from Foo.jack or
+// `overwrite` is set
+pub fn compile_jack_files(jack_files: &[PathBuf], overwrite: bool) -> Result<Vec<PathBuf>, String> {
+    let mut vm_files = Vec::new();
+    for jack_file in jack_files {
+        let source = read_to_string(jack_file)
+            .map_err(|err| format!("error reading {}: {}", jack_file.display(), err))?;
+        let commands = compile_jack(&jack_file.display().to_string(), &source)
+            .map_err(|diagnostic| diagnostic.to_string())?;
+        let vm_file = jack_file.with_extension("vm");
+        let header = compiled_from(jack_file);
+        let generated = read_to_string(&vm_file)
+            .map(|existing| existing.lines().next() == Some(header.as_str()))
+            .unwrap_or(true);
+        if !overwrite && !generated {
+            return Err(format!(
+                "{} already exists, pass --overwrite-vm to replace it with the compiled {}",
+                vm_file.display(),
+                jack_file.display()
+            ));
+        }
+        let write = || {
+            let mut file = VmFile::create(&vm_file)?;
+            writeln!(file.file, "{}", header)?;
+            for command in &commands {
+                writeln!(file.file, "{}", command)?;
+            }
+            file.commit()
+        };
+        write().map_err(|err| format!("error writing {}: {}", vm_file.display(), err))?;
+        vm_files.push(vm_file);
+    }
+    Ok(vm_files)
+}
+
+// write FooT.xml (tokens) and Foo.xml (parse tree) for Foo.jack into `output_dir`,
+// next to the source when it is None
+pub fn write_jack_xml(jack_file: &Path, output_dir: Option<&Path>) -> Result<PathBuf, String> {
+    let source = read_to_string(jack_file)
+        .map_err(|err| format!("error reading {}: {}", jack_file.display(), err))?;
+    let filename = jack_file.display().to_string();
+    let tokens = tokenize(&filename, &source).map_err(|diagnostic| diagnostic.to_string())?;
+    let class = parse_class(&filename, &tokens).map_err(|diagnostic| diagnostic.to_string())?;
+
+    let stem = jack_file.file_stem().unwrap().to_str().unwrap();
+    let dir = output_dir.unwrap_or(jack_file.parent().unwrap_or(Path::new("")));
+    let tree_path = dir.join(format!("{}.xml", stem));
+    for (path, xml) in [
+        (dir.join(format!("{}T.xml", stem)), tokens_xml(&tokens)),
+        (tree_path.clone(), class_xml(&class)),
+    ] {
+        write_text_file(&path, &xml)
+            .map_err(|err| format!("error writing {}: {}", path.display(), err))?;
+    }
+    Ok(tree_path)
+}
+
+// replace a file with `contents` in one step
+pub fn write_text_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut file = VmFile::create(path)?;
+    file.file.write_all(contents.as_bytes())?;
+    file.commit()
+}
+
+// write commands out in VM syntax, replacing the file only once it is complete
+pub fn write_vm_file(path: &Path, commands: &[Command]) -> std::io::Result<()> {
+    let mut vm_file = VmFile::create(path)?;
+    for command in commands {
+        writeln!(vm_file.file, "{}", command)?;
+    }
+    vm_file.commit()
+}
+
+// parse vm source keeping the line number of each command, the first line
+// that is not a command is reported as a diagnostic
+pub fn read_numbered_commands(
+    filename: &str,
+    source: &str,
+    extended: bool,
+) -> Result<Vec<(usize, Command)>, Diagnostic> {
+    let mut parser = Parser::new(source.lines().map(|line| line.trim().to_string()).collect());
+    parser.set_extended(extended);
+    let mut commands = Vec::new();
+    while let Some(result) = parser.next() {
+        match result {
+            Ok(command) => commands.push((parser.currentLine, command)),
+            Err(err) => return Err(Diagnostic::new(filename, err.line, 1, &err.message)),
+        }
+    }
+    Ok(commands)
+}
+
+// all the commands of a parser, or the first line that is not one
+pub fn read_commands(parser: Parser) -> Result<Vec<Command>, ParseError> {
+    parser.collect()
+}
+
+// translate every file of the program into one output file with any
+// backend, and close it
+pub fn compile_program<B: Backend>(
+    program: &[(String, Vec<Command>)],
+    mut code_writer: B,
+    test: bool,
+) -> std::io::Result<()> {
     // initialize the memory base address if we are testing/debugging
-    if *test {
-        code_writer.init_stack();
+    if test {
+        code_writer.init_stack()?;
+    }
+    // pass the commands sequentially to the code_writer, also invoking setFilename on the code_writer
+    for (filename, commands) in program {
+        code_writer.set_file_name(filename);
+        write_commands(commands, &mut code_writer)?;
     }
-    dbg!(test);
-    while parser.hasMoreLines() {
-        parser.advance();
-
-        dbg!(&parser.current_instruction());
-        dbg!(&parser.commandType());
-        // match on command type
-        if let Ok(cmd) = parser.commandType() {
-            match cmd {
-                "C_PUSH" => {
-                    dbg!(cmd);
-                    code_writer
-                        .write_push_pop(
-                            cmd,
-                            &parser.arg1().expect("error"),
-                            &parser.arg2().expect("error").parse().unwrap(),
-                        )
-                        .expect("error");
-                }
-                "C_POP" => {
-                    dbg!(cmd);
-                    code_writer
-                        .write_push_pop(
-                            cmd,
-                            &parser.arg1().expect("error"),
-                            &parser.arg2().expect("error").parse().unwrap(),
-                        )
-                        .expect("error");
-                }
-                "C_ARITHMETIC" => {
-                    dbg!(cmd);
-                    code_writer
-                        .write_arithmetic(&parser.arg1().expect("error"))
-                        .expect("error");
-                }
-                "C_LABEL" => {
-                    dbg!(cmd);
-                    code_writer
-                        .write_label(&parser.arg1().expect("error"))
-                        .expect("error");
-                }
-                "C_IFGOTO" => {
-                    dbg!(cmd);
-                    code_writer
-                        .write_ifgoto(&parser.arg1().expect("error"))
-                        .expect("error");
-                }
-                "C_GOTO" => {
-                    dbg!(cmd);
-                    code_writer
-                        .write_goto(&parser.arg1().expect("error"))
-                        .expect("error")
-                }
-                "C_FUNCTION" => {
-                    dbg!(cmd);
-                    code_writer
-                        .write_function(
-                            &parser.arg1().expect("function name error"),
-                            &parser.arg2().expect("nvars error"),
-                        )
-                        .expect("error")
-                }
-                "C_RETURN" => {
-                    dbg!(cmd);
-                    code_writer.write_return().expect("error")
-                }
-                "C_CALL" => {
-                    dbg!(cmd);
-                    code_writer
-                        .write_call(
-                            &parser.arg1().expect("error"),
-                            &parser.arg2().expect("error"),
-                        )
-                        .expect("error")
-                }
-                _ => {}
+    code_writer.close()
+}
+
+// translate a program to .asm text with a source map, through a temporary
+// file. Without `bootstrap` the code starts straight at the first command,
+// for test scripts that set up the stack themselves
+pub fn translate_to_string(
+    program: &[(String, Vec<Command>)],
+    bootstrap: bool,
+    extended: bool,
+) -> std::io::Result<String> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "hack_vm_{}_{}.asm",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut code_writer = CodeWriter::new(VmFile::create(&path)?, !bootstrap)?;
+    code_writer.set_extended(extended);
+    code_writer.set_source_map(true);
+    compile_program(program, code_writer, false)?;
+    let asm = read_to_string(&path);
+    _ = fs::remove_file(&path);
+    asm
+}
+
+pub fn write_commands<B: Backend>(
+    commands: &[Command],
+    code_writer: &mut B,
+) -> std::io::Result<()> {
+    let mut i = 0;
+    while i < commands.len() {
+        match &commands[i] {
+            Command::Call(name, nargs)
+                if code_writer.tail_calls() && commands.get(i + 1) == Some(&Command::Return) =>
+            {
+                code_writer.write_source_map(&commands[i])?;
+                code_writer.write_tail_call(name, *nargs)?;
+                // the return is never reached, skip it
+                i += 1;
+            }
+            command => {
+                code_writer.write_source_map(command)?;
+                code_writer.write_command(command)?
             }
-        } else {
-            panic!(
-                "{}: {}",
-                "command not implemented",
-                parser.current_instruction()
-            );
         }
+        i += 1;
     }
-
-    code_writer
+    Ok(())
 }
@@ -0,0 +1,606 @@
+// Recognizes the instruction templates CodeWriter emits and turns them back
+// into VM commands, split into the files they came from. `//@` source map
+// comments are used when there are any. Templates of older versions of the
+// translator, whose function entry was `@f` and whose if-goto did not load
+// D, are recognized too.
+use crate::code_writer::extended_arithmetic;
+use crate::diagnostics::Diagnostic;
+use crate::parser::{Command, Parser, EXTENDED_COMMANDS};
+use std::collections::HashSet;
+use std::path::Path;
+
+const PUSH_D: [&str; 5] = ["@SP", "A=M", "M=D", "@SP", "M=M+1"];
+const POP_TO_R13: [&str; 8] = ["@R13", "M=D", "@SP", "A=M", "D=M", "@R13", "A=M", "M=D"];
+const SEGMENTS: [(&str, &str); 4] = [
+    ("LCL", "local"),
+    ("ARG", "argument"),
+    ("THIS", "this"),
+    ("THAT", "that"),
+];
+const RETURN: &[&str] = &[
+    "@LCL", "D=M", "@SP", "A=M", "M=D", "@13", "M=D", "@SP", "M=M-1", "@5", "D=A", "@R13", "D=M-D",
+    "A=D", "D=M", "@14", "M=D", "@SP", "A=M", "D=M", "@ARG", "A=M", "M=D", "@ARG", "D=M", "@SP",
+    "M=D+1", "@R13", "A=M-1", "D=M", "@THAT", "M=D", "@2", "D=A", "@R13", "A=M-D", "D=M", "@THIS",
+    "M=D", "@3", "D=A", "@R13", "A=M-D", "D=M", "@ARG", "M=D", "@4", "D=A", "@R13", "A=M-D", "D=M",
+    "@LCL", "M=D",
+];
+const RETURN_JUMP: [&str; 3] = ["@R14", "A=M", "0;JMP"];
+// CodeWriter marks each local initialized by `function f n`
+const NVARS_MARKER: &str = "//nvars";
+// `//@ File: command` before each command's instructions with --source-map
+const SOURCE_MAP_MARKER: &str = "//@";
+
+struct Disassembler {
+    // (source line, instruction) without comments and spaces, source map
+    // comments are kept as they are
+    instructions: Vec<(usize, String)>,
+    position: usize,
+    function_name: Option<String>,
+    // functions named by the return label of a call to them, f$ret.i
+    called: HashSet<String>,
+    // the file of the last static matched, `File` of `File.i`
+    static_file: Option<String>,
+    // the return that ends a tail call
+    pending: Option<Command>,
+}
+
+// the commands of each file, in the order the files were translated
+pub fn disassemble(filename: &str, asm: &str) -> Result<Vec<(String, Vec<Command>)>, Diagnostic> {
+    let instructions: Vec<(usize, String)> = asm
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            if line.trim_start().starts_with(SOURCE_MAP_MARKER) {
+                return Some((i + 1, line.trim().to_string()));
+            }
+            let line: String = line.split_whitespace().collect();
+            if line == NVARS_MARKER {
+                return Some((i + 1, line));
+            }
+            let instruction = line.split("//").next().unwrap_or_default();
+            (!instruction.is_empty()).then(|| (i + 1, instruction.to_string()))
+        })
+        .collect();
+    let called = instructions
+        .iter()
+        .filter_map(|(_, instruction)| {
+            let label = instruction.strip_prefix('(')?.strip_suffix(')')?;
+            let (function, index) = label.rsplit_once("$ret.")?;
+            is_number(index).then(|| function.to_string())
+        })
+        .collect();
+    let mut disassembler = Disassembler {
+        instructions,
+        position: 0,
+        function_name: None,
+        called,
+        static_file: None,
+        pending: None,
+    };
+    disassembler.skip_setup();
+
+    let error = |(line, instruction): &(usize, String), message: &str| {
+        Diagnostic::new(
+            filename,
+            *line,
+            1,
+            &format!("{} '{}'", message, instruction),
+        )
+    };
+    let mut commands = Vec::new();
+    while disassembler.position < disassembler.instructions.len() {
+        if disassembler.at_source_map() {
+            let start = disassembler.instructions[disassembler.position].clone();
+            let mapped = disassembler
+                .source_map()
+                .ok_or_else(|| error(&start, "unrecognized source map comment"))?;
+            commands.extend(mapped);
+            continue;
+        }
+        match disassembler.command() {
+            Some(command) => {
+                commands.push((disassembler.static_file.take(), command));
+                if let Some(command) = disassembler.pending.take() {
+                    commands.push((None, command));
+                }
+            }
+            None => {
+                let start = &disassembler.instructions[disassembler.position];
+                return Err(error(start, "unrecognized instruction sequence at"));
+            }
+        }
+    }
+    let default = Path::new(filename)
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+    Ok(split_files(&default, commands))
+}
+
+// a function belongs to the file of the first static or source map comment
+// in it, else to its class, else to the file of the function before it
+fn split_files(
+    default: &str,
+    commands: Vec<(Option<String>, Command)>,
+) -> Vec<(String, Vec<Command>)> {
+    let mut functions: Vec<(Option<String>, Vec<Command>)> = Vec::new();
+    for (file, command) in commands {
+        if matches!(command, Command::Function(..)) || functions.is_empty() {
+            functions.push((None, Vec::new()));
+        }
+        let function = functions.last_mut().unwrap();
+        if function.0.is_none() {
+            function.0 = file;
+        }
+        function.1.push(command);
+    }
+
+    let mut files: Vec<(String, Vec<Command>)> = Vec::new();
+    let mut last = default.to_string();
+    for (file, function) in functions {
+        let class = match function.first() {
+            Some(Command::Function(name, _)) => name.split_once('.').map(|(class, _)| class),
+            _ => None,
+        };
+        last = file.or(class.map(str::to_string)).unwrap_or(last);
+        match files.iter_mut().find(|(name, _)| *name == last) {
+            Some((_, commands)) => commands.extend(function),
+            None => files.push((last.clone(), function)),
+        }
+    }
+    files
+}
+
+fn is_number(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_digit())
+}
+
+impl Disassembler {
+    // match a template at the current position, where each line may hold one
+    // `{}` placeholder, returning the captured text without consuming anything
+    fn capture(&self, template: &[&str]) -> Option<Vec<String>> {
+        let mut captures = Vec::new();
+        for (i, pattern) in template.iter().enumerate() {
+            let (_, instruction) = self.instructions.get(self.position + i)?;
+            match pattern.split_once("{}") {
+                Some((prefix, suffix)) => {
+                    let captured = instruction
+                        .strip_prefix(prefix)?
+                        .strip_suffix(suffix)
+                        .filter(|captured| !captured.is_empty())?;
+                    captures.push(captured.to_string());
+                }
+                None if instruction == pattern => {}
+                None => return None,
+            }
+        }
+        Some(captures)
+    }
+
+    fn accept(&mut self, template: &[&str]) -> Option<Vec<String>> {
+        let captures = self.capture(template)?;
+        self.position += template.len();
+        Some(captures)
+    }
+
+    fn at_source_map(&self) -> bool {
+        self.instructions
+            .get(self.position)
+            .is_some_and(|(_, instruction)| instruction.starts_with(SOURCE_MAP_MARKER))
+    }
+
+    // the command a `//@ File: command` comment names, its instructions are
+    // skipped. A call whose return label is missing was a tail call, so it
+    // also stands for the return after it
+    fn source_map(&mut self) -> Option<Vec<(Option<String>, Command)>> {
+        let (_, comment) = &self.instructions[self.position];
+        let (file, text) = comment.strip_prefix(SOURCE_MAP_MARKER)?.split_once(':')?;
+        let mut parser = Parser::new(vec![text.to_string()]);
+        parser.set_extended(true);
+        let command = parser.next()?.ok()?;
+        let file = Some(file.trim().to_string()).filter(|file| !file.is_empty());
+
+        self.position += 1;
+        let mut returns = false;
+        while self.position < self.instructions.len() && !self.at_source_map() {
+            let (_, instruction) = &self.instructions[self.position];
+            if let Command::Call(name, _) = &command {
+                returns |= instruction.starts_with(&format!("({}$ret.", name));
+            }
+            self.position += 1;
+        }
+        let mut commands = vec![(file, command.clone())];
+        if matches!(command, Command::Call(..)) && !returns {
+            commands.push((None, Command::Return));
+        }
+        Some(commands)
+    }
+
+    // the index of static `File.i`, remembering the file
+    fn static_index(&mut self, symbol: &str) -> Option<String> {
+        let (file, index) = symbol.rsplit_once('.')?;
+        self.static_file = Some(file.to_string());
+        Some(index.to_string())
+    }
+
+    // the bootstrap or the fixed segment addresses written in test mode
+    fn skip_setup(&mut self) {
+        let start = self.position;
+        if self.accept(&["@256", "D=A", "@0", "M=D"]).is_some() {
+            if let Some(Command::Call(name, 0)) = self.call() {
+                if name == "Sys.init" {
+                    return;
+                }
+            }
+            self.position = start;
+            return;
+        }
+        for register in ["SP", "LCL", "ARG", "THIS", "THAT"] {
+            let address = format!("@{}", register);
+            match self.accept(&["@{}", "D=A", &address, "M=D"]) {
+                Some(captures) if is_number(&captures[0]) => {}
+                _ => {
+                    self.position = start;
+                    return;
+                }
+            }
+        }
+    }
+
+    // calls go first, their return address push looks like a push of a symbol
+    fn command(&mut self) -> Option<Command> {
+        let matchers: [fn(&mut Self) -> Option<Command>; 8] = [
+            Self::tail_call,
+            Self::call,
+            Self::push,
+            Self::pop,
+            Self::arithmetic,
+            Self::branch,
+            Self::function_or_label,
+            Self::ret,
+        ];
+        let start = self.position;
+        for matcher in matchers {
+            if let Some(command) = matcher(self) {
+                return Some(command);
+            }
+            self.position = start;
+        }
+        None
+    }
+
+    fn push(&mut self) -> Option<Command> {
+        let push = |segment: &str, index: &str| {
+            Some(Command::Push(segment.to_string(), index.parse().ok()?))
+        };
+        if let Some(c) = self.capture(&[&["@{}", "D=A"][..], &PUSH_D].concat()) {
+            if is_number(&c[0]) {
+                self.position += 7;
+                return push("constant", &c[0]);
+            }
+        }
+        if let Some(c) =
+            self.capture(&[&["@{}", "D=A", "@{}", "A=M+D", "D=M"][..], &PUSH_D].concat())
+        {
+            let (_, segment) = SEGMENTS.iter().find(|(register, _)| *register == c[1])?;
+            self.position += 10;
+            return push(segment, &c[0]);
+        }
+        if let Some(c) = self.accept(&[&["@{}", "D=A", "@5", "A=A+D", "D=M"][..], &PUSH_D].concat())
+        {
+            return push("temp", &c[0]);
+        }
+        let c = self.accept(&[&["@{}", "D=M"][..], &PUSH_D].concat())?;
+        match c[0].as_str() {
+            "THIS" => push("pointer", "0"),
+            "THAT" => push("pointer", "1"),
+            symbol => push("static", &self.static_index(symbol)?),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Command> {
+        let pop = |segment: &str, index: &str| {
+            Some(Command::Pop(segment.to_string(), index.parse().ok()?))
+        };
+        if let Some(c) = self.capture(
+            &[
+                &["@SP", "M=M-1", "@{}", "D=A", "@{}", "D=M+D"][..],
+                &POP_TO_R13,
+            ]
+            .concat(),
+        ) {
+            let (_, segment) = SEGMENTS.iter().find(|(register, _)| *register == c[1])?;
+            self.position += 14;
+            return pop(segment, &c[0]);
+        }
+        if let Some(c) = self.accept(
+            &[
+                &["@SP", "M=M-1", "@{}", "D=A", "@5", "D=A+D"][..],
+                &POP_TO_R13,
+            ]
+            .concat(),
+        ) {
+            return pop("temp", &c[0]);
+        }
+        let c = self.accept(&["@SP", "M=M-1", "@SP", "A=M", "D=M", "@{}", "M=D"])?;
+        match c[0].as_str() {
+            "THIS" => pop("pointer", "0"),
+            "THAT" => pop("pointer", "1"),
+            symbol => pop("static", &self.static_index(symbol)?),
+        }
+    }
+
+    fn arithmetic(&mut self) -> Option<Command> {
+        let arithmetic = |command: &str| Some(Command::Arithmetic(command.to_string()));
+        if self.accept(&["@SP", "A=M-1", "M=!M"]).is_some() {
+            return arithmetic("not");
+        }
+        let neg = [
+            "@SP", "M=M-1", "@SP", "A=M", "D=M", "@SP", "A=M", "D=-D", "M=D", "@SP", "M=M+1",
+        ];
+        if self.accept(&neg).is_some() {
+            return arithmetic("neg");
+        }
+
+        let operands = ["@SP", "M=M-1", "@SP", "A=M", "D=M", "@SP", "A=M-1", "{}"];
+        if let Some(c) = self.capture(&[&operands[..], &["M=D"]].concat()) {
+            let command = match c[0].as_str() {
+                "D=D+M" => "add",
+                "D=M-D" => "sub",
+                "D=D&M" => "and",
+                "D=D|M" => "or",
+                _ => return None,
+            };
+            self.position += 9;
+            return arithmetic(command);
+        }
+        let compare = [
+            "@TRUE_{}",
+            "D;{}",
+            "@SP",
+            "M=M-1",
+            "A=M",
+            "M=0",
+            "@CONTINUE_{}",
+            "0;JMP",
+            "(TRUE_{})",
+            "@SP",
+            "M=M-1",
+            "A=M",
+            "M=-1",
+            "(CONTINUE_{})",
+            "@SP",
+            "M=M+1",
+        ];
+        // eq, and gt and lt as they were before they compared signs first
+        if let Some(c) = self.capture(&[&operands[..], &compare].concat()) {
+            let command = match (c[0].as_str(), c[2].as_str()) {
+                ("D=M-D", "JEQ") => Some("eq"),
+                ("D=M-D", "JGT") => Some("gt"),
+                ("D=D-M", "JGT") => Some("lt"),
+                _ => None,
+            };
+            if let Some(command) = command {
+                self.position += operands.len() + compare.len();
+                return arithmetic(command);
+            }
+        }
+
+        // --extended commands, their templates come from CodeWriter
+        for command in EXTENDED_COMMANDS {
+            let template: Vec<String> = extended_arithmetic(command, "{}")?
+                .iter()
+                .map(|line| line.split_whitespace().collect())
+                .filter(|line: &String| !line.starts_with("//"))
+                .collect();
+            let template: Vec<&str> = template.iter().map(String::as_str).collect();
+            if self.accept(&template).is_some() {
+                return arithmetic(command);
+            }
+        }
+
+        // gt and lt compare the signs before subtracting
+        let comparison = [
+            "@SP",
+            "AM=M-1",
+            "D=M",
+            "@R13",
+            "M=D",
+            "@SP",
+            "A=M-1",
+            "D=M",
+            "@XNEG_{}",
+            "D;JLT",
+            "@R13",
+            "D=M",
+            "@SAME_SIGN_{}",
+            "D;JGE",
+            "{}",
+            "@CONTINUE_{}",
+            "0;JMP",
+            "(XNEG_{})",
+            "@R13",
+            "D=M",
+            "@SAME_SIGN_{}",
+            "D;JLT",
+            "{}",
+            "@CONTINUE_{}",
+            "0;JMP",
+            "(SAME_SIGN_{})",
+            "@R13",
+            "D=M",
+            "@SP",
+            "A=M-1",
+            "D=M-D",
+            "@TRUE_{}",
+            "D;{}",
+            "D=0",
+            "@CONTINUE_{}",
+            "0;JMP",
+            "(TRUE_{})",
+            "D=-1",
+            "(CONTINUE_{})",
+            "@SP",
+            "A=M-1",
+            "M=D",
+        ];
+        let c = self.accept(&comparison)?;
+        match (c[2].as_str(), c[6].as_str(), c[10].as_str()) {
+            ("D=-1", "D=0", "JGT") => arithmetic("gt"),
+            ("D=0", "D=-1", "JLT") => arithmetic("lt"),
+            _ => None,
+        }
+    }
+
+    // labels written inside function f are scoped as f$label
+    fn unscoped(&self, label: &str) -> String {
+        self.function_name
+            .as_ref()
+            .and_then(|name| label.strip_prefix(&format!("{}$", name)))
+            .unwrap_or(label)
+            .to_string()
+    }
+
+    fn branch(&mut self) -> Option<Command> {
+        if let Some(c) = self.accept(&["@SP", "AM=M-1", "D=M", "@{}", "D;JNE"]) {
+            return Some(Command::IfGoto(self.unscoped(&c[0])));
+        }
+        // older versions jumped on whatever D held
+        if let Some(c) = self.accept(&["@SP", "M=M-1", "@{}", "D;JNE"]) {
+            return Some(Command::IfGoto(self.unscoped(&c[0])));
+        }
+        let c = self.accept(&["@{}", "0;JMP"])?;
+        Some(Command::Goto(self.unscoped(&c[0])))
+    }
+
+    // a function entry is followed by its nvars markers, or else is told
+    // apart from a label by a call to it or the Class.name dot. Older
+    // versions wrote the entry as `@f` instead of `(f)`
+    fn function_or_label(&mut self) -> Option<Command> {
+        if let Some(c) = self.capture(&["@{}"]) {
+            let name = c[0].clone();
+            self.position += 1;
+            let numeric = name.starts_with(|c: char| c.is_ascii_digit());
+            return (!numeric && self.is_function(&name)).then(|| self.function(&name));
+        }
+        let c = self.accept(&["({})"])?;
+        let name = c[0].as_str();
+        if !self.is_function(name) {
+            return Some(Command::Label(self.unscoped(name)));
+        }
+        Some(self.function(name))
+    }
+
+    // called with the position just after the entry
+    fn is_function(&self, name: &str) -> bool {
+        let scoped = self
+            .function_name
+            .as_ref()
+            .is_some_and(|function| name.starts_with(&format!("{}$", function)));
+        let has_markers = self.capture(&[NVARS_MARKER]).is_some();
+        !scoped && (has_markers || self.called.contains(name) || name.contains('.'))
+    }
+
+    // the locals pushed after the entry of function `name`
+    fn function(&mut self, name: &str) -> Command {
+        let has_markers = self.capture(&[NVARS_MARKER]).is_some();
+        let push_zero = [&["@0", "D=A"][..], &PUSH_D].concat();
+        let mut nvars = 0;
+        loop {
+            let template = if has_markers {
+                [&[NVARS_MARKER][..], &push_zero].concat()
+            } else {
+                push_zero.clone()
+            };
+            if self.accept(&template).is_none() {
+                break;
+            }
+            nvars += 1;
+        }
+        self.function_name = Some(name.to_string());
+        Command::Function(name.to_string(), nvars)
+    }
+
+    fn call(&mut self) -> Option<Command> {
+        let mut template = vec!["@{}", "D=A"];
+        template.extend(PUSH_D);
+        for register in ["@LCL", "@ARG", "@THIS", "@THAT"] {
+            template.extend([register, "D=M"]);
+            template.extend(PUSH_D);
+        }
+        template.extend([
+            "@SP", "D=M", "@5", "D=D-A", "@{}", "D=D-A", "@ARG", "M=D", "@SP", "D=M", "@LCL",
+            "M=D", "@{}", "0;JMP", "({})",
+        ]);
+        let c = self.capture(&template)?;
+        let (function, _) = c[0].rsplit_once("$ret.")?;
+        if function != c[2] || c[0] != c[3] {
+            return None;
+        }
+        let nargs = c[1].parse().ok()?;
+        self.position += template.len();
+        Some(Command::Call(function.to_string(), nargs))
+    }
+
+    // `call f n` directly followed by `return` with --tail-calls: the saved
+    // frame is pushed again and moved down to ARG along with the arguments
+    fn tail_call(&mut self) -> Option<Command> {
+        let mut template = Vec::new();
+        let offsets = ["@5", "@4", "@3", "@2", "@1"];
+        for offset in offsets {
+            template.extend(["@LCL", "D=M", offset, "A=D-A", "D=M"]);
+            template.extend(PUSH_D);
+        }
+        template.extend([
+            "@SP",
+            "D=M",
+            "@{}",
+            "D=D-A",
+            "@R13",
+            "M=D",
+            "@ARG",
+            "D=M",
+            "@R14",
+            "M=D",
+            "@{}",
+            "D=A",
+            "@R15",
+            "M=D",
+            "(TAIL_COPY_{})",
+            "@R13",
+            "A=M",
+            "D=M",
+            "@R14",
+            "A=M",
+            "M=D",
+            "@R13",
+            "M=M+1",
+            "@R14",
+            "M=M+1",
+            "@R15",
+            "MD=M-1",
+            "@TAIL_COPY_{}",
+            "D;JGT",
+            "@R14",
+            "D=M",
+            "@SP",
+            "M=D",
+            "@LCL",
+            "M=D",
+            "@{}",
+            "0;JMP",
+        ]);
+        let c = self.capture(&template)?;
+        let moved: i16 = c[0].parse().ok()?;
+        if c[0] != c[1] || c[2] != c[3] || moved < 5 {
+            return None;
+        }
+        self.position += template.len();
+        self.pending = Some(Command::Return);
+        Some(Command::Call(c[4].clone(), moved - 5))
+    }
+
+    fn ret(&mut self) -> Option<Command> {
+        self.accept(&[RETURN, &RETURN_JUMP].concat())?;
+        Some(Command::Return)
+    }
+}
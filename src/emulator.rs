@@ -0,0 +1,229 @@
+// Hack CPU emulator running assembled machine code against 32K words of RAM.
+// The ROM is decoded once into an op table so the run loop doesn't pick
+// instructions apart every cycle
+pub const RAM_SIZE: usize = 32768;
+
+pub struct Emulator {
+    pub rom: Vec<u16>,
+    pub ram: Vec<i16>,
+    pub a: i16,
+    pub d: i16,
+    pub pc: u16,
+    pub cycles: u64,
+    ops: Vec<Op>,
+    // see set_no_progress
+    no_progress: Option<u64>,
+    snapshot: Option<Snapshot>,
+    stuck: bool,
+}
+
+// the machine state at a taken jump, to notice coming back to it
+struct Snapshot {
+    cycles: u64,
+    pc: u16,
+    a: i16,
+    d: i16,
+    ram: Vec<i16>,
+}
+
+// a ROM word decoded ahead of time. The ALU's zero and negate bits become
+// masks, so computing is branch-free: x = (x & keep_x) ^ flip_x and the same
+// for y, then x + y or x & y, then ^ flip_out
+#[derive(Debug, Clone, Copy)]
+struct Op {
+    // @value, when not a C-instruction
+    load: bool,
+    value: i16,
+    keep_x: i16,
+    flip_x: i16,
+    keep_y: i16,
+    flip_y: i16,
+    add: bool,
+    flip_out: i16,
+    // y is RAM[A] rather than A
+    m: bool,
+    dest_a: bool,
+    dest_d: bool,
+    dest_m: bool,
+    jump: u8,
+}
+
+fn decode(instruction: u16) -> Op {
+    let bit = |n: u16| instruction & (1 << n) != 0;
+    let mask = |set: bool| if set { -1 } else { 0 };
+    Op {
+        load: !bit(15),
+        value: instruction as i16,
+        keep_x: mask(!bit(11)),
+        flip_x: mask(bit(10)),
+        keep_y: mask(!bit(9)),
+        flip_y: mask(bit(8)),
+        add: bit(7),
+        flip_out: mask(bit(6)),
+        m: bit(12),
+        dest_a: bit(5),
+        dest_d: bit(4),
+        dest_m: bit(3),
+        jump: (instruction & 0b111) as u8,
+    }
+}
+
+fn jumps(value: i16, jump: u8) -> bool {
+    (jump & 0b100 != 0 && value < 0)
+        || (jump & 0b010 != 0 && value == 0)
+        || (jump & 0b001 != 0 && value > 0)
+}
+
+impl Emulator {
+    pub fn new(rom: Vec<u16>) -> Self {
+        Emulator {
+            ops: rom.iter().map(|instruction| decode(*instruction)).collect(),
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            no_progress: None,
+            snapshot: None,
+            stuck: false,
+        }
+    }
+
+    // also count as halted when a run comes back to exactly the state it
+    // was in at an earlier jump, taken less than `window` cycles before,
+    // which catches loops like the OS's `Sys.halt` that aren't a jump to
+    // self. This stops at every taken jump, so runs are slower; `None` turns
+    // it off. Only sound while nothing changes RAM from outside, like keys
+    pub fn set_no_progress(&mut self, window: Option<u64>) {
+        self.no_progress = window;
+        self.snapshot = None;
+    }
+
+    // false once the program counter has run off the end of the ROM
+    pub fn is_running(&self) -> bool {
+        usize::from(self.pc) < self.rom.len()
+    }
+
+    // at an unconditional jump to itself that changes nothing else, like
+    // `(END) @END 0;JMP`, the way programs stop, or stuck in a loop that
+    // makes no progress (see set_no_progress)
+    pub fn is_halted(&self) -> bool {
+        self.stuck || self.jumps_to_self()
+    }
+
+    // true when is_halted() is from the no-progress check
+    pub fn is_stuck(&self) -> bool {
+        self.stuck
+    }
+
+    fn jumps_to_self(&self) -> bool {
+        let pc = usize::from(self.pc);
+        let Some(op) = self.ops.get(pc) else {
+            return false;
+        };
+        if op.load || op.jump != 0b111 || op.dest_a || op.dest_d || op.dest_m {
+            return false;
+        }
+        // the jump itself, or `@target` just before it
+        let target = usize::from(self.a as u16);
+        target == pc || (target + 1 == pc && self.rom[target] == target as u16)
+    }
+
+    pub fn step(&mut self) {
+        self.execute(1, false);
+    }
+
+    // compare with the snapshot after a taken jump, taking a new one every
+    // `window` cycles
+    fn check_progress(&mut self, window: u64) {
+        if let Some(snapshot) = &self.snapshot {
+            let registers = (snapshot.pc, snapshot.a, snapshot.d);
+            if registers == (self.pc, self.a, self.d) && snapshot.ram == self.ram {
+                self.stuck = true;
+                return;
+            }
+            if self.cycles - snapshot.cycles < window {
+                return;
+            }
+        }
+        self.snapshot = Some(Snapshot {
+            cycles: self.cycles,
+            pc: self.pc,
+            a: self.a,
+            d: self.d,
+            ram: self.ram.clone(),
+        });
+    }
+
+    // run until the program halts or ends, or `max_cycles` have passed.
+    // Returns false when it ran out of cycles
+    pub fn run(&mut self, max_cycles: u64) -> bool {
+        let limit = self.cycles.saturating_add(max_cycles);
+        self.stuck = false;
+        self.snapshot = None;
+        while self.is_running() && !self.is_halted() {
+            if self.cycles >= limit {
+                return false;
+            }
+            // programs halt at an unconditional jump, so run straight
+            // through to the next one, or the next taken jump when checking
+            // for progress
+            self.execute(limit - self.cycles, self.no_progress.is_some());
+            if let Some(window) = self.no_progress {
+                self.check_progress(window);
+            }
+        }
+        true
+    }
+
+    // run at most `max_cycles` instructions, stopping before an unconditional
+    // jump (other than the first), after any taken jump with `stop_at_jumps`
+    // or at the end of the ROM. The registers
+    // live in locals and RAM is a fixed-size array, which keeps bounds checks
+    // out of the loop
+    fn execute(&mut self, max_cycles: u64, stop_at_jumps: bool) {
+        let ram: &mut [i16; RAM_SIZE] = (&mut self.ram[..]).try_into().unwrap();
+        let (mut a, mut d, mut pc) = (self.a, self.d, self.pc);
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            let Some(&op) = self.ops.get(usize::from(pc)) else {
+                break;
+            };
+            if cycles > 0 && !op.load && op.jump == 0b111 {
+                break;
+            }
+            cycles += 1;
+            if op.load {
+                a = op.value;
+                pc += 1;
+                continue;
+            }
+            // M and the jump target both use the A held before this instruction
+            let address = usize::from(a as u16) % RAM_SIZE;
+            let x = (d & op.keep_x) ^ op.flip_x;
+            let y = (if op.m { ram[address] } else { a } & op.keep_y) ^ op.flip_y;
+            let out = if op.add { x.wrapping_add(y) } else { x & y } ^ op.flip_out;
+            let target = a as u16;
+            if op.dest_m {
+                ram[address] = out;
+            }
+            if op.dest_a {
+                a = out;
+            }
+            if op.dest_d {
+                d = out;
+            }
+            if jumps(out, op.jump) {
+                pc = target;
+                if stop_at_jumps {
+                    break;
+                }
+            } else {
+                pc += 1;
+            }
+        }
+        (self.a, self.d, self.pc) = (a, d, pc);
+        self.cycles += cycles;
+    }
+}
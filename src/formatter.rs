@@ -0,0 +1,125 @@
+// `hack_vm fmt`: one command per line with single spaces between tokens,
+// function bodies indented, trailing comments aligned within each run of
+// commented lines and at most one blank line in a row
+use crate::compiler::read_numbered_commands;
+use crate::diagnostics::Diagnostic;
+use crate::parser::Command;
+use std::collections::HashMap;
+
+const INDENT: &str = "    ";
+
+enum Line {
+    Blank,
+    Comment(String),
+    // (indented command, trailing comment)
+    Code(String, Option<String>),
+}
+
+// everything after the first `//`, normalized to `// text`
+fn comment(line: &str) -> Option<String> {
+    let (_, text) = line.split_once("//")?;
+    let text = text.trim();
+    Some(if text.is_empty() {
+        "//".to_string()
+    } else {
+        format!("// {}", text)
+    })
+}
+
+pub fn format_vm(filename: &str, source: &str, extended: bool) -> Result<String, Diagnostic> {
+    let commands: HashMap<usize, Command> = read_numbered_commands(filename, source, extended)?
+        .into_iter()
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut in_function = false;
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        match commands.get(&(i + 1)) {
+            Some(command) => {
+                if let Command::Function(..) = command {
+                    in_function = true;
+                }
+                let indent = match command {
+                    Command::Function(..) => "",
+                    _ if in_function => INDENT,
+                    _ => "",
+                };
+                lines.push(Line::Code(format!("{}{}", indent, command), comment(line)));
+            }
+            None if line.is_empty() => lines.push(Line::Blank),
+            None => lines.push(Line::Comment(comment(line).unwrap_or_default())),
+        }
+    }
+
+    // a comment block takes the indentation of the command right below it
+    let mut indents = vec![String::new(); lines.len()];
+    let mut below = String::new();
+    for (i, line) in lines.iter().enumerate().rev() {
+        match line {
+            Line::Code(code, _) => {
+                below = code[..code.len() - code.trim_start().len()].to_string();
+            }
+            Line::Comment(_) => indents[i] = below.clone(),
+            Line::Blank => {
+                // a block followed by a blank line stays with the code above
+                below = lines[..i]
+                    .iter()
+                    .rev()
+                    .find_map(|line| match line {
+                        Line::Code(code, _) if code.starts_with(INDENT) => Some(INDENT),
+                        Line::Code(..) => Some(""),
+                        _ => None,
+                    })
+                    .unwrap_or("")
+                    .to_string();
+            }
+        }
+    }
+
+    let mut output = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        match &lines[i] {
+            Line::Blank => {
+                let at_edge = output.is_empty() || i + 1 == lines.len();
+                if !at_edge && !output.ends_with("\n\n") {
+                    output.push('\n');
+                }
+                i += 1;
+            }
+            Line::Comment(text) => {
+                output.push_str(&format!("{}{}\n", indents[i], text));
+                i += 1;
+            }
+            Line::Code(..) => {
+                // align the trailing comments of consecutive commented commands
+                let run: Vec<(&String, &Option<String>)> = lines[i..]
+                    .iter()
+                    .map_while(|line| match line {
+                        Line::Code(code, comment) => Some((code, comment)),
+                        _ => None,
+                    })
+                    .take_while(|(_, comment)| comment.is_some())
+                    .collect();
+                if run.is_empty() {
+                    if let Line::Code(code, _) = &lines[i] {
+                        output.push_str(&format!("{}\n", code));
+                    }
+                    i += 1;
+                    continue;
+                }
+                let width = run.iter().map(|(code, _)| code.len()).max().unwrap_or(0);
+                for (code, comment) in &run {
+                    let comment = comment.as_deref().unwrap_or_default();
+                    output.push_str(&format!("{:width$} {}\n", code, comment, width = width));
+                }
+                i += run.len();
+            }
+        }
+    }
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+    Ok(output)
+}
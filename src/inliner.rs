@@ -0,0 +1,212 @@
+use crate::analysis::{function_spans, stack_depths, stack_effect};
+use crate::parser::Command;
+use std::collections::HashMap;
+
+// functions longer than this many commands are never inlined
+pub const DEFAULT_INLINE_THRESHOLD: usize = 12;
+
+// the temp segment is RAM[5]-RAM[12]
+const TEMP_SIZE: i16 = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Inlined {
+    pub callee: String,
+    pub caller: String,
+    pub call_sites: usize,
+}
+
+struct LeafFunction {
+    file: String,
+    nvars: i16,
+    body: Vec<Command>,
+    // pointer 0/1 written by the body, restored after the inlined code
+    saves_pointer: Vec<i16>,
+    uses_static: bool,
+    // number of arguments the body reads or writes
+    nargs_used: i16,
+}
+
+// Replace `call f n` with the body of f when f is a small leaf function (it makes no
+// calls, so it can never be recursive). Arguments and locals of the inlined body are
+// remapped to the temp segment, which the VM does not preserve across calls anyway.
+pub fn inline_leaf_functions(
+    files: &mut [(String, Vec<Command>)],
+    threshold: usize,
+) -> Vec<Inlined> {
+    let leaves = find_leaf_functions(files, threshold);
+    let mut report: Vec<Inlined> = Vec::new();
+    let mut inline_number = 0;
+
+    for (filename, commands) in files.iter_mut() {
+        let mut caller = String::new();
+        let mut rewritten = Vec::with_capacity(commands.len());
+        for command in commands.drain(..) {
+            if let Command::Function(name, _) = &command {
+                caller = name.to_string();
+            }
+            let leaf = match &command {
+                Command::Call(name, nargs) => leaves
+                    .get(name)
+                    .filter(|leaf| can_inline(leaf, filename, *nargs))
+                    .map(|leaf| (name.to_string(), *nargs, leaf)),
+                _ => None,
+            };
+            match leaf {
+                Some((callee, nargs, leaf)) => {
+                    rewritten.extend(expand(&callee, nargs, leaf, inline_number));
+                    inline_number += 1;
+                    match report
+                        .iter_mut()
+                        .find(|r| r.callee == callee && r.caller == caller)
+                    {
+                        Some(entry) => entry.call_sites += 1,
+                        None => report.push(Inlined {
+                            callee,
+                            caller: caller.to_string(),
+                            call_sites: 1,
+                        }),
+                    }
+                }
+                None => rewritten.push(command),
+            }
+        }
+        *commands = rewritten;
+    }
+
+    report
+}
+
+fn find_leaf_functions(
+    files: &[(String, Vec<Command>)],
+    threshold: usize,
+) -> HashMap<String, LeafFunction> {
+    let mut leaves = HashMap::new();
+    for (filename, commands) in files {
+        for (start, end) in function_spans(commands) {
+            let Command::Function(name, nvars) = &commands[start] else {
+                continue;
+            };
+            let nvars = *nvars;
+            let body = &commands[start + 1..end];
+
+            let is_leaf = body.iter().all(|c| !matches!(c, Command::Call(_, _)));
+            let uses_temp = body.iter().any(|c| segment_of(c) == Some("temp"));
+            if !is_leaf || uses_temp || body.len() > threshold || !is_balanced(body) {
+                continue;
+            }
+
+            let mut saves_pointer = Vec::new();
+            for pointer in 0..2 {
+                if body.contains(&Command::Pop("pointer".to_string(), pointer)) {
+                    saves_pointer.push(pointer);
+                }
+            }
+            let nargs_used = body
+                .iter()
+                .filter_map(|c| match c {
+                    Command::Push(segment, index) | Command::Pop(segment, index)
+                        if segment == "argument" =>
+                    {
+                        Some(index + 1)
+                    }
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0);
+
+            leaves.insert(
+                name.to_string(),
+                LeafFunction {
+                    file: filename.to_string(),
+                    nvars,
+                    body: body.to_vec(),
+                    saves_pointer,
+                    uses_static: body.iter().any(|c| segment_of(c) == Some("static")),
+                    nargs_used,
+                },
+            );
+        }
+    }
+    leaves
+}
+
+fn can_inline(leaf: &LeafFunction, caller_file: &str, nargs: i16) -> bool {
+    // statics are named after the file, they can't move to another one
+    let statics_ok = !leaf.uses_static || leaf.file == caller_file;
+    let temps_needed = nargs + leaf.nvars + leaf.saves_pointer.len() as i16;
+    statics_ok && leaf.nargs_used <= nargs && temps_needed <= TEMP_SIZE
+}
+
+fn segment_of(command: &Command) -> Option<&str> {
+    match command {
+        Command::Push(segment, _) | Command::Pop(segment, _) => Some(segment),
+        _ => None,
+    }
+}
+
+// every path must leave exactly the return value on the stack and never pop
+// below the function's own working stack
+fn is_balanced(body: &[Command]) -> bool {
+    let Some(depths) = stack_depths(body) else {
+        return false;
+    };
+    depths.iter().all(|&(i, depth)| match body.get(i) {
+        // fell off the end without returning
+        None => false,
+        Some(command) => {
+            let depth = depth + stack_effect(command);
+            depth >= 0 && (command != &Command::Return || depth == 1)
+        }
+    })
+}
+
+fn expand(callee: &str, nargs: i16, leaf: &LeafFunction, inline_number: usize) -> Vec<Command> {
+    let prefix = format!("{}.inline{}", callee, inline_number);
+    let save_base = nargs + leaf.nvars;
+    let mut commands = Vec::new();
+
+    // arguments are on top of the stack, last one first
+    for arg in (0..nargs).rev() {
+        commands.push(Command::Pop("temp".to_string(), arg));
+    }
+    for (slot, pointer) in leaf.saves_pointer.iter().enumerate() {
+        commands.push(Command::Push("pointer".to_string(), *pointer));
+        commands.push(Command::Pop("temp".to_string(), save_base + slot as i16));
+    }
+    for local in 0..leaf.nvars {
+        commands.push(Command::Push("constant".to_string(), 0));
+        commands.push(Command::Pop("temp".to_string(), nargs + local));
+    }
+
+    let remap = |segment: &String, index: &i16| match segment.as_str() {
+        "argument" => ("temp".to_string(), *index),
+        "local" => ("temp".to_string(), nargs + index),
+        _ => (segment.to_string(), *index),
+    };
+    let last = leaf.body.len() - 1;
+    for (i, command) in leaf.body.iter().enumerate() {
+        commands.push(match command {
+            Command::Push(segment, index) => {
+                let (segment, index) = remap(segment, index);
+                Command::Push(segment, index)
+            }
+            Command::Pop(segment, index) => {
+                let (segment, index) = remap(segment, index);
+                Command::Pop(segment, index)
+            }
+            Command::Label(label) => Command::Label(format!("{}.{}", prefix, label)),
+            Command::Goto(label) => Command::Goto(format!("{}.{}", prefix, label)),
+            Command::IfGoto(label) => Command::IfGoto(format!("{}.{}", prefix, label)),
+            Command::Return if i == last => continue,
+            Command::Return => Command::Goto(prefix.to_string()),
+            command => command.clone(),
+        });
+    }
+    commands.push(Command::Label(prefix));
+
+    for (slot, pointer) in leaf.saves_pointer.iter().enumerate() {
+        commands.push(Command::Push("temp".to_string(), save_base + slot as i16));
+        commands.push(Command::Pop("pointer".to_string(), *pointer));
+    }
+    commands
+}
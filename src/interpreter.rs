@@ -0,0 +1,251 @@
+// Reference VM interpreter. It runs commands directly, using the same RAM
+// layout as the translated code (stack, frames, segments and statics from
+// RAM[16] in order of first use), so its RAM can be compared with the
+// emulator's after running the .asm. Return addresses are kept aside and
+// their stack slots hold 0.
+use crate::emulator::RAM_SIZE;
+use crate::parser::Command;
+use std::collections::HashMap;
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP_BASE: i16 = 5;
+const STATIC_BASE: i16 = 16;
+const STACK_BASE: i16 = 256;
+
+pub struct Interpreter {
+    pub ram: Vec<i16>,
+    commands: Vec<Command>,
+    // the file each command came from, for its statics
+    files: Vec<String>,
+    // function entry points and `function$label` targets, by command index
+    targets: HashMap<String, usize>,
+    // the function each command belongs to, labels are scoped to it
+    scopes: Vec<String>,
+    statics: HashMap<String, i16>,
+    return_stack: Vec<usize>,
+    pub pc: usize,
+    pub steps: u64,
+}
+
+impl Interpreter {
+    pub fn new(program: &[(String, Vec<Command>)]) -> Self {
+        let mut interpreter = Interpreter {
+            ram: vec![0; RAM_SIZE],
+            commands: Vec::new(),
+            files: Vec::new(),
+            targets: HashMap::new(),
+            scopes: Vec::new(),
+            statics: HashMap::new(),
+            return_stack: Vec::new(),
+            pc: 0,
+            steps: 0,
+        };
+        let mut scope = String::new();
+        for (file, commands) in program {
+            for command in commands {
+                let index = interpreter.commands.len();
+                match command {
+                    Command::Function(name, _) => {
+                        scope = name.to_string();
+                        interpreter.targets.insert(name.to_string(), index);
+                    }
+                    Command::Label(label) => {
+                        interpreter
+                            .targets
+                            .insert(format!("{}${}", scope, label), index);
+                    }
+                    Command::Push(segment, i) | Command::Pop(segment, i) if segment == "static" => {
+                        let next = STATIC_BASE + interpreter.statics.len() as i16;
+                        interpreter
+                            .statics
+                            .entry(format!("{}.{}", file, i))
+                            .or_insert(next);
+                    }
+                    _ => {}
+                }
+                interpreter.commands.push(command.clone());
+                interpreter.files.push(file.to_string());
+                interpreter.scopes.push(scope.clone());
+            }
+        }
+        interpreter
+    }
+
+    // SP = 256 and call Sys.init, as the bootstrap code does
+    pub fn bootstrap(&mut self) -> Result<(), String> {
+        self.ram[SP] = STACK_BASE;
+        self.call("Sys.init", 0, self.commands.len())
+    }
+
+    fn address(&self, pointer: i16) -> usize {
+        usize::from(pointer as u16) % RAM_SIZE
+    }
+
+    fn push(&mut self, value: i16) {
+        let sp = self.address(self.ram[SP]);
+        self.ram[sp] = value;
+        self.ram[SP] = self.ram[SP].wrapping_add(1);
+    }
+
+    fn pop(&mut self) -> i16 {
+        self.ram[SP] = self.ram[SP].wrapping_sub(1);
+        self.ram[self.address(self.ram[SP])]
+    }
+
+    fn segment_address(&self, segment: &str, index: i16) -> Result<usize, String> {
+        let base = |register: usize| Ok(self.address(self.ram[register].wrapping_add(index)));
+        match segment {
+            "local" => base(LCL),
+            "argument" => base(ARG),
+            "this" => base(THIS),
+            "that" => base(THAT),
+            "pointer" => Ok(self.address(THIS as i16 + index)),
+            "temp" => Ok(self.address(TEMP_BASE + index)),
+            "static" => {
+                let name = format!("{}.{}", self.files[self.pc], index);
+                Ok(self.address(self.statics[&name]))
+            }
+            _ => Err(format!("no segment {}", segment)),
+        }
+    }
+
+    fn target(&self, label: &str) -> Result<usize, String> {
+        let name = format!("{}${}", self.scopes[self.pc], label);
+        self.targets
+            .get(&name)
+            .copied()
+            .ok_or(format!("no label {}", name))
+    }
+
+    fn call(&mut self, function: &str, nargs: i16, return_to: usize) -> Result<(), String> {
+        let entry = *self
+            .targets
+            .get(function)
+            .ok_or(format!("no function {}", function))?;
+        self.return_stack.push(return_to);
+        self.push(0);
+        for register in [LCL, ARG, THIS, THAT] {
+            self.push(self.ram[register]);
+        }
+        self.ram[ARG] = self.ram[SP].wrapping_sub(5).wrapping_sub(nargs);
+        self.ram[LCL] = self.ram[SP];
+        self.pc = entry;
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, command: &str) -> Result<(), String> {
+        let truth = |value: bool| if value { -1 } else { 0 };
+        match command {
+            "neg" => {
+                let x = self.pop();
+                self.push(x.wrapping_neg());
+            }
+            "not" => {
+                let x = self.pop();
+                self.push(!x);
+            }
+            _ => {
+                let y = self.pop();
+                let x = self.pop();
+                let value = match command {
+                    "add" => x.wrapping_add(y),
+                    "sub" => x.wrapping_sub(y),
+                    "eq" => truth(x == y),
+                    "gt" => truth(x > y),
+                    "lt" => truth(x < y),
+                    "and" => x & y,
+                    "or" => x | y,
+                    "mul" => x.wrapping_mul(y),
+                    "div" | "mod" if y == 0 => return Err("division by zero".to_string()),
+                    "div" => x.wrapping_div(y),
+                    "mod" => x.wrapping_rem(y),
+                    "shl" => x.wrapping_shl((y & 15) as u32),
+                    "shr" => x.wrapping_shr((y & 15) as u32),
+                    "xor" => x ^ y,
+                    _ => return Err(format!("unknown command {}", command)),
+                };
+                self.push(value);
+            }
+        }
+        Ok(())
+    }
+
+    // false once the program counter has run off the end of the commands
+    pub fn is_running(&self) -> bool {
+        self.pc < self.commands.len()
+    }
+
+    // `label END` followed by `goto END`, the way programs stop
+    pub fn is_halted(&self) -> bool {
+        match (self.commands.get(self.pc), self.pc.checked_sub(1)) {
+            (Some(Command::Goto(label)), Some(previous)) => {
+                self.commands[previous] == Command::Label(label.to_string())
+            }
+            _ => false,
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), String> {
+        let command = self.commands[self.pc].clone();
+        self.steps += 1;
+        let mut next = self.pc + 1;
+        match &command {
+            Command::Push(segment, index) if segment == "constant" => self.push(*index),
+            Command::Push(segment, index) => {
+                let address = self.segment_address(segment, *index)?;
+                self.push(self.ram[address]);
+            }
+            Command::Pop(segment, index) => {
+                let address = self.segment_address(segment, *index)?;
+                self.ram[address] = self.pop();
+            }
+            Command::Arithmetic(command) => self.arithmetic(command)?,
+            Command::Label(_) => {}
+            Command::Goto(label) => next = self.target(label)?,
+            Command::IfGoto(label) => {
+                if self.pop() != 0 {
+                    next = self.target(label)?;
+                }
+            }
+            Command::Function(_, nvars) => {
+                for _ in 0..*nvars {
+                    self.push(0);
+                }
+            }
+            Command::Call(function, nargs) => return self.call(function, *nargs, next),
+            Command::Return => {
+                let frame = self.address(self.ram[LCL]);
+                let value = self.pop();
+                let arg = self.address(self.ram[ARG]);
+                self.ram[arg] = value;
+                self.ram[SP] = self.ram[ARG].wrapping_add(1);
+                for (register, offset) in [(THAT, 1), (THIS, 2), (ARG, 3), (LCL, 4)] {
+                    self.ram[register] = self.ram[(frame + RAM_SIZE - offset) % RAM_SIZE];
+                }
+                next = self
+                    .return_stack
+                    .pop()
+                    .ok_or("return without a call".to_string())?;
+            }
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    // run until the program halts or ends, or `max_steps` have passed.
+    // Returns false when it ran out of steps
+    pub fn run(&mut self, max_steps: u64) -> Result<bool, String> {
+        let limit = self.steps + max_steps;
+        while self.is_running() && !self.is_halted() {
+            if self.steps >= limit {
+                return Ok(false);
+            }
+            self.step()?;
+        }
+        Ok(true)
+    }
+}
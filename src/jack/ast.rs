@@ -0,0 +1,99 @@
+// Jack syntax tree. Types, kinds and operators are kept as they are written in
+// the source so the tree can be printed back out token for token.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Class {
+    pub name: String,
+    pub var_decs: Vec<ClassVarDec>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassVarDec {
+    // static or field
+    pub kind: String,
+    pub var_type: String,
+    pub names: Vec<String>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutine {
+    // constructor, function or method
+    pub kind: String,
+    pub return_type: String,
+    pub name: String,
+    // (type, name)
+    pub parameters: Vec<(String, String)>,
+    pub var_decs: Vec<VarDec>,
+    pub statements: Vec<Statement>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarDec {
+    pub var_type: String,
+    pub names: Vec<String>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Let {
+        name: String,
+        index: Option<Expression>,
+        value: Expression,
+        line: usize,
+        column: usize,
+    },
+    If {
+        condition: Expression,
+        then_branch: Vec<Statement>,
+        else_branch: Option<Vec<Statement>>,
+    },
+    While {
+        condition: Expression,
+        body: Vec<Statement>,
+    },
+    Do(SubroutineCall),
+    Return(Option<Expression>),
+}
+
+// term (op term)*, Jack has no operator precedence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    pub first: Term,
+    pub rest: Vec<(char, Term)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    IntegerConstant(u16),
+    StringConstant(String),
+    // true, false, null or this
+    KeywordConstant(String),
+    Var {
+        name: String,
+        line: usize,
+        column: usize,
+    },
+    ArrayAccess {
+        name: String,
+        index: Box<Expression>,
+        line: usize,
+        column: usize,
+    },
+    Call(SubroutineCall),
+    Parenthesized(Box<Expression>),
+    Unary(char, Box<Term>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubroutineCall {
+    // a variable or class name before the dot, None for a method on this
+    pub receiver: Option<String>,
+    pub name: String,
+    pub arguments: Vec<Expression>,
+    pub line: usize,
+    pub column: usize,
+}
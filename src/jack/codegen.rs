@@ -0,0 +1,307 @@
+use crate::diagnostics::Diagnostic;
+use crate::jack::ast::{Class, Expression, Statement, Subroutine, SubroutineCall, Term};
+use crate::jack::symbol_table::SymbolTable;
+use crate::parser::Command;
+
+type CodegenResult = Result<(), Diagnostic>;
+
+struct CodeGenerator<'a> {
+    filename: &'a str,
+    class_name: &'a str,
+    symbols: SymbolTable,
+    commands: Vec<Command>,
+    if_count: usize,
+    while_count: usize,
+}
+
+// translate a parsed class into VM commands
+pub fn compile_class(filename: &str, class: &Class) -> Result<Vec<Command>, Diagnostic> {
+    let mut generator = CodeGenerator {
+        filename,
+        class_name: &class.name,
+        symbols: SymbolTable::new(),
+        commands: Vec::new(),
+        if_count: 0,
+        while_count: 0,
+    };
+
+    for var_dec in &class.var_decs {
+        for name in &var_dec.names {
+            generator.define(name, &var_dec.var_type, &var_dec.kind, var_dec.line)?;
+        }
+    }
+    for subroutine in &class.subroutines {
+        generator.subroutine(subroutine)?;
+    }
+    Ok(generator.commands)
+}
+
+impl CodeGenerator<'_> {
+    fn emit(&mut self, command: Command) {
+        self.commands.push(command);
+    }
+
+    fn push(&mut self, segment: &str, index: i16) {
+        self.emit(Command::Push(segment.to_string(), index));
+    }
+
+    fn pop(&mut self, segment: &str, index: i16) {
+        self.emit(Command::Pop(segment.to_string(), index));
+    }
+
+    fn arithmetic(&mut self, command: &str) {
+        self.emit(Command::Arithmetic(command.to_string()));
+    }
+
+    fn call(&mut self, name: &str, nargs: usize) {
+        self.emit(Command::Call(name.to_string(), nargs as i16));
+    }
+
+    fn define(&mut self, name: &str, var_type: &str, kind: &str, line: usize) -> CodegenResult {
+        if self.symbols.define(name, var_type, kind) {
+            Ok(())
+        } else {
+            Err(Diagnostic::new(
+                self.filename,
+                line,
+                1,
+                &format!("'{}' is already defined", name),
+            ))
+        }
+    }
+
+    fn undefined(&self, name: &str, line: usize, column: usize) -> Diagnostic {
+        Diagnostic::new(
+            self.filename,
+            line,
+            column,
+            &format!("undefined variable '{}'", name),
+        )
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) -> CodegenResult {
+        self.symbols.start_subroutine();
+        self.if_count = 0;
+        self.while_count = 0;
+
+        // a method's object is its hidden first argument
+        if subroutine.kind == "method" {
+            self.define("this", self.class_name, "argument", subroutine.line)?;
+        }
+        for (var_type, name) in &subroutine.parameters {
+            self.define(name, var_type, "argument", subroutine.line)?;
+        }
+        for var_dec in &subroutine.var_decs {
+            for name in &var_dec.names {
+                self.define(name, &var_dec.var_type, "var", var_dec.line)?;
+            }
+        }
+
+        let nvars = self.symbols.var_count("var");
+        self.emit(Command::Function(
+            format!("{}.{}", self.class_name, subroutine.name),
+            nvars,
+        ));
+        match subroutine.kind.as_str() {
+            "constructor" => {
+                let nfields = self.symbols.var_count("field");
+                self.push("constant", nfields);
+                self.call("Memory.alloc", 1);
+                self.pop("pointer", 0);
+            }
+            "method" => {
+                self.push("argument", 0);
+                self.pop("pointer", 0);
+            }
+            _ => {}
+        }
+
+        self.statements(&subroutine.statements)
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> CodegenResult {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> CodegenResult {
+        match statement {
+            Statement::Let {
+                name,
+                index,
+                value,
+                line,
+                column,
+            } => {
+                let symbol = self
+                    .symbols
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| self.undefined(name, *line, *column))?;
+                match index {
+                    Some(index) => {
+                        // target address first, the value may itself use THAT
+                        self.push(symbol.segment(), symbol.index);
+                        self.expression(index)?;
+                        self.arithmetic("add");
+                        self.expression(value)?;
+                        self.pop("temp", 0);
+                        self.pop("pointer", 1);
+                        self.push("temp", 0);
+                        self.pop("that", 0);
+                    }
+                    None => {
+                        self.expression(value)?;
+                        self.pop(symbol.segment(), symbol.index);
+                    }
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let n = self.if_count;
+                self.if_count += 1;
+                self.expression(condition)?;
+                self.emit(Command::IfGoto(format!("IF_TRUE{}", n)));
+                self.emit(Command::Goto(format!("IF_FALSE{}", n)));
+                self.emit(Command::Label(format!("IF_TRUE{}", n)));
+                self.statements(then_branch)?;
+                match else_branch {
+                    Some(else_branch) => {
+                        self.emit(Command::Goto(format!("IF_END{}", n)));
+                        self.emit(Command::Label(format!("IF_FALSE{}", n)));
+                        self.statements(else_branch)?;
+                        self.emit(Command::Label(format!("IF_END{}", n)));
+                    }
+                    None => self.emit(Command::Label(format!("IF_FALSE{}", n))),
+                }
+            }
+            Statement::While { condition, body } => {
+                let n = self.while_count;
+                self.while_count += 1;
+                self.emit(Command::Label(format!("WHILE_EXP{}", n)));
+                self.expression(condition)?;
+                self.arithmetic("not");
+                self.emit(Command::IfGoto(format!("WHILE_END{}", n)));
+                self.statements(body)?;
+                self.emit(Command::Goto(format!("WHILE_EXP{}", n)));
+                self.emit(Command::Label(format!("WHILE_END{}", n)));
+            }
+            Statement::Do(call) => {
+                self.subroutine_call(call)?;
+                // discard the return value
+                self.pop("temp", 0);
+            }
+            Statement::Return(value) => {
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.push("constant", 0),
+                }
+                self.emit(Command::Return);
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> CodegenResult {
+        self.term(&expression.first)?;
+        for (op, term) in &expression.rest {
+            self.term(term)?;
+            match op {
+                '+' => self.arithmetic("add"),
+                '-' => self.arithmetic("sub"),
+                '*' => self.call("Math.multiply", 2),
+                '/' => self.call("Math.divide", 2),
+                '&' => self.arithmetic("and"),
+                '|' => self.arithmetic("or"),
+                '<' => self.arithmetic("lt"),
+                '>' => self.arithmetic("gt"),
+                _ => self.arithmetic("eq"),
+            }
+        }
+        Ok(())
+    }
+
+    fn term(&mut self, term: &Term) -> CodegenResult {
+        match term {
+            Term::IntegerConstant(value) => self.push("constant", *value as i16),
+            Term::StringConstant(value) => {
+                self.push("constant", value.chars().count() as i16);
+                self.call("String.new", 1);
+                for c in value.chars() {
+                    self.push("constant", c as i16);
+                    self.call("String.appendChar", 2);
+                }
+            }
+            Term::KeywordConstant(keyword) => match keyword.as_str() {
+                "true" => {
+                    self.push("constant", 0);
+                    self.arithmetic("not");
+                }
+                "this" => self.push("pointer", 0),
+                // false and null
+                _ => self.push("constant", 0),
+            },
+            Term::Var { name, line, column } => {
+                let symbol = self
+                    .symbols
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| self.undefined(name, *line, *column))?;
+                self.push(symbol.segment(), symbol.index);
+            }
+            Term::ArrayAccess {
+                name,
+                index,
+                line,
+                column,
+            } => {
+                let symbol = self
+                    .symbols
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| self.undefined(name, *line, *column))?;
+                self.push(symbol.segment(), symbol.index);
+                self.expression(index)?;
+                self.arithmetic("add");
+                self.pop("pointer", 1);
+                self.push("that", 0);
+            }
+            Term::Call(call) => self.subroutine_call(call)?,
+            Term::Parenthesized(expression) => self.expression(expression)?,
+            Term::Unary(op, term) => {
+                self.term(term)?;
+                self.arithmetic(if *op == '-' { "neg" } else { "not" });
+            }
+        }
+        Ok(())
+    }
+
+    fn subroutine_call(&mut self, call: &SubroutineCall) -> CodegenResult {
+        let (function, nargs) = match &call.receiver {
+            // a method of the current object
+            None => {
+                self.push("pointer", 0);
+                (format!("{}.{}", self.class_name, call.name), 1)
+            }
+            Some(receiver) => match self.symbols.get(receiver).cloned() {
+                // a method of the object held in a variable
+                Some(symbol) => {
+                    self.push(symbol.segment(), symbol.index);
+                    (format!("{}.{}", symbol.var_type, call.name), 1)
+                }
+                // a function or constructor of a class
+                None => (format!("{}.{}", receiver, call.name), 0),
+            },
+        };
+        for argument in &call.arguments {
+            self.expression(argument)?;
+        }
+        self.call(&function, nargs + call.arguments.len());
+        Ok(())
+    }
+}
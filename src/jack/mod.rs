@@ -0,0 +1,16 @@
+// Jack compiler front end: source -> tokens -> syntax tree -> VM commands
+pub mod ast;
+pub mod codegen;
+pub mod parser;
+pub mod symbol_table;
+pub mod tokenizer;
+pub mod xml;
+
+use crate::diagnostics::Diagnostic;
+use crate::parser::Command;
+
+pub fn compile_jack(filename: &str, source: &str) -> Result<Vec<Command>, Diagnostic> {
+    let tokens = tokenizer::tokenize(filename, source)?;
+    let class = parser::parse_class(filename, &tokens)?;
+    codegen::compile_class(filename, &class)
+}
@@ -0,0 +1,409 @@
+use crate::diagnostics::Diagnostic;
+use crate::jack::ast::{
+    Class, ClassVarDec, Expression, Statement, Subroutine, SubroutineCall, Term, VarDec,
+};
+use crate::jack::tokenizer::{Token, TokenKind};
+
+pub const OPERATORS: &str = "+-*/&|<>=";
+
+pub struct JackParser<'a> {
+    filename: &'a str,
+    tokens: &'a [Token],
+    position: usize,
+}
+
+type ParseResult<T> = Result<T, Diagnostic>;
+
+pub fn parse_class(filename: &str, tokens: &[Token]) -> ParseResult<Class> {
+    let mut parser = JackParser {
+        filename,
+        tokens,
+        position: 0,
+    };
+    let class = parser.class()?;
+    match parser.peek() {
+        Some(token) => Err(parser.error_at(token, "expected end of file")),
+        None => Ok(class),
+    }
+}
+
+impl JackParser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.position + offset)
+    }
+
+    fn line(&self) -> usize {
+        self.peek().map_or(0, |token| token.line)
+    }
+
+    fn error_at(&self, token: &Token, message: &str) -> Diagnostic {
+        Diagnostic::new(self.filename, token.line, token.column, message)
+    }
+
+    fn error(&self, expected: &str) -> Diagnostic {
+        match self.peek().or(self.tokens.last()) {
+            Some(token) if self.position < self.tokens.len() => self.error_at(
+                token,
+                &format!("expected {}, found '{}'", expected, token.text()),
+            ),
+            Some(token) => {
+                self.error_at(token, &format!("expected {}, found end of file", expected))
+            }
+            None => Diagnostic::new(
+                self.filename,
+                1,
+                1,
+                &format!("expected {}, found end of file", expected),
+            ),
+        }
+    }
+
+    fn at_symbol(&self, symbol: char) -> bool {
+        self.peek().is_some_and(|t| t.is_symbol(symbol))
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        self.peek().is_some_and(|t| t.is_keyword(keyword))
+    }
+
+    fn symbol(&mut self, symbol: char) -> ParseResult<()> {
+        if self.at_symbol(symbol) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("'{}'", symbol)))
+        }
+    }
+
+    fn keyword(&mut self, keywords: &[&str]) -> ParseResult<String> {
+        match self.peek() {
+            Some(token) if keywords.iter().any(|k| token.is_keyword(k)) => {
+                let keyword = token.text();
+                self.position += 1;
+                Ok(keyword)
+            }
+            _ => Err(self.error(&keywords.join(" or "))),
+        }
+    }
+
+    fn identifier(&mut self) -> ParseResult<String> {
+        match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Identifier(name)) => {
+                let name = name.to_string();
+                self.position += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("an identifier")),
+        }
+    }
+
+    // int, char, boolean or a class name
+    fn var_type(&mut self) -> ParseResult<String> {
+        if self.at_keyword("int") || self.at_keyword("char") || self.at_keyword("boolean") {
+            self.keyword(&["int", "char", "boolean"])
+        } else {
+            self.identifier().map_err(|_| self.error("a type"))
+        }
+    }
+
+    fn class(&mut self) -> ParseResult<Class> {
+        self.keyword(&["class"])?;
+        let name = self.identifier()?;
+        self.symbol('{')?;
+
+        let mut var_decs = Vec::new();
+        while self.at_keyword("static") || self.at_keyword("field") {
+            let line = self.line();
+            let kind = self.keyword(&["static", "field"])?;
+            let var_type = self.var_type()?;
+            let names = self.names()?;
+            var_decs.push(ClassVarDec {
+                kind,
+                var_type,
+                names,
+                line,
+            });
+        }
+
+        let mut subroutines = Vec::new();
+        while self.at_keyword("constructor")
+            || self.at_keyword("function")
+            || self.at_keyword("method")
+        {
+            subroutines.push(self.subroutine()?);
+        }
+
+        self.symbol('}')?;
+        Ok(Class {
+            name,
+            var_decs,
+            subroutines,
+        })
+    }
+
+    // varName (',' varName)* ';'
+    fn names(&mut self) -> ParseResult<Vec<String>> {
+        let mut names = vec![self.identifier()?];
+        while self.at_symbol(',') {
+            self.position += 1;
+            names.push(self.identifier()?);
+        }
+        self.symbol(';')?;
+        Ok(names)
+    }
+
+    fn subroutine(&mut self) -> ParseResult<Subroutine> {
+        let line = self.line();
+        let kind = self.keyword(&["constructor", "function", "method"])?;
+        let return_type = if self.at_keyword("void") {
+            self.keyword(&["void"])?
+        } else {
+            self.var_type()?
+        };
+        let name = self.identifier()?;
+
+        self.symbol('(')?;
+        let mut parameters = Vec::new();
+        if !self.at_symbol(')') {
+            loop {
+                let var_type = self.var_type()?;
+                parameters.push((var_type, self.identifier()?));
+                if !self.at_symbol(',') {
+                    break;
+                }
+                self.position += 1;
+            }
+        }
+        self.symbol(')')?;
+
+        self.symbol('{')?;
+        let mut var_decs = Vec::new();
+        while self.at_keyword("var") {
+            let line = self.line();
+            self.position += 1;
+            let var_type = self.var_type()?;
+            let names = self.names()?;
+            var_decs.push(VarDec {
+                var_type,
+                names,
+                line,
+            });
+        }
+        let statements = self.statements()?;
+        self.symbol('}')?;
+
+        Ok(Subroutine {
+            kind,
+            return_type,
+            name,
+            parameters,
+            var_decs,
+            statements,
+            line,
+        })
+    }
+
+    fn statements(&mut self) -> ParseResult<Vec<Statement>> {
+        let mut statements = Vec::new();
+        while !self.at_symbol('}') {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn block(&mut self) -> ParseResult<Vec<Statement>> {
+        self.symbol('{')?;
+        let statements = self.statements()?;
+        self.symbol('}')?;
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> ParseResult<Statement> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(self.error("a statement")),
+        };
+        match &token.kind {
+            TokenKind::Keyword(keyword) if keyword == "let" => {
+                self.position += 1;
+                let (line, column) = self
+                    .peek()
+                    .map_or((token.line, token.column), |t| (t.line, t.column));
+                let name = self.identifier()?;
+                let index = if self.at_symbol('[') {
+                    self.position += 1;
+                    let index = self.expression()?;
+                    self.symbol(']')?;
+                    Some(index)
+                } else {
+                    None
+                };
+                self.symbol('=')?;
+                let value = self.expression()?;
+                self.symbol(';')?;
+                Ok(Statement::Let {
+                    name,
+                    index,
+                    value,
+                    line,
+                    column,
+                })
+            }
+            TokenKind::Keyword(keyword) if keyword == "if" => {
+                self.position += 1;
+                self.symbol('(')?;
+                let condition = self.expression()?;
+                self.symbol(')')?;
+                let then_branch = self.block()?;
+                let else_branch = if self.at_keyword("else") {
+                    self.position += 1;
+                    Some(self.block()?)
+                } else {
+                    None
+                };
+                Ok(Statement::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                })
+            }
+            TokenKind::Keyword(keyword) if keyword == "while" => {
+                self.position += 1;
+                self.symbol('(')?;
+                let condition = self.expression()?;
+                self.symbol(')')?;
+                let body = self.block()?;
+                Ok(Statement::While { condition, body })
+            }
+            TokenKind::Keyword(keyword) if keyword == "do" => {
+                self.position += 1;
+                let call = self.subroutine_call()?;
+                self.symbol(';')?;
+                Ok(Statement::Do(call))
+            }
+            TokenKind::Keyword(keyword) if keyword == "return" => {
+                self.position += 1;
+                let value = if self.at_symbol(';') {
+                    None
+                } else {
+                    Some(self.expression()?)
+                };
+                self.symbol(';')?;
+                Ok(Statement::Return(value))
+            }
+            _ => Err(self.error("a statement")),
+        }
+    }
+
+    fn expression(&mut self) -> ParseResult<Expression> {
+        let first = self.term()?;
+        let mut rest = Vec::new();
+        while let Some(TokenKind::Symbol(op)) = self.peek().map(|t| &t.kind) {
+            if !OPERATORS.contains(*op) {
+                break;
+            }
+            let op = *op;
+            self.position += 1;
+            rest.push((op, self.term()?));
+        }
+        Ok(Expression { first, rest })
+    }
+
+    fn expression_list(&mut self) -> ParseResult<Vec<Expression>> {
+        let mut expressions = Vec::new();
+        if !self.at_symbol(')') {
+            expressions.push(self.expression()?);
+            while self.at_symbol(',') {
+                self.position += 1;
+                expressions.push(self.expression()?);
+            }
+        }
+        Ok(expressions)
+    }
+
+    // name '(' args ')' or (className | varName) '.' name '(' args ')'
+    fn subroutine_call(&mut self) -> ParseResult<SubroutineCall> {
+        let (line, column) = match self.peek() {
+            Some(token) => (token.line, token.column),
+            None => return Err(self.error("a subroutine call")),
+        };
+        let first = self.identifier()?;
+        let (receiver, name) = if self.at_symbol('.') {
+            self.position += 1;
+            (Some(first), self.identifier()?)
+        } else {
+            (None, first)
+        };
+        self.symbol('(')?;
+        let arguments = self.expression_list()?;
+        self.symbol(')')?;
+        Ok(SubroutineCall {
+            receiver,
+            name,
+            arguments,
+            line,
+            column,
+        })
+    }
+
+    fn term(&mut self) -> ParseResult<Term> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(self.error("a term")),
+        };
+        match &token.kind {
+            TokenKind::IntegerConstant(value) => {
+                self.position += 1;
+                Ok(Term::IntegerConstant(*value))
+            }
+            TokenKind::StringConstant(value) => {
+                self.position += 1;
+                Ok(Term::StringConstant(value.to_string()))
+            }
+            TokenKind::Keyword(keyword)
+                if ["true", "false", "null", "this"].contains(&keyword.as_str()) =>
+            {
+                self.position += 1;
+                Ok(Term::KeywordConstant(keyword.to_string()))
+            }
+            TokenKind::Symbol('(') => {
+                self.position += 1;
+                let expression = self.expression()?;
+                self.symbol(')')?;
+                Ok(Term::Parenthesized(Box::new(expression)))
+            }
+            TokenKind::Symbol(op) if *op == '-' || *op == '~' => {
+                self.position += 1;
+                Ok(Term::Unary(*op, Box::new(self.term()?)))
+            }
+            TokenKind::Identifier(name) => {
+                let next = self.peek_at(1);
+                if next.is_some_and(|t| t.is_symbol('(') || t.is_symbol('.')) {
+                    Ok(Term::Call(self.subroutine_call()?))
+                } else if next.is_some_and(|t| t.is_symbol('[')) {
+                    self.position += 2;
+                    let index = self.expression()?;
+                    self.symbol(']')?;
+                    Ok(Term::ArrayAccess {
+                        name: name.to_string(),
+                        index: Box::new(index),
+                        line: token.line,
+                        column: token.column,
+                    })
+                } else {
+                    self.position += 1;
+                    Ok(Term::Var {
+                        name: name.to_string(),
+                        line: token.line,
+                        column: token.column,
+                    })
+                }
+            }
+            _ => Err(self.error("a term")),
+        }
+    }
+}
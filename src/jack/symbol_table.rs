@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub var_type: String,
+    // static, field, argument or var
+    pub kind: String,
+    pub index: i16,
+}
+
+impl Symbol {
+    // the VM segment holding variables of this kind
+    pub fn segment(&self) -> &str {
+        match self.kind.as_str() {
+            "static" => "static",
+            "field" => "this",
+            "argument" => "argument",
+            _ => "local",
+        }
+    }
+}
+
+// class scope (static, field) and subroutine scope (argument, var)
+#[derive(Default)]
+pub struct SymbolTable {
+    class_scope: HashMap<String, Symbol>,
+    subroutine_scope: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn start_subroutine(&mut self) {
+        self.subroutine_scope.clear();
+    }
+
+    // false if the name is already defined in the same scope
+    pub fn define(&mut self, name: &str, var_type: &str, kind: &str) -> bool {
+        let index = self.var_count(kind);
+        let scope = match kind {
+            "static" | "field" => &mut self.class_scope,
+            _ => &mut self.subroutine_scope,
+        };
+        if scope.contains_key(name) {
+            return false;
+        }
+        scope.insert(
+            name.to_string(),
+            Symbol {
+                var_type: var_type.to_string(),
+                kind: kind.to_string(),
+                index,
+            },
+        );
+        true
+    }
+
+    pub fn var_count(&self, kind: &str) -> i16 {
+        self.class_scope
+            .values()
+            .chain(self.subroutine_scope.values())
+            .filter(|symbol| symbol.kind == kind)
+            .count() as i16
+    }
+
+    // subroutine scope shadows class scope
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.subroutine_scope
+            .get(name)
+            .or_else(|| self.class_scope.get(name))
+    }
+}
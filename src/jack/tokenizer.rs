@@ -0,0 +1,162 @@
+use crate::diagnostics::Diagnostic;
+
+pub const KEYWORDS: [&str; 21] = [
+    "class",
+    "constructor",
+    "function",
+    "method",
+    "field",
+    "static",
+    "var",
+    "int",
+    "char",
+    "boolean",
+    "void",
+    "true",
+    "false",
+    "null",
+    "this",
+    "let",
+    "do",
+    "if",
+    "else",
+    "while",
+    "return",
+];
+
+pub const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword(String),
+    Symbol(char),
+    IntegerConstant(u16),
+    StringConstant(String),
+    Identifier(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Token {
+    pub fn is_symbol(&self, symbol: char) -> bool {
+        self.kind == TokenKind::Symbol(symbol)
+    }
+
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.kind, TokenKind::Keyword(k) if k == keyword)
+    }
+
+    // the token as it appears in the source, for error messages
+    pub fn text(&self) -> String {
+        match &self.kind {
+            TokenKind::Keyword(keyword) => keyword.to_string(),
+            TokenKind::Symbol(symbol) => symbol.to_string(),
+            TokenKind::IntegerConstant(value) => value.to_string(),
+            TokenKind::StringConstant(value) => format!("\"{}\"", value),
+            TokenKind::Identifier(name) => name.to_string(),
+        }
+    }
+}
+
+pub fn tokenize(filename: &str, source: &str) -> Result<Vec<Token>, Diagnostic> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i - line_start + 1;
+        // errors point at the start of the token
+        let start_line = line;
+        let error = |message: &str| Diagnostic::new(filename, start_line, column, message);
+
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            line_start = i;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            // block and /** api */ comments, which may span lines
+            i += 2;
+            loop {
+                match chars.get(i) {
+                    None => return Err(error("unterminated comment")),
+                    Some('*') if chars.get(i + 1) == Some(&'/') => {
+                        i += 2;
+                        break;
+                    }
+                    Some('\n') => {
+                        i += 1;
+                        line += 1;
+                        line_start = i;
+                    }
+                    Some(_) => i += 1,
+                }
+            }
+        } else if SYMBOLS.contains(c) {
+            tokens.push(Token {
+                kind: TokenKind::Symbol(c),
+                line,
+                column,
+            });
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().collect();
+            let value = match digits.parse::<u16>() {
+                Ok(value) if value <= 32767 => value,
+                _ => return Err(error(&format!("integer constant {} out of range", digits))),
+            };
+            tokens.push(Token {
+                kind: TokenKind::IntegerConstant(value),
+                line,
+                column,
+            });
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' && chars[i] != '\n' {
+                i += 1;
+            }
+            if chars.get(i) != Some(&'"') {
+                return Err(error("unterminated string constant"));
+            }
+            tokens.push(Token {
+                kind: TokenKind::StringConstant(chars[start..i].iter().collect()),
+                line,
+                column,
+            });
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let kind = if KEYWORDS.contains(&word.as_str()) {
+                TokenKind::Keyword(word)
+            } else {
+                TokenKind::Identifier(word)
+            };
+            tokens.push(Token { kind, line, column });
+        } else {
+            return Err(error(&format!("unexpected character '{}'", c)));
+        }
+    }
+    Ok(tokens)
+}
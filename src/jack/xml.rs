@@ -0,0 +1,280 @@
+// The course's XML formats: FooT.xml lists the tokens, Foo.xml is the parse tree.
+// The parse tree is printed back out from the syntax tree, token for token.
+use crate::jack::ast::{Class, Expression, Statement, Subroutine, SubroutineCall, Term};
+use crate::jack::tokenizer::{Token, TokenKind};
+
+const TYPE_KEYWORDS: [&str; 4] = ["int", "char", "boolean", "void"];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn tokens_xml(tokens: &[Token]) -> String {
+    let mut xml = String::from("<tokens>\n");
+    for token in tokens {
+        let (tag, text) = match &token.kind {
+            TokenKind::Keyword(keyword) => ("keyword", keyword.to_string()),
+            TokenKind::Symbol(symbol) => ("symbol", symbol.to_string()),
+            TokenKind::IntegerConstant(value) => ("integerConstant", value.to_string()),
+            TokenKind::StringConstant(value) => ("stringConstant", value.to_string()),
+            TokenKind::Identifier(name) => ("identifier", name.to_string()),
+        };
+        xml.push_str(&format!("<{}> {} </{}>\n", tag, escape(&text), tag));
+    }
+    xml.push_str("</tokens>\n");
+    xml
+}
+
+pub fn class_xml(class: &Class) -> String {
+    let mut writer = XmlWriter {
+        xml: String::new(),
+        depth: 0,
+    };
+    writer.class(class);
+    writer.xml
+}
+
+struct XmlWriter {
+    xml: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn line(&mut self, line: &str) {
+        self.xml.push_str(&"  ".repeat(self.depth));
+        self.xml.push_str(line);
+        self.xml.push('\n');
+    }
+
+    fn open(&mut self, tag: &str) {
+        self.line(&format!("<{}>", tag));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.line(&format!("</{}>", tag));
+    }
+
+    fn terminal(&mut self, tag: &str, text: &str) {
+        self.line(&format!("<{}> {} </{}>", tag, escape(text), tag));
+    }
+
+    fn keyword(&mut self, keyword: &str) {
+        self.terminal("keyword", keyword);
+    }
+
+    fn symbol(&mut self, symbol: char) {
+        self.terminal("symbol", &symbol.to_string());
+    }
+
+    fn identifier(&mut self, name: &str) {
+        self.terminal("identifier", name);
+    }
+
+    // int, char, boolean and void are keywords, class names are identifiers
+    fn var_type(&mut self, var_type: &str) {
+        if TYPE_KEYWORDS.contains(&var_type) {
+            self.keyword(var_type);
+        } else {
+            self.identifier(var_type);
+        }
+    }
+
+    fn names(&mut self, names: &[String]) {
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.identifier(name);
+        }
+        self.symbol(';');
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.open("class");
+        self.keyword("class");
+        self.identifier(&class.name);
+        self.symbol('{');
+        for var_dec in &class.var_decs {
+            self.open("classVarDec");
+            self.keyword(&var_dec.kind);
+            self.var_type(&var_dec.var_type);
+            self.names(&var_dec.names);
+            self.close("classVarDec");
+        }
+        for subroutine in &class.subroutines {
+            self.subroutine(subroutine);
+        }
+        self.symbol('}');
+        self.close("class");
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.open("subroutineDec");
+        self.keyword(&subroutine.kind);
+        self.var_type(&subroutine.return_type);
+        self.identifier(&subroutine.name);
+        self.symbol('(');
+        self.open("parameterList");
+        for (i, (var_type, name)) in subroutine.parameters.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.var_type(var_type);
+            self.identifier(name);
+        }
+        self.close("parameterList");
+        self.symbol(')');
+
+        self.open("subroutineBody");
+        self.symbol('{');
+        for var_dec in &subroutine.var_decs {
+            self.open("varDec");
+            self.keyword("var");
+            self.var_type(&var_dec.var_type);
+            self.names(&var_dec.names);
+            self.close("varDec");
+        }
+        self.statements(&subroutine.statements);
+        self.symbol('}');
+        self.close("subroutineBody");
+        self.close("subroutineDec");
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        self.open("statements");
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.close("statements");
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        self.symbol('{');
+        self.statements(statements);
+        self.symbol('}');
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let {
+                name, index, value, ..
+            } => {
+                self.open("letStatement");
+                self.keyword("let");
+                self.identifier(name);
+                if let Some(index) = index {
+                    self.symbol('[');
+                    self.expression(index);
+                    self.symbol(']');
+                }
+                self.symbol('=');
+                self.expression(value);
+                self.symbol(';');
+                self.close("letStatement");
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.open("ifStatement");
+                self.keyword("if");
+                self.symbol('(');
+                self.expression(condition);
+                self.symbol(')');
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.keyword("else");
+                    self.block(else_branch);
+                }
+                self.close("ifStatement");
+            }
+            Statement::While { condition, body } => {
+                self.open("whileStatement");
+                self.keyword("while");
+                self.symbol('(');
+                self.expression(condition);
+                self.symbol(')');
+                self.block(body);
+                self.close("whileStatement");
+            }
+            Statement::Do(call) => {
+                self.open("doStatement");
+                self.keyword("do");
+                self.subroutine_call(call);
+                self.symbol(';');
+                self.close("doStatement");
+            }
+            Statement::Return(value) => {
+                self.open("returnStatement");
+                self.keyword("return");
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                self.symbol(';');
+                self.close("returnStatement");
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.open("expression");
+        self.term(&expression.first);
+        for (op, term) in &expression.rest {
+            self.symbol(*op);
+            self.term(term);
+        }
+        self.close("expression");
+    }
+
+    fn term(&mut self, term: &Term) {
+        self.open("term");
+        match term {
+            Term::IntegerConstant(value) => self.terminal("integerConstant", &value.to_string()),
+            Term::StringConstant(value) => self.terminal("stringConstant", value),
+            Term::KeywordConstant(keyword) => self.keyword(keyword),
+            Term::Var { name, .. } => self.identifier(name),
+            Term::ArrayAccess { name, index, .. } => {
+                self.identifier(name);
+                self.symbol('[');
+                self.expression(index);
+                self.symbol(']');
+            }
+            Term::Call(call) => self.subroutine_call(call),
+            Term::Parenthesized(expression) => {
+                self.symbol('(');
+                self.expression(expression);
+                self.symbol(')');
+            }
+            Term::Unary(op, term) => {
+                self.symbol(*op);
+                self.term(term);
+            }
+        }
+        self.close("term");
+    }
+
+    // the course format has no element for the call itself
+    fn subroutine_call(&mut self, call: &SubroutineCall) {
+        if let Some(receiver) = &call.receiver {
+            self.identifier(receiver);
+            self.symbol('.');
+        }
+        self.identifier(&call.name);
+        self.symbol('(');
+        self.open("expressionList");
+        for (i, argument) in call.arguments.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.expression(argument);
+        }
+        self.close("expressionList");
+        self.symbol(')');
+    }
+}
@@ -1,11 +1,63 @@
+pub mod analysis;
+pub mod assembler;
+pub mod backend;
+pub mod c_writer;
 pub mod code_writer;
 pub mod compiler;
+pub mod diagnostics;
+pub mod disassembler;
+pub mod emulator;
+pub mod formatter;
+pub mod inliner;
+pub mod interpreter;
+pub mod jack;
+pub mod linter;
+pub mod lsp;
 pub mod parser;
+pub mod ram_file;
+pub mod screen;
+pub mod test_script;
+pub mod trace;
+pub mod tui;
+pub mod wat_writer;
 
 #[cfg(test)]
 mod tests {
-    use crate::compiler::VmFile;
-    use crate::parser::Parser;
+    use crate::analysis::analyze;
+    use crate::assembler::{assemble, instruction_text};
+    use crate::backend::Backend;
+    use crate::c_writer::CWriter;
+    use crate::code_writer::CodeWriter;
+    use crate::compiler::{
+        check_output_path, compile_jack_files, compile_program, files_with_extension,
+        parse_filename, read_commands, read_numbered_commands, read_program, translate_to_string,
+        VmFile,
+    };
+    use crate::disassembler::disassemble;
+    use crate::emulator::{Emulator, RAM_SIZE};
+    use crate::formatter::format_vm;
+    use crate::inliner::{inline_leaf_functions, Inlined, DEFAULT_INLINE_THRESHOLD};
+    use crate::interpreter::Interpreter;
+    use crate::jack::compile_jack;
+    use crate::jack::parser::parse_class;
+    use crate::jack::tokenizer::tokenize;
+    use crate::jack::xml::{class_xml, tokens_xml};
+    use crate::linter::lint;
+    use crate::lsp::Server;
+    use crate::parser::{strip_comment, Command, Parser};
+    use crate::ram_file;
+    use crate::screen::{
+        key_code, parse_key_script, pixel, to_pbm, to_png, KeyAction, HEIGHT, KBD, SCREEN, WIDTH,
+    };
+    use crate::test_script::{compare_output, TestScript};
+    use crate::trace::{run_traced, TraceMode};
+    use crate::tui::{self, Charset};
+    use crate::wat_writer::WatWriter;
+    use crossterm::event::KeyCode;
+    use proptest::prelude::*;
+    use serde_json::json;
+    use std::io::Write;
+    use std::path::PathBuf;
     use std::vec;
 
     #[test]
@@ -14,6 +66,35 @@ mod tests {
         assert!(test_vm_file.name() == "test");
     }
 
+    #[test]
+    fn vm_file_only_appears_on_commit() {
+        let path = std::env::temp_dir().join("hack_vm_commit_test.asm");
+        _ = std::fs::remove_file(&path);
+
+        let mut file = VmFile::create(&path).unwrap();
+        writeln!(file.file, "@0").unwrap();
+        drop(file);
+        assert!(!path.exists());
+
+        let mut file = VmFile::create(&path).unwrap();
+        writeln!(file.file, "@0").unwrap();
+        assert!(file.path() == path);
+        file.commit().unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap() == "@0\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn output_path_must_not_be_an_input() {
+        let inputs = vec![PathBuf::from("Main.vm"), PathBuf::from("Sys.vm")];
+        assert!(check_output_path(&PathBuf::from("Main.asm"), &inputs).is_ok());
+        assert!(check_output_path(&PathBuf::from("Sys.vm"), &inputs).is_err());
+        assert!(check_output_path(&PathBuf::from("Other.vm"), &inputs).is_err());
+        let inputs = vec![PathBuf::from("Main.jack"), PathBuf::from("Main.vm")];
+        assert!(check_output_path(&PathBuf::from("Main.jack"), &inputs).is_err());
+        assert!(check_output_path(&PathBuf::from("Other.jack"), &inputs).is_err());
+    }
+
     #[test]
     fn test_parser() {
         let test_data = vec![
@@ -24,6 +105,7 @@ mod tests {
             "add".to_string(),
         ];
         let mut parser = Parser::new(test_data);
+        parser.advance();
         assert!(parser.currentInstruction == "push constant 7");
         assert!(parser.commandType().unwrap() == "C_PUSH");
         assert!(parser.arg1().unwrap() == "constant");
@@ -39,7 +121,1148 @@ mod tests {
         assert!(parser.currentInstruction == "add");
         assert!(parser.commandType().unwrap() == "C_ARITHMETIC");
         assert!(parser.arg1().unwrap() == "add");
-        assert!(parser.arg2() == None);
+        assert!(parser.arg2().is_none());
+    }
+
+    #[test]
+    fn test_parser_extended_commands() {
+        let mut parser = Parser::new(vec!["mul".to_string(), "xor".to_string()]);
+        parser.advance();
+        assert!(parser.commandType().is_err());
+
+        parser.set_extended(true);
+        assert!(parser.commandType().unwrap() == "C_ARITHMETIC");
+        assert!(parser.arg1().unwrap() == "mul");
+        parser.advance();
+        assert!(parser.arg1().unwrap() == "xor");
+    }
+
+    #[test]
+    fn test_parser_lexes_whole_words() {
+        let test_data = vec![
+            "\tpush\tconstant   7 // seven\r".to_string(),
+            "label LOOP// x".to_string(),
+            "  \t// indented comment\r".to_string(),
+            "\r".to_string(),
+            "if-goto LOOP\r".to_string(),
+            "pushy constant 1".to_string(),
+            "push constant 1 2".to_string(),
+        ];
+        let mut parser = Parser::new(test_data);
+        parser.advance();
+        assert!(parser.command() == Some(Command::Push("constant".to_string(), 7)));
+        parser.advance();
+        assert!(parser.command() == Some(Command::Label("LOOP".to_string())));
+        assert!(parser.peek_instruction() == Some("if-goto LOOP"));
+        parser.advance();
+        assert!(parser.command() == Some(Command::IfGoto("LOOP".to_string())));
+        parser.advance();
+        assert!(parser.commandType().is_err());
+        assert!(parser.command().is_none());
+        parser.advance();
+        assert!(parser.command().is_none());
+    }
+
+    #[test]
+    fn test_parser_end_of_input() {
+        for source in ["", "// only a comment", "\n\n", "add\n\n// trailing\n\n"] {
+            let lines: Vec<String> = source.lines().map(String::from).collect();
+            let mut parser = Parser::new(lines.clone());
+            let commands: Vec<_> = Parser::new(lines).collect();
+            assert!(commands.len() == usize::from(source.starts_with("add")));
+            while parser.hasMoreLines() {
+                parser.advance();
+            }
+            // advancing past the end is a no-op
+            parser.advance();
+            assert!(parser.next().is_none());
+        }
+
+        let mut parser = Parser::new(vec!["push constant 1".to_string(), "pushy".to_string()]);
+        assert!(parser.next() == Some(Ok(Command::Push("constant".to_string(), 1))));
+        let err = parser.next().unwrap().unwrap_err();
+        assert!(err.line == 2 && err.instruction == "pushy");
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn test_parser_rejects_negative_index() {
+        let mut parser = Parser::new(vec!["push constant -1".to_string()]);
+        let err = parser.next().unwrap().unwrap_err();
+        assert!(err.line == 1 && err.instruction == "push constant -1");
+        assert!(err.to_string() == "line 1: push index -1 is negative");
+    }
+
+    #[test]
+    fn test_parse_error_messages() {
+        let lines = [
+            "pushy constant 1",
+            "pop con",
+            "add 1",
+            "push constant x",
+            "call f 40000",
+        ];
+        let messages: Vec<_> = Parser::new(lines.iter().map(|l| l.to_string()).collect())
+            .map(|result| result.unwrap_err().message)
+            .collect();
+        assert!(
+            messages
+                == [
+                    "unknown command 'pushy constant 1'",
+                    "pop takes 2 arguments, not 1",
+                    "add takes 0 arguments, not 1",
+                    "push index 'x' is not a number",
+                    "call nArgs 40000 is above 32767"
+                ]
+        );
+    }
+
+    #[test]
+    fn test_parser_rejects_negative_count() {
+        let lines = vec!["function f -3".to_string(), "call f -1".to_string()];
+        let messages: Vec<_> = Parser::new(lines)
+            .map(|result| result.unwrap_err().message)
+            .collect();
+        assert!(messages == ["function nVars -3 is negative", "call nArgs -1 is negative"]);
+        let mut parser = Parser::new(vec!["function f 0".to_string()]);
+        assert!(parser.next() == Some(Ok(Command::Function("f".to_string(), 0))));
+    }
+
+    fn command_strategy() -> impl Strategy<Value = Command> {
+        let segment = prop::sample::select(vec![
+            "constant", "local", "argument", "this", "that", "pointer", "temp", "static",
+        ]);
+        let name = "[A-Za-z_][A-Za-z0-9_.$:]{0,8}";
+        prop_oneof![
+            (segment.clone(), 0..1000i16).prop_map(|(s, i)| Command::Push(s.to_string(), i)),
+            (segment, 0..1000i16).prop_map(|(s, i)| Command::Pop(s.to_string(), i)),
+            prop::sample::select(vec![
+                "add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not"
+            ])
+            .prop_map(|c| Command::Arithmetic(c.to_string())),
+            name.prop_map(Command::Label),
+            name.prop_map(Command::Goto),
+            name.prop_map(Command::IfGoto),
+            (name, 0..20i16).prop_map(|(n, i)| Command::Function(n, i)),
+            (name, 0..20i16).prop_map(|(n, i)| Command::Call(n, i)),
+            Just(Command::Return),
+        ]
+    }
+
+    proptest! {
+        // arbitrary input never panics and every instruction line gives one item
+        #[test]
+        fn fuzz_parser_never_panics(lines in prop::collection::vec(".{0,30}", 0..20)) {
+            let instructions = lines
+                .iter()
+                .filter(|line| !strip_comment(line).is_empty())
+                .count();
+            let results: Vec<_> = Parser::new(lines).collect();
+            prop_assert_eq!(results.len(), instructions);
+        }
+
+        // any spelling of a command with odd whitespace, comments and CRLF
+        // endings parses back to the same command
+        #[test]
+        fn fuzz_parser_round_trip(
+            lines in prop::collection::vec(
+                (command_strategy(), "[ \t]{0,2}", "[ \t]{1,3}", prop::option::of("[ a-z/]{0,10}"), any::<bool>()),
+                0..20,
+            )
+        ) {
+            let commands: Vec<Command> = lines.iter().map(|(c, ..)| c.clone()).collect();
+            let source: String = lines
+                .iter()
+                .map(|(command, indent, separator, comment, crlf)| {
+                    let mut line = indent.to_string();
+                    line.push_str(&command.to_string().replace(' ', separator));
+                    if let Some(comment) = comment {
+                        line.push_str(&format!("{}//{}", separator, comment));
+                    }
+                    line.push_str(if *crlf { "\r\n// between\r\n\r\n" } else { "\n\n" });
+                    line
+                })
+                .collect();
+            let lines: Vec<String> = source.lines().map(String::from).collect();
+            let parsed: Vec<Command> = Parser::new(lines).map(Result::unwrap).collect();
+            prop_assert_eq!(parsed, commands);
+        }
+    }
+
+    #[test]
+    fn test_parser_peek_instruction() {
+        let test_data = vec![
+            "call Main.fib 1".to_string(),
+            "// comment".to_string(),
+            "".to_string(),
+            "return".to_string(),
+        ];
+        let mut parser = Parser::new(test_data);
+        parser.advance();
+        assert!(parser.peek_instruction() == Some("return"));
+        assert!(parser.currentInstruction == "call Main.fib 1");
+
+        parser.advance();
+        assert!(parser.peek_instruction().is_none());
+    }
+
+    #[test]
+    fn test_inline_leaf_functions() {
+        let point = vec![
+            "function Point.getX 0",
+            "push argument 0",
+            "pop pointer 0",
+            "push this 0",
+            "return",
+            "function Point.loop 0",
+            "call Point.loop 0",
+            "return",
+        ];
+        let main = vec![
+            "function Main.main 0",
+            "push constant 2048",
+            "call Point.getX 1",
+            "call Point.loop 0",
+            "return",
+        ];
+        let parse = |lines: Vec<&str>| {
+            read_commands(Parser::new(lines.iter().map(|l| l.to_string()).collect())).unwrap()
+        };
+        let mut program = vec![
+            ("Point".to_string(), parse(point)),
+            ("Main".to_string(), parse(main)),
+        ];
+
+        let report = inline_leaf_functions(&mut program, DEFAULT_INLINE_THRESHOLD);
+        assert!(
+            report
+                == vec![Inlined {
+                    callee: "Point.getX".to_string(),
+                    caller: "Main.main".to_string(),
+                    call_sites: 1,
+                }]
+        );
+        let main = &program[1].1;
+        assert!(!main.contains(&Command::Call("Point.getX".to_string(), 1)));
+        assert!(main.contains(&Command::Call("Point.loop".to_string(), 0)));
+        assert!(main.contains(&Command::Push("temp".to_string(), 0)));
+        // THIS is restored after the inlined getter
+        assert!(main.contains(&Command::Pop("pointer".to_string(), 0)));
+    }
+
+    #[test]
+    fn test_inlined_program_runs_the_same() {
+        let sys = "function Sys.init 0\npush constant 7\npush constant 5\ncall Main.max 2\n\
+                   pop static 0\npush constant 3\ncall Main.double 1\npop static 1\n\
+                   push constant 2048\npush constant 42\ncall Main.setX 2\npop static 2\n\
+                   push constant 2048\ncall Main.getX 1\npop static 3\nlabel END\ngoto END\n";
+        let main = "function Main.max 0\npush argument 0\npush argument 1\ngt\n\
+                    if-goto FIRST\npush argument 1\nreturn\nlabel FIRST\npush argument 0\n\
+                    return\nfunction Main.double 1\npush argument 0\npop local 0\n\
+                    push local 0\npush local 0\nadd\nreturn\nfunction Main.setX 0\n\
+                    push argument 0\npop pointer 0\npush argument 1\npop this 0\n\
+                    push constant 0\nreturn\nfunction Main.getX 0\npush argument 0\n\
+                    pop pointer 0\npush this 0\nreturn\n";
+        let parse = |source: &str| {
+            read_commands(Parser::new(source.lines().map(String::from).collect())).unwrap()
+        };
+        let program = vec![
+            ("Sys".to_string(), parse(sys)),
+            ("Main".to_string(), parse(main)),
+        ];
+        let mut inlined = program.clone();
+        let report = inline_leaf_functions(&mut inlined, DEFAULT_INLINE_THRESHOLD);
+        assert!(report.len() == 4);
+
+        let run = |program: &[(String, Vec<Command>)]| {
+            let mut interpreter = Interpreter::new(program);
+            interpreter.bootstrap().unwrap();
+            assert!(interpreter.run(10_000).unwrap());
+            interpreter.ram
+        };
+        let (expected, actual) = (run(&program), run(&inlined));
+        assert!(expected[16..20] == [7, 6, 0, 42] && actual[16..20] == expected[16..20]);
+        assert!(actual[2048] == 42);
+    }
+
+    #[test]
+    fn test_analyze_call_graph() {
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect();
+        let sys = read_commands(Parser::new(lines(&[
+            "function Sys.init 0",
+            "push constant 6",
+            "call Main.fib 1",
+            "call Main.get 0",
+            "label END",
+            "goto END",
+        ])))
+        .unwrap();
+        let main = read_commands(Parser::new(lines(&[
+            "function Main.fib 0",
+            "push argument 0",
+            "call Main.fib 1",
+            "return",
+            "function Main.get 1",
+            "push static 3",
+            "push static 0",
+            "add",
+            "return",
+        ])))
+        .unwrap();
+        let analysis = analyze(&[("Sys".to_string(), sys), ("Main".to_string(), main)]);
+
+        assert!(analysis.recursive_groups == vec![vec!["Main.fib".to_string()]]);
+        assert!(analysis.entry == vec!["Sys.init".to_string()]);
+        // recursion reachable from Sys.init makes the stack unbounded
+        assert!(analysis.max_stack_depth.is_none());
+        let get = analysis
+            .functions
+            .iter()
+            .find(|f| f.name == "Main.get")
+            .unwrap();
+        assert!(get.max_stack_depth == Some(3));
+        assert!(get.callers == vec!["Sys.init".to_string()]);
+        assert!(analysis.statics[0].file == "Main");
+        assert!(analysis.statics[0].indices == vec![0, 3]);
+    }
+
+    #[test]
+    fn test_compile_jack() {
+        let source = "class Main {
+            field int x;
+            method int get(int i) {
+                var Array a;
+                let a[i] = x;
+                while (~(i = 0)) { let i = i - 1; }
+                return a[0];
+            }
+        }";
+        let commands = compile_jack("Main.jack", source).unwrap();
+        let vm: Vec<String> = commands.iter().map(|c| c.to_string()).collect();
+        let expected = [
+            "function Main.get 1",
+            "push argument 0",
+            "pop pointer 0",
+            "push local 0",
+            "push argument 1",
+            "add",
+            "push this 0",
+            "pop temp 0",
+            "pop pointer 1",
+            "push temp 0",
+            "pop that 0",
+            "label WHILE_EXP0",
+            "push argument 1",
+            "push constant 0",
+            "eq",
+            "not",
+            "not",
+            "if-goto WHILE_END0",
+            "push argument 1",
+            "push constant 1",
+            "sub",
+            "pop argument 1",
+            "goto WHILE_EXP0",
+            "label WHILE_END0",
+            "push local 0",
+            "push constant 0",
+            "add",
+            "pop pointer 1",
+            "push that 0",
+            "return",
+        ];
+        assert!(vm == expected);
+    }
+
+    #[test]
+    fn test_parse_filename() {
+        let args = vec!["hack_vm".to_string(), "Main.vm".to_string()];
+        assert!(parse_filename(&args) == Ok(&args[1]));
+        assert!(parse_filename(&args[..1]).is_err() && parse_filename(&[]).is_err());
+    }
+
+    #[test]
+    fn test_compile_jack_keeps_existing_vm() {
+        let dir = std::env::temp_dir().join(format!("hack_vm_jack_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let jack_file = dir.join("Main.jack");
+        let vm_file = dir.join("Main.vm");
+        std::fs::write(
+            &jack_file,
+            "class Main { function void main() { return; } }",
+        )
+        .unwrap();
+        std::fs::write(&vm_file, "function Main.main 0\n").unwrap();
+
+        let err = compile_jack_files(std::slice::from_ref(&jack_file), false).unwrap_err();
+        assert!(err.contains("already exists"));
+        assert!(std::fs::read_to_string(&vm_file).unwrap() == "function Main.main 0\n");
+
+        // once compiled it is replaced without asking
+        compile_jack_files(std::slice::from_ref(&jack_file), true).unwrap();
+        compile_jack_files(std::slice::from_ref(&jack_file), false).unwrap();
+        let vm = std::fs::read_to_string(&vm_file).unwrap();
+        assert!(vm.starts_with("// compiled from Main.jack\nfunction Main.main 0\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compile_jack_diagnostics() {
+        let err = compile_jack(
+            "Main.jack",
+            "class Main {\n  function void f() {\n    let y = 1;\n    return;\n  }\n}",
+        )
+        .unwrap_err();
+        assert!(err.to_string() == "Main.jack:3:9: undefined variable 'y'");
+
+        let err = compile_jack(
+            "Main.jack",
+            "class Main {\n  function void f() { return }\n}",
+        )
+        .unwrap_err();
+        assert!(err.line == 2 && err.column == 30);
+        assert!(err.message == "expected a term, found '}'");
+    }
+
+    #[test]
+    fn test_jack_xml() {
+        let tokens = tokenize(
+            "Main.jack",
+            "class Main { function void f() { do g(1 < 2, \"&\"); return; } }",
+        )
+        .unwrap();
+        let token_xml = tokens_xml(&tokens);
+        assert!(token_xml.starts_with("<tokens>\n<keyword> class </keyword>\n"));
+        assert!(token_xml.contains("<symbol> &lt; </symbol>\n"));
+        assert!(token_xml.contains("<stringConstant> &amp; </stringConstant>\n"));
+
+        let tree_xml = class_xml(&parse_class("Main.jack", &tokens).unwrap());
+        let expected = "    <parameterList>
+    </parameterList>
+    <symbol> ) </symbol>
+    <subroutineBody>
+      <symbol> { </symbol>
+      <statements>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> g </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+            <expression>
+              <term>
+                <integerConstant> 1 </integerConstant>
+              </term>
+              <symbol> &lt; </symbol>
+";
+        assert!(tree_xml.contains(expected));
+        assert!(tree_xml.ends_with("  <symbol> } </symbol>\n</class>\n"));
+    }
+
+    #[test]
+    fn test_labels_scoped_to_function() {
+        // both functions use LOOP, as the Jack compiler's WHILE_EXP0 labels do
+        let source = "function Sys.init 0\ncall Sys.count 0\ncall Sys.skip 0\nadd\n\
+                      pop static 0\nlabel LOOP\ngoto LOOP\n\
+                      function Sys.count 1\nlabel LOOP\npush local 0\npush constant 1\nadd\n\
+                      pop local 0\npush local 0\npush constant 3\nlt\nif-goto LOOP\n\
+                      push local 0\nreturn\n\
+                      function Sys.skip 0\npush constant 1\nif-goto LOOP\npush constant 10\n\
+                      return\nlabel LOOP\npush constant 20\nreturn\n";
+        let lines = source.lines().map(String::from).collect();
+        let program = vec![(
+            "Sys".to_string(),
+            read_commands(Parser::new(lines)).unwrap(),
+        )];
+        let asm = translate_to_string(&program, true, false).unwrap();
+        assert!(asm.contains("(Sys.count$LOOP)") && asm.contains("(Sys.skip$LOOP)"));
+
+        let mut emulator = Emulator::new(assemble("Sys.asm", &asm).unwrap().rom);
+        assert!(emulator.run(100_000));
+        assert!(emulator.ram[16] == 23);
+    }
+
+    #[test]
+    fn test_invalid_push_pop() {
+        let path = std::env::temp_dir().join("hack_vm_invalid_test.vm");
+        std::fs::write(&path, "push constant 1\npop constant 0\n").unwrap();
+        let err = read_program(std::slice::from_ref(&path), false).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.line == 2);
+        assert!(err.message == "cannot pop to constant in 'pop constant 0'");
+
+        let path = std::env::temp_dir().join("hack_vm_invalid_test.asm");
+        let mut writer = CodeWriter::new(VmFile::create(&path).unwrap(), false).unwrap();
+        let err = writer.write_pop("temp", 9).unwrap_err();
+        assert!(err.to_string() == "temp index 9 is out of range 0..7");
+        let err = writer.write_push("static", 0).unwrap_err();
+        assert!(err.to_string() == "static outside of a file");
+        assert!(writer.write_push("foo", 1).is_err());
+        assert!(writer.write_push("pointer", 2).is_err());
+    }
+
+    #[test]
+    fn test_disassemble_round_trip() {
+        let dir = PathBuf::from("test_files/FunctionCalls/StaticsTest");
+        let program = read_program(&files_with_extension(&dir, "vm"), false).unwrap();
+        let path = std::env::temp_dir().join("hack_vm_disasm_test.asm");
+        let file = VmFile::create(&path).unwrap();
+        compile_program(&program, CodeWriter::new(file, false).unwrap(), false).unwrap();
+
+        let asm = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // statics tell the files apart
+        assert!(disassemble("StaticsTest.asm", &asm).unwrap() == program);
+
+        // gt and lt as the translator wrote them before comparing signs
+        let operands = "@SP\nM=M-1\n@SP\nA=M\nD=M\n@SP\nA=M-1\n";
+        let compare = "@TRUE_0\nD;JGT\n@SP\nM=M-1\nA=M\nM=0\n@CONTINUE_0\n0;JMP\n(TRUE_0)\n\
+                       @SP\nM=M-1\nA=M\nM=-1\n(CONTINUE_0)\n@SP\nM=M+1\n";
+        let asm = format!("{0}D=M-D\n{1}{0}D=D-M\n{1}", operands, compare);
+        let expected = vec![
+            Command::Arithmetic("gt".to_string()),
+            Command::Arithmetic("lt".to_string()),
+        ];
+        assert!(disassemble("Old.asm", &asm).unwrap() == vec![("Old".to_string(), expected)]);
+
+        // written by an older version, with `@Sys.init` as the function entry
+        let dir = PathBuf::from("test_files/FunctionCalls/FibonacciElement");
+        let asm = std::fs::read_to_string(dir.join("Sys.asm")).unwrap();
+        let sys = read_program(&[dir.join("Sys.vm")], false).unwrap();
+        assert!(disassemble("Sys.asm", &asm).unwrap() == sys);
+
+        let err = disassemble("Bad.asm", "@SP\nM=M+1\n").unwrap_err();
+        assert!(err.line == 1);
+    }
+
+    #[test]
+    fn test_format_vm() {
+        let source = "\n// header\n\n\nfunction   Main.f 0\n  push constant 1 //one\n\
+                      add   // sum\n// done\n  return\n\n";
+        let expected = "// header\n\nfunction Main.f 0\n    push constant 1 // one\n    \
+                        add             // sum\n    // done\n    return\n";
+        let formatted = format_vm("Main.vm", source, false).unwrap();
+        assert!(formatted == expected);
+        assert!(format_vm("Main.vm", &formatted, false).unwrap() == formatted);
+        assert!(
+            format_vm("Main.vm", "push constant 1\nfoo\n", false)
+                .unwrap_err()
+                .line
+                == 2
+        );
+    }
+
+    #[test]
+    fn test_lint() {
+        let source = "function Main.main 1\npush constant 3\ncall Main.f 1\npop local 1\n\
+                      label UNUSED\nreturn\npush constant 1\nfunction Main.f 0\n\
+                      push argument 1\nreturn\n";
+        let commands = read_numbered_commands("Main.vm", source, false).unwrap();
+        let lints = lint(&[("Main.vm".to_string(), commands)]);
+        let found: Vec<(&str, usize)> = lints
+            .iter()
+            .map(|lint| (lint.rule, lint.diagnostic.line))
+            .collect();
+        assert!(
+            found
+                == vec![
+                    ("nvars-mismatch", 1),
+                    ("unused-label", 5),
+                    ("unreachable-code", 7),
+                    ("argument-out-of-range", 9),
+                ]
+        );
+    }
+
+    #[test]
+    fn test_emulator() {
+        let program = assemble(
+            "Add.asm",
+            "@2\nD=A\n@3\nD=D+A\n@R0\nM=D\n(END)\n@END\n0; JMP\n",
+        );
+        let program = program.unwrap();
+        assert!(program.rom[..4] == [2, 0xEC10, 3, 0xE090]);
+        assert!(program.symbols["END"] == 6);
+        let mut emulator = Emulator::new(program.rom);
+        assert!(emulator.run(100));
+        assert!(emulator.ram[0] == 5);
+        assert!(emulator.is_halted() && emulator.cycles == 7);
+        assert!(assemble("Bad.asm", "@1\nD=Q\n").unwrap_err().line == 2);
+
+        // run() goes through the op table in batches; it stops on the same
+        // cycle as single steps, including a budget ending mid-loop
+        let source = "@5\nD=A\n@i\nM=D\n(LOOP)\n@i\nMD=M-1\n@SKIP\nD;JEQ\n@LOOP\n0;JMP\n\
+                      (SKIP)\n@i\nD=!M\nM=-D\n(END)\n@END\n0;JMP\n";
+        let rom = assemble("Loop.asm", source).unwrap().rom;
+        let mut stepped = Emulator::new(rom.clone());
+        while !stepped.is_halted() {
+            stepped.step();
+        }
+        let mut batched = Emulator::new(rom.clone());
+        assert!(!batched.run(20) && batched.cycles == 20);
+        assert!(batched.run(1000) && batched.cycles == stepped.cycles);
+        assert!((batched.a, batched.d, batched.pc) == (stepped.a, stepped.d, stepped.pc));
+        assert!(batched.ram[16] == 1 && stepped.ram[16] == 1);
+
+        // a jump to itself halts, one with a side effect doesn't
+        let mut emulator = Emulator::new(assemble("Self.asm", "@2\nD=A\n0;JMP\n").unwrap().rom);
+        assert!(emulator.run(100) && emulator.pc == 2 && emulator.cycles == 2);
+        let source = "@2\nD=A\nM=M+1;JMP\n";
+        let mut emulator = Emulator::new(assemble("Count.asm", source).unwrap().rom);
+        assert!(!emulator.run(100) && emulator.ram[2] == 98);
+
+        // the OS's `while (true) {}` only halts with the no-progress check
+        let source = "function Sys.init 0\ncall Sys.halt 0\nfunction Sys.halt 0\n\
+                      label WHILE\npush constant 0\nnot\nif-goto WHILE\npush constant 0\nreturn\n";
+        let commands = read_numbered_commands("Sys.vm", source, false).unwrap();
+        let commands = commands.into_iter().map(|(_, command)| command).collect();
+        let asm = translate_to_string(&[("Sys".to_string(), commands)], true, false).unwrap();
+        let program = assemble("Sys.asm", &asm).unwrap();
+        let mut emulator = Emulator::new(program.rom.clone());
+        assert!(!emulator.run(10_000));
+        emulator.set_no_progress(Some(1000));
+        assert!(emulator.run(10_000) && emulator.is_halted() && emulator.is_stuck());
+        assert!(tui::current_function(&program, emulator.pc) == Some("Sys.halt"));
+    }
+
+    #[test]
+    fn test_interpreter() {
+        let source = "function Sys.init 0\npush constant 20000\npush constant 20000\nneg\n\
+                      gt\npop static 0\nlabel END\ngoto END\n";
+        let commands = read_numbered_commands("Sys.vm", source, false).unwrap();
+        let commands = commands.into_iter().map(|(_, command)| command).collect();
+        let mut interpreter = Interpreter::new(&[("Sys".to_string(), commands)]);
+        interpreter.bootstrap().unwrap();
+        assert!(interpreter.run(100).unwrap());
+        assert!(interpreter.ram[16] == -1);
+        assert!(interpreter.ram[0] == 261);
+    }
+
+    #[test]
+    fn test_script() {
+        let source = "// adds 2 and 3\nload Add.asm,\ncompare-to Add.cmp,\n\
+                      output-list RAM[0]%D1.6.1 RAM[1]%D2.4.2;\n\
+                      set RAM[1] -7,\nrepeat 10 {\n  ticktock;\n}\noutput;\n";
+        let script = TestScript::parse(source).unwrap();
+        assert!(script.load.as_deref() == Some("Add.asm"));
+        let program = assemble("Add.asm", "@2\nD=A\n@3\nD=D+A\n@R0\nM=D\n").unwrap();
+        let output = script.run(&mut Emulator::new(program.rom));
+        assert!(output == vec!["| RAM[0] | RAM[1] |", "|      5 |    -7  |"]);
+        assert!(compare_output(&output, "| RAM[0] | RAM[1] |\n|   5 |  * |\n").is_ok());
+        assert!(
+            compare_output(&output, "|RAM[0]|RAM[1]|\n|6|-7|\n")
+                .unwrap_err()
+                .line
+                == 2
+        );
+        let error = script
+            .compare(&output, "|RAM[0]|RAM[1]|\n|6|-7|\n")
+            .unwrap_err();
+        let table: Vec<&str> = error.message.lines().collect();
+        assert!(
+            error.line == 2
+                && table[0] == "output row 1 is the first to differ (1 of 1 rows differ)"
+        );
+        assert!(table[1] == "    column  address  expected  actual");
+        assert!(table[2] == "    RAM[0]        0         6       5  <");
+        assert!(table[3] == "    RAM[1]        1        -7      -7");
+        assert!(script.compare(&output, "|RAM[0]|\n|5|\n").unwrap_err().line == 1);
+        assert!(
+            TestScript::parse("set RAM[0] 1,\nset PC 0;\n")
+                .unwrap_err()
+                .line
+                == 2
+        );
+    }
+
+    #[test]
+    fn test_lsp() {
+        let mut server = Server::new();
+        let uri = "file:///nonexistent/Main.vm";
+        let text = "function Main.main 0\ncall Main.f 0\nlabel L\ngoto L\n\
+                    function Main.f 0\npush constant 1\nreturn\npop con\n";
+        let open = json!({"method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": uri, "text": text}}});
+        let replies = server.handle(&open);
+        let diagnostics = &replies[0]["params"]["diagnostics"];
+        assert!(diagnostics[0]["range"]["start"]["line"] == 7);
+
+        let request = |method: &str, line: usize, character: usize| {
+            json!({"id": 1, "method": method, "params": {"textDocument": {"uri": uri},
+                "position": {"line": line, "character": character}}})
+        };
+        let definition = &server.handle(&request("textDocument/definition", 1, 7))[0]["result"];
+        assert!(definition["range"]["start"] == json!({"line": 4, "character": 9}));
+        let label = &server.handle(&request("textDocument/definition", 3, 5))[0]["result"];
+        assert!(label["range"]["start"]["line"] == 2);
+        let hover = &server.handle(&request("textDocument/hover", 4, 10))[0]["result"];
+        assert!(hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .ends_with("0 local variables, called from Main.main"));
+        let symbols = &server.handle(&request("textDocument/documentSymbol", 0, 0))[0]["result"];
+        assert!(symbols[1]["name"] == "Main.f" && symbols[1]["range"]["end"]["line"] == 7);
+        let completion = &server.handle(&request("textDocument/completion", 7, 7))[0]["result"];
+        assert!(completion.as_array().unwrap().is_empty());
+        let completion = &server.handle(&request("textDocument/completion", 7, 4))[0]["result"];
+        assert!(completion.as_array().unwrap().len() == 7);
+
+        assert!(server.handle(&json!({"id": 2, "method": "shutdown"})).len() == 1);
+        server.handle(&json!({"method": "exit"}));
+        assert!(server.exit_code() == Some(0));
+    }
+
+    #[test]
+    fn test_lsp_validates_segments() {
+        let mut server = Server::new();
+        let uri = "file:///nonexistent/Bad.vm";
+        let text = "push foo 1\npop constant 0\npush temp 9\npush pointer 2 // é😀\n";
+        let open = json!({"method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": uri, "text": text}}});
+        let replies = server.handle(&open);
+        let diagnostics = replies[0]["params"]["diagnostics"].as_array().unwrap();
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic["severity"] == 1)
+            .map(|diagnostic| diagnostic["message"].as_str().unwrap())
+            .collect();
+        assert!(
+            messages
+                == vec![
+                    "unknown segment 'foo'",
+                    "cannot pop to constant",
+                    "temp index 9 is out of range 0..7",
+                    "pointer index 2 is out of range 0..1"
+                ]
+        );
+        // columns count UTF-16 code units, the emoji is two of them
+        assert!(diagnostics[3]["range"]["end"]["character"] == 21);
+    }
+
+    #[test]
+    fn test_screen() {
+        let mut ram = vec![0; RAM_SIZE];
+        // pixels 0 and 9 of the first row, the last pixel of the screen
+        ram[SCREEN] = 0b10_0000_0001;
+        ram[KBD - 1] = i16::MIN;
+        assert!(pixel(&ram, 0, 0) && pixel(&ram, 9, 0) && !pixel(&ram, 1, 0));
+        assert!(pixel(&ram, WIDTH - 1, HEIGHT - 1));
+        let pbm = to_pbm(&ram);
+        assert!(pbm.starts_with(b"P4\n512 256\n") && pbm[11..13] == [0x80, 0x40]);
+        assert!(pbm.len() == 11 + WIDTH / 8 * HEIGHT && pbm.last() == Some(&1));
+        assert!(to_png(&ram).starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        assert!(key_code("a") == Some(97) && key_code("5") == Some(53));
+        assert!(key_code("Left") == Some(130) && key_code("esc") == Some(140));
+        assert!(key_code("F12") == Some(152) && key_code("space") == Some(32));
+        assert!(key_code("F13").is_none() && key_code("nope").is_none());
+        let events = parse_key_script("// keys\n20 release\n10 up\n30 screenshot\n").unwrap();
+        assert!(
+            events
+                == vec![
+                    (10, KeyAction::Press(131)),
+                    (20, KeyAction::Release),
+                    (30, KeyAction::Screenshot)
+                ]
+        );
+        assert!(parse_key_script("10\n").unwrap_err().starts_with("line 1"));
+    }
+
+    #[test]
+    fn test_tui() {
+        let mut ram = vec![0; RAM_SIZE];
+        // a 2x2 square in the top left corner and pixel (0, 3)
+        ram[SCREEN] = 0b11;
+        ram[SCREEN + 32] = 0b11;
+        ram[SCREEN + 96] = 0b1;
+        let braille = tui::render(&ram, Charset::Braille, 1);
+        assert!(braille.len() == 64 && braille[0].chars().count() == 256);
+        assert!(braille[0].starts_with('\u{285B}'));
+        let blocks = tui::render(&ram, Charset::Blocks, 2);
+        assert!(blocks.len() == 64 && blocks[0].starts_with("\u{2588} "));
+        assert!(tui::fit_scale(Charset::Braille, 140, 39) == 2);
+        assert!(tui::fit_scale(Charset::Blocks, 80, 24) == 7);
+        assert!(tui::key_code(KeyCode::Right) == Some(132));
+        assert!(tui::key_code(KeyCode::F(1)) == Some(141));
+
+        let source = "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n\
+                      function Main.main 0\nlabel LOOP\ngoto LOOP\n";
+        let commands = read_numbered_commands("Main.vm", source, false).unwrap();
+        let commands = commands.into_iter().map(|(_, command)| command).collect();
+        let asm = translate_to_string(&[("Main".to_string(), commands)], true, false).unwrap();
+        let program = assemble("Main.asm", &asm).unwrap();
+        let mut emulator = Emulator::new(program.rom.clone());
+        emulator.run(1000);
+        assert!(tui::current_function(&program, emulator.pc) == Some("Main.main"));
+        assert!(tui::current_function(&program, 0).is_none());
+    }
+
+    #[test]
+    fn test_trace() {
+        let source = "@2\nD=A\n@3\nD=D+A\n@0\nM=D\nAM=M-1;JGT\n0;JMP\n";
+        let program = assemble("Add.asm", source).unwrap();
+        let texts: Vec<String> = program
+            .rom
+            .iter()
+            .map(|word| instruction_text(*word))
+            .collect();
+        assert!(texts.join("\n") + "\n" == source);
+
+        let mut out = Vec::new();
+        let mut emulator = Emulator::new(program.rom.clone());
+        let finished = run_traced(
+            &mut emulator,
+            &program,
+            6,
+            TraceMode::Instructions,
+            &mut out,
+        )
+        .unwrap();
+        let trace = String::from_utf8(out).unwrap();
+        assert!(!finished && trace.lines().count() == 6);
+        assert!(trace.lines().nth(3) == Some("4 3 D=D+A A=3 D=5"));
+        assert!(trace.lines().nth(5) == Some("6 5 M=D A=0 D=5 RAM[0]=5"));
+        assert!(run_traced(&mut emulator, &program, 100, TraceMode::Vm, &mut Vec::new()).is_err());
+
+        let source = "function Sys.init 0\npush constant 7\npop temp 0\nlabel END\ngoto END\n";
+        let commands = read_numbered_commands("Sys.vm", source, false).unwrap();
+        let commands = commands.into_iter().map(|(_, command)| command).collect();
+        let asm = translate_to_string(&[("Sys".to_string(), commands)], true, false).unwrap();
+        let program = assemble("Sys.asm", &asm).unwrap();
+        assert!(program.source_map.len() == 5);
+        let mut out = Vec::new();
+        let mut emulator = Emulator::new(program.rom.clone());
+        run_traced(&mut emulator, &program, 1000, TraceMode::Vm, &mut out).unwrap();
+        let trace = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert!(lines.len() == 5 && lines[0].starts_with("1 Sys: function Sys.init 0 SP=261"));
+        assert!(lines[1] == "2 Sys: push constant 7 SP=261 LCL=261 ARG=256 THIS=0 THAT=0");
+        assert!(lines[2] == "3 Sys: pop temp 0 SP=262 LCL=261 ARG=256 THIS=0 THAT=0");
+        assert!(lines[3].starts_with("4 Sys: label END SP=261"));
+    }
+
+    #[test]
+    fn test_ram_file() {
+        let text = "// a frame\nargument[0..3] = 7 8 -1\nARG = 400\nR13 = 65535\n\
+                    RAM[16384..16386] = -1\nlocal[1] = 5\nLCL = 300\n";
+        let mut ram = vec![0; RAM_SIZE];
+        ram_file::load(text, &mut ram).unwrap();
+        assert!(ram[400..403] == [7, 8, -1] && ram[301] == 5 && ram[13] == -1);
+        assert!(ram[16384..16387] == [-1, -1, 0]);
+        let json = r#"{"ARG": 400, "LCL": 300, "argument[0..3]": [7, 8, -1], "local[1]": 5,
+                       "R13": -1, "SCREEN[0..2]": -1}"#;
+        let mut from_json = vec![0; RAM_SIZE];
+        ram_file::load(json, &mut from_json).unwrap();
+        assert!(from_json == ram);
+
+        // dumps read back to the same RAM
+        for dump in [
+            ram_file::dump_text(&ram, &[]),
+            ram_file::dump_json(&ram, &[]),
+        ] {
+            let mut loaded = vec![0; RAM_SIZE];
+            ram_file::load(&dump, &mut loaded).unwrap();
+            assert!(loaded == ram);
+        }
+        let ranges = ram_file::parse_ranges("2..3,400..402").unwrap();
+        assert!(ram_file::dump_text(&ram, &ranges) == "RAM[2] = 400\nRAM[400..402] = 7 8\n");
+
+        let error = |source: &str| ram_file::load(source, &mut vec![0; RAM_SIZE]).unwrap_err();
+        assert!(error("SP = 256\nfoo[2] = 1\n") == "line 2: unknown target 'foo'");
+        assert!(error("RAM[0..3] = 1 2\n") == "line 1: 2 values for 3 cells");
+        assert!(error("RAM[32767..32769] = 0\n") == "RAM[32767..32769] is outside RAM");
+        assert!(error(r#"{"temp[0]": 70000}"#) == "'temp[0]': invalid value '70000'");
+
+        // the interpreter takes the same files
+        let source = "push argument 1\npush local 0\nadd\npop temp 0\n";
+        let commands = read_numbered_commands("Test.vm", source, false).unwrap();
+        let commands = commands.into_iter().map(|(_, command)| command).collect();
+        let mut interpreter = Interpreter::new(&[("Test".to_string(), commands)]);
+        ram_file::load(
+            "SP = 256\nLCL = 300\nARG = 400\nlocal[0] = 2\nargument[1] = 3\n",
+            &mut interpreter.ram,
+        )
+        .unwrap();
+        assert!(interpreter.run(100).unwrap() && interpreter.ram[5] == 5);
+    }
+
+    // writes each call it gets as a line, to check what the driver sends
+    struct Recorder<'a> {
+        lines: &'a mut Vec<String>,
+    }
+
+    impl Backend for Recorder<'_> {
+        fn set_file_name(&mut self, filename: &str) {
+            self.lines.push(format!("file {}", filename));
+        }
+        fn init_stack(&mut self) -> std::io::Result<()> {
+            self.lines.push("init".to_string());
+            Ok(())
+        }
+        fn write_push(&mut self, segment: &str, index: i16) -> std::io::Result<()> {
+            self.lines.push(format!("push {} {}", segment, index));
+            Ok(())
+        }
+        fn write_pop(&mut self, segment: &str, index: i16) -> std::io::Result<()> {
+            self.lines.push(format!("pop {} {}", segment, index));
+            Ok(())
+        }
+        fn write_arithmetic(&mut self, command: &str) -> std::io::Result<()> {
+            self.lines.push(command.to_string());
+            Ok(())
+        }
+        fn write_label(&mut self, label: &str) -> std::io::Result<()> {
+            self.lines.push(format!("label {}", label));
+            Ok(())
+        }
+        fn write_goto(&mut self, label: &str) -> std::io::Result<()> {
+            self.lines.push(format!("goto {}", label));
+            Ok(())
+        }
+        fn write_ifgoto(&mut self, label: &str) -> std::io::Result<()> {
+            self.lines.push(format!("if-goto {}", label));
+            Ok(())
+        }
+        fn write_function(&mut self, function_name: &str, nvars: i16) -> std::io::Result<()> {
+            self.lines
+                .push(format!("function {} {}", function_name, nvars));
+            Ok(())
+        }
+        fn write_call(&mut self, function_name: &str, nargs: i16) -> std::io::Result<()> {
+            self.lines.push(format!("call {} {}", function_name, nargs));
+            Ok(())
+        }
+        fn write_return(&mut self) -> std::io::Result<()> {
+            self.lines.push("return".to_string());
+            Ok(())
+        }
+        fn close(self) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn tail_calls(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_backend() {
+        let main = "function Main.f 1\npush argument 0\nneg\npop local 0\ncall Main.f 1\nreturn\n";
+        let program: Vec<(String, Vec<Command>)> =
+            [("Main", main), ("Sys", "label END\ngoto END\n")]
+                .iter()
+                .map(|(file, source)| {
+                    let commands = read_numbered_commands(file, source, false).unwrap();
+                    (
+                        file.to_string(),
+                        commands.into_iter().map(|(_, command)| command).collect(),
+                    )
+                })
+                .collect();
+        let mut lines = Vec::new();
+        compile_program(&program, Recorder { lines: &mut lines }, true).unwrap();
+        assert!(lines[..2] == ["init", "file Main"]);
+        assert!(lines[2..6] == ["function Main.f 1", "push argument 0", "neg", "pop local 0"]);
+        // the tail call falls back to the default, a call then a return
+        assert!(
+            lines[6..]
+                == [
+                    "call Main.f 1",
+                    "return",
+                    "file Sys",
+                    "label END",
+                    "goto END"
+                ]
+        );
+    }
+
+    // recursion and the extended commands, run by the backend tests next to
+    // the interpreter
+    fn backend_test_program() -> Vec<(String, Vec<Command>)> {
+        let sys = "function Sys.init 0\npush constant 20\ncall Main.fib 1\npop static 0\n\
+                   push constant 7\nneg\npush constant 2\ndiv\npop static 1\n\
+                   push constant 7\nneg\npush constant 2\nmod\npop static 2\n\
+                   push constant 300\npush constant 300\nmul\npop static 3\n\
+                   push constant 1\nneg\npush constant 3\nshr\npop static 4\n\
+                   push constant 1\npush constant 15\nshl\npop static 5\nlabel END\ngoto END\n";
+        let main = "function Main.fib 0\npush argument 0\npush constant 2\nlt\nif-goto BASE\n\
+                    push argument 0\npush constant 1\nsub\ncall Main.fib 1\n\
+                    push argument 0\npush constant 2\nsub\ncall Main.fib 1\nadd\nreturn\n\
+                    label BASE\npush argument 0\nreturn\n";
+        [("Sys", sys), ("Main", main)]
+            .iter()
+            .map(|(file, source)| {
+                let commands = read_numbered_commands(file, source, true).unwrap();
+                (
+                    file.to_string(),
+                    commands.into_iter().map(|(_, command)| command).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_disassemble_extended_and_tail_calls() {
+        let mut program = backend_test_program();
+        program[1].1.extend([
+            Command::Function("Main.tail".to_string(), 0),
+            Command::Push("argument".to_string(), 0),
+            Command::Call("Main.fib".to_string(), 1),
+            Command::Return,
+        ]);
+        let path = std::env::temp_dir().join("hack_vm_disasm_extended_test.asm");
+        for source_map in [false, true] {
+            let mut code_writer = CodeWriter::new(VmFile::create(&path).unwrap(), false).unwrap();
+            code_writer.set_extended(true);
+            code_writer.set_tail_calls(true);
+            code_writer.set_source_map(source_map);
+            compile_program(&program, code_writer, false).unwrap();
+            let asm = std::fs::read_to_string(&path).unwrap();
+            assert!(asm.contains("TAIL_COPY_") && asm.contains("//@") == source_map);
+            assert!(disassemble("Prog.asm", &asm).unwrap() == program);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tail_call_max_nargs() {
+        let program = [(
+            "Main".to_string(),
+            vec![
+                Command::Function("Main.f".to_string(), 0),
+                Command::Call("Main.f".to_string(), i16::MAX),
+                Command::Return,
+            ],
+        )];
+        let path = std::env::temp_dir().join("hack_vm_tail_call_max_test.asm");
+        let mut code_writer = CodeWriter::new(VmFile::create(&path).unwrap(), false).unwrap();
+        code_writer.set_tail_calls(true);
+        compile_program(&program, code_writer, false).unwrap();
+        let asm = std::fs::read_to_string(&path).unwrap();
+        assert!(asm.contains("TAIL_COPY_") && asm.contains("@32772"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_c_writer() {
+        let program = backend_test_program();
+        let dir = std::env::temp_dir();
+        let source = dir.join("hack_vm_c_writer_test.c");
+        let mut c_writer = CWriter::new(VmFile::create(&source).unwrap(), false).unwrap();
+        c_writer.set_extended(true);
+        compile_program(&program, c_writer, false).unwrap();
+
+        // undefined functions are caught before anything is written
+        let missing = [(
+            "Sys".to_string(),
+            vec![Command::Call("Main.g".to_string(), 0)],
+        )];
+        let c_writer = CWriter::new(VmFile::create(&source).unwrap(), false).unwrap();
+        assert!(compile_program(&missing, c_writer, false).is_err());
+
+        // the rest needs a C compiler
+        let binary = dir.join("hack_vm_c_writer_test");
+        let compiled = std::process::Command::new("cc")
+            .args(["-std=c99", "-O1", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .status();
+        if !compiled.is_ok_and(|status| status.success()) {
+            return;
+        }
+        let mut interpreter = Interpreter::new(&program);
+        interpreter.bootstrap().unwrap();
+        assert!(interpreter.run(1_000_000).unwrap());
+        let output = std::process::Command::new(&binary).output().unwrap();
+        assert!(output.status.success());
+        let report = format!("halted after {} commands\n", interpreter.steps);
+        assert!(String::from_utf8_lossy(&output.stderr) == report);
+        let mut ram = vec![0; RAM_SIZE];
+        ram_file::load(&String::from_utf8_lossy(&output.stdout), &mut ram).unwrap();
+        assert!(ram[16..22] == [6765, -3, -1, 24464, -1, -32768]);
+        // return addresses differ, the rest of RAM doesn't
+        assert!(ram[..16] == interpreter.ram[..16] && ram[22..256] == interpreter.ram[22..256]);
+
+        // stopped after a number of commands
+        let output = std::process::Command::new(&binary)
+            .arg("100")
+            .output()
+            .unwrap();
+        assert!(output.status.code() == Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr) == "stopped after 100 commands\n");
+        _ = std::fs::remove_file(&source);
+        _ = std::fs::remove_file(&binary);
+    }
+
+    #[test]
+    fn test_wat_writer() {
+        let path = std::env::temp_dir().join("hack_vm_wat_writer_test.wat");
+        let translate = |program: &[(String, Vec<Command>)], is_test: bool| {
+            let mut wat_writer = WatWriter::new(VmFile::create(&path).unwrap(), is_test).unwrap();
+            wat_writer.set_extended(true);
+            compile_program(program, wat_writer, is_test)?;
+            std::fs::read_to_string(&path)
+        };
+        let engine = wasmi::Engine::default();
+        let mut store = wasmi::Store::new(&engine, ());
+        let instantiate = |store: &mut wasmi::Store<()>, text: &str| {
+            let module = wasmi::Module::new(&engine, &wat::parse_str(text).unwrap()[..]).unwrap();
+            let instance = wasmi::Linker::new(&engine)
+                .instantiate(&mut *store, &module)
+                .unwrap()
+                .start(&mut *store)
+                .unwrap();
+            let run = instance.get_typed_func::<i64, i32>(&*store, "run").unwrap();
+            let steps = instance
+                .get_typed_func::<(), i64>(&*store, "steps")
+                .unwrap();
+            let memory = instance.get_memory(&*store, "memory").unwrap();
+            (run, steps, memory)
+        };
+        let ram = |memory: &wasmi::Memory, store: &wasmi::Store<()>| -> Vec<i16> {
+            let data = memory.data(store);
+            data.chunks(2)
+                .map(|word| i16::from_le_bytes([word[0], word[1]]))
+                .collect()
+        };
+
+        let program = backend_test_program();
+        let (run, steps, memory) = instantiate(&mut store, &translate(&program, false).unwrap());
+        let mut interpreter = Interpreter::new(&program);
+        interpreter.bootstrap().unwrap();
+        assert!(interpreter.run(1_000_000).unwrap());
+        assert!(run.call(&mut store, -1).unwrap() == 1);
+        assert!(steps.call(&mut store, ()).unwrap() == interpreter.steps as i64);
+        // frames hold 0 for the return address as in the interpreter, so all
+        // of RAM matches
+        let after = ram(&memory, &store);
+        assert!(after[16..22] == [6765, -3, -1, 24464, -1, -32768]);
+        assert!(after == interpreter.ram);
+        assert!(run.call(&mut store, 100).unwrap() == 2);
+        assert!(steps.call(&mut store, ()).unwrap() == 100);
+
+        // test programs start at the first command with the test pointers,
+        // loop back, jump forward and run off the end
+        let source = "push constant 0\npop local 0\nlabel LOOP\npush local 0\npush constant 1\n\
+                      add\npop local 0\npush local 0\npush constant 10\nlt\nif-goto LOOP\n\
+                      push local 0\npush constant 10\neq\nif-goto DONE\npush constant 99\n\
+                      pop temp 0\nlabel DONE\n";
+        let commands = read_numbered_commands("Test.vm", source, false).unwrap();
+        let commands = commands.into_iter().map(|(_, command)| command).collect();
+        let program = [("Test".to_string(), commands)];
+        let (run, steps, memory) = instantiate(&mut store, &translate(&program, true).unwrap());
+        let mut interpreter = Interpreter::new(&program);
+        interpreter.ram[..5].copy_from_slice(&[256, 456, 756, 1056, 1356]);
+        assert!(interpreter.run(1000).unwrap());
+        assert!(run.call(&mut store, -1).unwrap() == 1);
+        assert!(steps.call(&mut store, ()).unwrap() == interpreter.steps as i64);
+        let after = ram(&memory, &store);
+        assert!(after[456] == 10 && after[5] == 0 && after == interpreter.ram);
+
+        let jump = [(
+            "Test".to_string(),
+            vec![Command::Goto("NOWHERE".to_string())],
+        )];
+        assert!(translate(&jump, true).is_err());
+        _ = std::fs::remove_file(&path);
     }
 
     #[test]
@@ -0,0 +1,159 @@
+// `hack_vm lint`: warnings about VM code that is valid but most likely wrong
+use crate::analysis::function_spans;
+use crate::diagnostics::Diagnostic;
+use crate::parser::Command;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Lint {
+    // short kebab-case name of the check
+    pub rule: &'static str,
+    #[serde(flatten)]
+    pub diagnostic: Diagnostic,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]", self.diagnostic, self.rule)
+    }
+}
+
+// a function's commands with the line each one is on
+struct Function<'a> {
+    file: &'a str,
+    name: &'a str,
+    line: usize,
+    nvars: i16,
+    commands: &'a [(usize, Command)],
+}
+
+fn functions(files: &[(String, Vec<(usize, Command)>)]) -> Vec<Function<'_>> {
+    let mut functions = Vec::new();
+    for (file, commands) in files {
+        for (start, end) in function_spans(commands.iter().map(|(_, command)| command)) {
+            if let (line, Command::Function(name, nvars)) = &commands[start] {
+                functions.push(Function {
+                    file,
+                    name,
+                    line: *line,
+                    nvars: *nvars,
+                    commands: &commands[start + 1..end],
+                });
+            }
+        }
+    }
+    functions
+}
+
+// lint a whole program, `files` holds each file's commands with their lines
+pub fn lint(files: &[(String, Vec<(usize, Command)>)]) -> Vec<Lint> {
+    let functions = functions(files);
+
+    // the fewest arguments any call site passes to each function
+    let mut min_nargs: HashMap<&str, i16> = HashMap::new();
+    for (_, commands) in files {
+        for (_, command) in commands {
+            if let Command::Call(name, nargs) = command {
+                let min = min_nargs.entry(name).or_insert(*nargs);
+                *min = (*min).min(*nargs);
+            }
+        }
+    }
+
+    let mut lints = Vec::new();
+    for function in &functions {
+        let mut warn = |rule, line, message: String| {
+            lints.push(Lint {
+                rule,
+                diagnostic: Diagnostic::new(function.file, line, 1, &message),
+            })
+        };
+
+        // code after a return is dead until the next label
+        let mut after_return = false;
+        for (line, command) in function.commands {
+            match command {
+                Command::Return => after_return = true,
+                Command::Label(_) => after_return = false,
+                _ if after_return => {
+                    warn(
+                        "unreachable-code",
+                        *line,
+                        format!("'{}' is unreachable after return", command),
+                    );
+                    after_return = false;
+                }
+                _ => {}
+            }
+        }
+
+        let targets: HashSet<&str> = function
+            .commands
+            .iter()
+            .filter_map(|(_, command)| match command {
+                Command::Goto(label) | Command::IfGoto(label) => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+        for (line, command) in function.commands {
+            if let Command::Label(label) = command {
+                if !targets.contains(label.as_str()) {
+                    warn(
+                        "unused-label",
+                        *line,
+                        format!("label {} is never jumped to", label),
+                    );
+                }
+            }
+        }
+
+        let locals = function
+            .commands
+            .iter()
+            .filter_map(|(_, command)| match command {
+                Command::Push(segment, index) | Command::Pop(segment, index)
+                    if segment == "local" =>
+                {
+                    Some(*index)
+                }
+                _ => None,
+            });
+        let used = locals.max().map_or(0, |index| index + 1);
+        if used != function.nvars {
+            warn(
+                "nvars-mismatch",
+                function.line,
+                format!(
+                    "{} declares {} locals but uses {}",
+                    function.name, function.nvars, used
+                ),
+            );
+        }
+
+        if let Some(&nargs) = min_nargs.get(function.name) {
+            for (line, command) in function.commands {
+                match command {
+                    Command::Push(segment, index) | Command::Pop(segment, index)
+                        if segment == "argument" && *index >= nargs =>
+                    {
+                        warn(
+                            "argument-out-of-range",
+                            *line,
+                            format!(
+                                "argument {} is beyond the {} arguments {} is called with",
+                                index, nargs, function.name
+                            ),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    lints.sort_by(|a, b| {
+        (&a.diagnostic.file, a.diagnostic.line).cmp(&(&b.diagnostic.file, b.diagnostic.line))
+    });
+    lints
+}
@@ -0,0 +1,536 @@
+// Language server for .vm files over stdio (see src/bin/hack_vm_lsp.rs).
+// Documents are synced in full; diagnostics come from the parser and the
+// linter, and definitions, hovers and callers look at every open document
+// plus the other .vm files in the same directory.
+use crate::linter;
+use crate::parser::{lex, validate, Parser, EXTENDED_COMMANDS};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const SEGMENTS: [&str; 8] = [
+    "argument", "local", "static", "constant", "this", "that", "pointer", "temp",
+];
+const KEYWORDS: [&str; 17] = [
+    "push", "pop", "add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not", "label", "goto",
+    "if-goto", "function", "call", "return",
+];
+
+// LSP severities and symbol kinds
+const ERROR: u8 = 1;
+const WARNING: u8 = 2;
+const FUNCTION_SYMBOL: u8 = 12;
+const KEYWORD_ITEM: u8 = 14;
+const FIELD_ITEM: u8 = 5;
+
+// read one `Content-Length` framed message, None at the end of input
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length =
+        length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+// file:///a/b.vm -> /a/b.vm, with %XX escapes decoded
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let path = path.display().to_string();
+    let escaped: String = path
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'.' | b'-' | b'_' | b'$' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+    format!("file://{}", escaped)
+}
+
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+// LSP positions count UTF-16 code units
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+// the byte offset of UTF-16 position `character` in `text`, its end if past it
+fn byte_offset(text: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+// the words of a line with the columns they span, comments left out
+fn words(line: &str) -> Vec<(usize, usize, &str)> {
+    let mut words = Vec::new();
+    let code = line.split("//").next().unwrap_or_default();
+    let mut start = None;
+    for (i, c) in code.char_indices().chain([(code.len(), ' ')]) {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                words.push((utf16_len(&code[..s]), utf16_len(&code[..i]), &code[s..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    words
+}
+
+// a `function` declaration, or a `call` or `label`/`goto` at some line
+struct Symbol {
+    uri: String,
+    line: usize,
+    start: usize,
+    end: usize,
+    // the function the line is in
+    scope: String,
+}
+
+struct Function {
+    name: String,
+    nvars: String,
+    declaration: Symbol,
+    // the last line of its body
+    end: usize,
+}
+
+// the parts of a document the requests need
+#[derive(Default)]
+struct Outline {
+    functions: Vec<Function>,
+    calls: Vec<(String, Symbol)>,
+    labels: Vec<(String, Symbol)>,
+}
+
+fn outline(uri: &str, text: &str) -> Outline {
+    let mut outline = Outline::default();
+    let mut scope = String::new();
+    let lines: Vec<&str> = text.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        let words = words(line);
+        let symbol = |index: usize, scope: &str| {
+            let (start, end, _) = words[index];
+            Symbol {
+                uri: uri.to_string(),
+                line: i,
+                start,
+                end,
+                scope: scope.to_string(),
+            }
+        };
+        match words.iter().map(|(_, _, word)| *word).collect::<Vec<_>>()[..] {
+            ["function", name, nvars] => {
+                if let Some(last) = outline.functions.last_mut() {
+                    last.end = i.saturating_sub(1);
+                }
+                scope = name.to_string();
+                outline.functions.push(Function {
+                    name: name.to_string(),
+                    nvars: nvars.to_string(),
+                    declaration: symbol(1, &scope),
+                    end: lines.len(),
+                });
+            }
+            ["call", name, _] => outline.calls.push((name.to_string(), symbol(1, &scope))),
+            ["label", name] => outline.labels.push((name.to_string(), symbol(1, &scope))),
+            _ => {}
+        }
+    }
+    if let Some(last) = outline.functions.last_mut() {
+        last.end = lines.len().saturating_sub(1);
+    }
+    outline
+}
+
+fn location(symbol: &Symbol) -> Value {
+    json!({ "uri": symbol.uri, "range": range(symbol.line, symbol.start, symbol.end) })
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    extended: bool,
+    shutdown: bool,
+    exit_code: Option<i32>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server::default()
+    }
+
+    // set once `exit` has been received: 0 after a `shutdown`, 1 otherwise
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    // the open documents plus the unopened .vm files next to `uri`
+    fn workspace(&self, uri: &str) -> Vec<(String, String)> {
+        let mut documents: Vec<(String, String)> = self
+            .documents
+            .iter()
+            .map(|(uri, text)| (uri.clone(), text.clone()))
+            .collect();
+        let dir = uri_to_path(uri).and_then(|path| path.parent().map(Path::to_path_buf));
+        if let Some(entries) = dir.and_then(|dir| std::fs::read_dir(dir).ok()) {
+            for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+                let file_uri = path_to_uri(&path);
+                if path.extension().is_some_and(|extension| extension == "vm")
+                    && !self.documents.contains_key(&file_uri)
+                {
+                    if let Ok(text) = std::fs::read_to_string(&path) {
+                        documents.push((file_uri, text));
+                    }
+                }
+            }
+        }
+        documents.sort();
+        documents
+    }
+
+    fn diagnostics(&self, uri: &str) -> Value {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let mut parser = Parser::new(lines.clone());
+        parser.set_extended(self.extended);
+
+        let mut diagnostics = Vec::new();
+        let mut commands = Vec::new();
+        while let Some(result) = parser.next() {
+            match result {
+                Ok(command) => {
+                    // segments and indices are checked as the translator does
+                    if let Err(message) = validate(&command) {
+                        let line = parser.currentLine - 1;
+                        diagnostics.push(json!({
+                            "range": range(line, 0, utf16_len(&lines[line])),
+                            "severity": ERROR,
+                            "source": "hack_vm",
+                            "message": message,
+                        }));
+                    }
+                    commands.push((parser.currentLine, command));
+                }
+                Err(err) => {
+                    let line = err.line - 1;
+                    diagnostics.push(json!({
+                        "range": range(line, 0, utf16_len(&lines[line])),
+                        "severity": ERROR,
+                        "source": "hack_vm",
+                        "message": err.message,
+                    }));
+                }
+            }
+        }
+        for lint in linter::lint(&[(uri.to_string(), commands)]) {
+            let line = lint.diagnostic.line - 1;
+            diagnostics.push(json!({
+                "range": range(line, 0, utf16_len(&lines[line])),
+                "severity": WARNING,
+                "source": "hack_vm",
+                "code": lint.rule,
+                "message": lint.diagnostic.message,
+            }));
+        }
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    // the word under the cursor, the words of its line and its index among them
+    fn word_at(&self, params: &Value) -> Option<(String, Vec<String>, usize, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        let text = self.documents.get(uri)?.lines().nth(line)?;
+        let words = words(text);
+        let index = words
+            .iter()
+            .position(|(start, end, _)| *start <= character && character <= *end)?;
+        let all = words.iter().map(|(_, _, word)| word.to_string()).collect();
+        Some((words[index].2.to_string(), all, index, line))
+    }
+
+    fn scope_at(&self, uri: &str, line: usize) -> String {
+        let outline = outline(uri, self.documents.get(uri).map_or("", String::as_str));
+        outline
+            .functions
+            .iter()
+            .rev()
+            .find(|function| function.declaration.line <= line)
+            .map(|function| function.name.clone())
+            .unwrap_or_default()
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let (word, words, _, line) = match self.word_at(params) {
+            Some(found) if found.2 == 1 => found,
+            _ => return Value::Null,
+        };
+        match words[0].as_str() {
+            "call" | "function" => {
+                for (uri, text) in self.workspace(uri) {
+                    let outline = outline(&uri, &text);
+                    if let Some(function) = outline.functions.iter().find(|f| f.name == word) {
+                        return location(&function.declaration);
+                    }
+                }
+                Value::Null
+            }
+            "goto" | "if-goto" | "label" => {
+                let scope = self.scope_at(uri, line);
+                let text = self.documents.get(uri).map_or("", String::as_str);
+                outline(uri, text)
+                    .labels
+                    .iter()
+                    .find(|(name, symbol)| *name == word && symbol.scope == scope)
+                    .map_or(Value::Null, |(_, symbol)| location(symbol))
+            }
+            _ => Value::Null,
+        }
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let word = match self.word_at(params) {
+            Some((word, words, 1, _)) if words[0] == "call" || words[0] == "function" => word,
+            _ => return Value::Null,
+        };
+        let mut nvars = None;
+        let mut callers = Vec::new();
+        for (uri, text) in self.workspace(uri) {
+            let outline = outline(&uri, &text);
+            if let Some(function) = outline.functions.iter().find(|f| f.name == word) {
+                nvars = Some(function.nvars.clone());
+            }
+            for (callee, symbol) in &outline.calls {
+                if *callee == word && !callers.contains(&symbol.scope) {
+                    callers.push(symbol.scope.clone());
+                }
+            }
+        }
+        let nvars = match nvars {
+            Some(nvars) => format!("{} local variables", nvars),
+            None => "not defined".to_string(),
+        };
+        let callers = if callers.is_empty() {
+            "no callers".to_string()
+        } else {
+            format!("called from {}", callers.join(", "))
+        };
+        json!({
+            "contents": {
+                "kind": "markdown",
+                "value": format!("**function {}**\n\n{}, {}", word, nvars, callers),
+            }
+        })
+    }
+
+    fn document_symbols(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let symbols: Vec<Value> = outline(uri, text)
+            .functions
+            .iter()
+            .map(|function| {
+                let declaration = &function.declaration;
+                let end_character = text.lines().nth(function.end).map_or(0, utf16_len);
+                json!({
+                    "name": function.name,
+                    "detail": format!("{} locals", function.nvars),
+                    "kind": FUNCTION_SYMBOL,
+                    "range": {
+                        "start": { "line": declaration.line, "character": 0 },
+                        "end": { "line": function.end, "character": end_character },
+                    },
+                    "selectionRange": range(declaration.line, declaration.start, declaration.end),
+                })
+            })
+            .collect();
+        json!(symbols)
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+        let text = self
+            .documents
+            .get(uri)
+            .and_then(|text| text.lines().nth(line));
+        let before = text.map_or("", |text| &text[..byte_offset(text, character)]);
+        // the word being typed is the last one unless the cursor follows a space
+        let mut typed = lex(before);
+        if before.ends_with(char::is_whitespace) || typed.is_empty() {
+            typed.push("");
+        }
+
+        let (candidates, kind): (Vec<&str>, u8) = match typed[..] {
+            [_] if self.extended => (
+                KEYWORDS
+                    .iter()
+                    .chain(EXTENDED_COMMANDS.iter())
+                    .copied()
+                    .collect(),
+                KEYWORD_ITEM,
+            ),
+            [_] => (KEYWORDS.to_vec(), KEYWORD_ITEM),
+            ["push", _] => (SEGMENTS.to_vec(), FIELD_ITEM),
+            // constant can't be popped to
+            ["pop", _] => (
+                SEGMENTS
+                    .iter()
+                    .copied()
+                    .filter(|s| *s != "constant")
+                    .collect(),
+                FIELD_ITEM,
+            ),
+            _ => (Vec::new(), KEYWORD_ITEM),
+        };
+        let prefix = typed.last().copied().unwrap_or_default();
+        let items: Vec<Value> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(prefix))
+            .map(|candidate| json!({ "label": candidate, "kind": kind }))
+            .collect();
+        json!(items)
+    }
+
+    // handle one message, returning the responses and notifications to send
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let result = match method {
+            "initialize" => {
+                self.extended = params["initializationOptions"]["extended"]
+                    .as_bool()
+                    .unwrap_or(false);
+                json!({
+                    "capabilities": {
+                        // full document sync
+                        "textDocumentSync": 1,
+                        "definitionProvider": true,
+                        "hoverProvider": true,
+                        "documentSymbolProvider": true,
+                        "completionProvider": { "triggerCharacters": [" "] },
+                    },
+                    "serverInfo": { "name": "hack_vm_lsp", "version": env!("CARGO_PKG_VERSION") },
+                })
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "exit" => {
+                self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+                return Vec::new();
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didChange" => {
+                // full sync: the last change holds the whole text
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|changes| changes.last()) {
+                    let text = text["text"].as_str().unwrap_or_default();
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })];
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/completion" => self.completion(params),
+            _ if message.get("id").is_none() => return Vec::new(),
+            _ => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": { "code": -32601, "message": format!("unknown method {}", method) },
+                })]
+            }
+        };
+        match message.get("id") {
+            Some(id) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => Vec::new(),
+        }
+    }
+}
@@ -1,83 +1,830 @@
+use hack_vm::analysis;
+use hack_vm::assembler::{assemble, Program};
+use hack_vm::c_writer::CWriter;
 use hack_vm::code_writer::CodeWriter;
-use hack_vm::compiler::{compile_vm_code, parse_filename, read_lines, VmFile};
-use hack_vm::parser::Parser;
+use hack_vm::compiler::{
+    check_output_path, compile_jack_files, compile_program, files_with_extension, flag_value,
+    has_flag, parse_filename, read_numbered_commands, read_program, translate_to_string,
+    write_jack_xml, write_text_file, write_vm_file, VmFile,
+};
+use hack_vm::diagnostics::Diagnostic;
+use hack_vm::disassembler::disassemble;
+use hack_vm::emulator::Emulator;
+use hack_vm::formatter::format_vm;
+use hack_vm::inliner::{inline_leaf_functions, DEFAULT_INLINE_THRESHOLD};
+use hack_vm::interpreter::Interpreter;
+use hack_vm::linter;
+use hack_vm::parser::Command;
+use hack_vm::ram_file;
+use hack_vm::screen::{self, KeyAction, KBD};
+use hack_vm::test_script::TestScript;
+use hack_vm::trace::{run_traced, TraceMode};
+use hack_vm::tui::{self, Charset};
+use hack_vm::wat_writer::WatWriter;
 use std::env;
 use std::ffi::OsStr;
 use std::fs;
-use std::path::Path;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if !args.len() == 2 {
-        panic!("two arguments required, filepath string and test boolean(true/false)")
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("analyze") => analyze(&args),
+        Some("xml") => xml(&args),
+        Some("disasm") => disasm(&args),
+        Some("fmt") => fmt(&args),
+        Some("lint") => lint(&args),
+        Some("watch") => watch(&args),
+        Some("run") => run(&args),
+        _ => translate(&args),
     }
+}
+
+// hack_vm analyze <dir|file.vm> [--format text|dot|json]
+fn analyze(args: &[String]) {
+    let path = args.get(2).unwrap_or_else(|| {
+        println!("usage: hack_vm analyze <dir|file.vm> [--format text|dot|json]");
+        std::process::exit(1);
+    });
+    let extended = has_flag(args, "--extended");
+    let entries = if Path::new(path).is_dir() {
+        files_with_extension(Path::new(path), "vm")
+    } else {
+        vec![PathBuf::from(path)]
+    };
+
+    let program = read_program(&entries, extended).unwrap_or_else(|diagnostic| {
+        println!("{}", diagnostic);
+        std::process::exit(1);
+    });
+    let analysis = analysis::analyze(&program);
+    match flag_value(args, "--format").unwrap_or("text") {
+        "text" => print!("{}", analysis.to_text()),
+        "dot" => print!("{}", analysis.to_dot()),
+        "json" => println!("{}", analysis.to_json()),
+        format => {
+            println!("unknown format: {}", format);
+            std::process::exit(1);
+        }
+    }
+}
+
+// hack_vm xml <dir|file.jack> [--output-dir dir]
+fn xml(args: &[String]) {
+    let path = args.get(2).unwrap_or_else(|| {
+        println!("usage: hack_vm xml <dir|file.jack> [--output-dir dir]");
+        std::process::exit(1);
+    });
+    let output_dir = flag_value(args, "--output-dir").map(Path::new);
+    let entries = if Path::new(path).is_dir() {
+        files_with_extension(Path::new(path), "jack")
+    } else {
+        vec![PathBuf::from(path)]
+    };
+
+    // report every file with errors, not just the first one
+    let mut failed = false;
+    for entry in &entries {
+        match write_jack_xml(entry, output_dir) {
+            Ok(output) => println!("wrote {}", output.display()),
+            Err(err) => {
+                println!("{}", err);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+// the .vm files of a directory, or the single file given
+fn vm_entries(path: &str) -> Vec<PathBuf> {
+    if Path::new(path).is_dir() {
+        files_with_extension(Path::new(path), "vm")
+    } else {
+        vec![PathBuf::from(path)]
+    }
+}
+
+// hack_vm fmt <dir|file.vm> [--check] [--format text|json]
+fn fmt(args: &[String]) {
+    let path = args.get(2).unwrap_or_else(|| {
+        println!("usage: hack_vm fmt <dir|file.vm> [--check] [--format text|json]");
+        std::process::exit(1);
+    });
+    // only report the files that are not formatted
+    let check = has_flag(args, "--check");
+    let json = flag_value(args, "--format") == Some("json");
+    let extended = has_flag(args, "--extended");
+
+    let mut changed = Vec::new();
+    let mut errors = Vec::new();
+    for entry in vm_entries(path) {
+        let name = entry.display().to_string();
+        let source = match fs::read_to_string(&entry) {
+            Ok(source) => source,
+            Err(err) => {
+                errors.push(Diagnostic::new(&name, 1, 1, &err.to_string()));
+                continue;
+            }
+        };
+        match format_vm(&name, &source, extended) {
+            Ok(formatted) if formatted != source => {
+                if !check {
+                    if let Err(err) = write_text_file(&entry, &formatted) {
+                        errors.push(Diagnostic::new(&name, 1, 1, &err.to_string()));
+                        continue;
+                    }
+                }
+                changed.push(name);
+            }
+            Ok(_) => {}
+            Err(diagnostic) => errors.push(diagnostic),
+        }
+    }
+
+    if json {
+        let report = serde_json::json!({ "changed": changed, "errors": errors });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        for name in &changed {
+            let verb = if check { "would reformat" } else { "formatted" };
+            println!("{} {}", verb, name);
+        }
+        for diagnostic in &errors {
+            println!("{}", diagnostic);
+        }
+    }
+    if !errors.is_empty() || (check && !changed.is_empty()) {
+        std::process::exit(1);
+    }
+}
+
+// hack_vm lint <dir|file.vm> [--format text|json]
+fn lint(args: &[String]) {
+    let path = args.get(2).unwrap_or_else(|| {
+        println!("usage: hack_vm lint <dir|file.vm> [--format text|json]");
+        std::process::exit(1);
+    });
+    let json = flag_value(args, "--format") == Some("json");
+    let extended = has_flag(args, "--extended");
+
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for entry in vm_entries(path) {
+        let name = entry.display().to_string();
+        let result = fs::read_to_string(&entry)
+            .map_err(|err| Diagnostic::new(&name, 1, 1, &err.to_string()))
+            .and_then(|source| read_numbered_commands(&name, &source, extended));
+        match result {
+            Ok(commands) => files.push((name, commands)),
+            Err(diagnostic) => errors.push(diagnostic),
+        }
+    }
+    let warnings = linter::lint(&files);
+
+    if json {
+        let report = serde_json::json!({ "warnings": warnings, "errors": errors });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        for warning in &warnings {
+            println!("{}", warning);
+        }
+        for diagnostic in &errors {
+            println!("{}", diagnostic);
+        }
+    }
+    if !warnings.is_empty() || !errors.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+// hack_vm disasm <file.asm> [-o file.vm|dir]
+fn disasm(args: &[String]) {
+    let path = args.get(2).unwrap_or_else(|| {
+        println!("usage: hack_vm disasm <file.asm> [-o file.vm|dir]");
+        std::process::exit(1);
+    });
+    let asm = fs::read_to_string(path).unwrap_or_else(|err| {
+        println!("error reading {}: {}", path, err);
+        std::process::exit(1);
+    });
+    let program = disassemble(path, &asm).unwrap_or_else(|diagnostic| {
+        println!("{}", diagnostic);
+        std::process::exit(1);
+    });
+
+    match flag_value(args, "--output").or(flag_value(args, "-o")) {
+        // one .vm file, or a directory with a .vm file for each file
+        Some(output) => {
+            let output = Path::new(output);
+            let paths: Vec<PathBuf> = match &program[..] {
+                [_] if !output.is_dir() => vec![output.to_path_buf()],
+                _ => {
+                    if let Err(err) = fs::create_dir_all(output) {
+                        println!("error creating {}: {}", output.display(), err);
+                        std::process::exit(1);
+                    }
+                    program
+                        .iter()
+                        .map(|(file, _)| output.join(format!("{}.vm", file)))
+                        .collect()
+                }
+            };
+            for (path, (_, commands)) in paths.iter().zip(&program) {
+                if let Err(err) = write_vm_file(path, commands) {
+                    println!("error writing {}: {}", path.display(), err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {
+            for (file, commands) in &program {
+                if program.len() > 1 {
+                    println!("// {}.vm", file);
+                }
+                for command in commands {
+                    println!("{}", command);
+                }
+            }
+        }
+    }
+}
+
+// the files watch reacts to, with when they last changed
+fn watched_files(dir: &Path) -> Vec<(PathBuf, Option<SystemTime>)> {
+    ["vm", "jack", "tst", "cmp"]
+        .iter()
+        .flat_map(|extension| files_with_extension(dir, extension))
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+// compile .jack files, translate the directory to Dir/Dir.asm (bootstrapped
+// when there is a Sys.vm) and, with
+// `run_tests`, run its CPU emulator .tst scripts
+fn build(
+    dir: &Path,
+    extended: bool,
+    tail_calls: bool,
+    run_tests: bool,
+    overwrite: bool,
+) -> Result<(), String> {
+    compile_jack_files(&files_with_extension(dir, "jack"), overwrite)?;
+    let entries = files_with_extension(dir, "vm");
+    let program = read_program(&entries, extended).map_err(|diagnostic| diagnostic.to_string())?;
+    let dirname = dir.canonicalize().map_err(|err| err.to_string())?;
+    let dirname = dirname.file_name().unwrap_or_default().to_string_lossy();
+    let output = dir.join(format!("{}.asm", dirname));
+    // programs without Sys.vm are course tests whose scripts set up the stack
+    let bootstrap = entries
+        .iter()
+        .any(|entry| entry.file_name() == Some(OsStr::new("Sys.vm")));
+    VmFile::create(&output)
+        .and_then(|file| CodeWriter::new(file, !bootstrap))
+        .and_then(|mut code_writer| {
+            code_writer.set_extended(extended);
+            code_writer.set_tail_calls(tail_calls);
+            compile_program(&program, code_writer, false)
+        })
+        .map_err(|err| format!("error writing {}: {}", output.display(), err))?;
+    println!("translated {}", output.display());
+    if !run_tests {
+        return Ok(());
+    }
+
+    let asm = fs::read_to_string(&output).map_err(|err| err.to_string())?;
+    let rom = assemble(&output.display().to_string(), &asm)
+        .map_err(|diagnostic| diagnostic.to_string())?
+        .rom;
+    // `FooVME.tst` scripts are for the VM emulator
+    let scripts = files_with_extension(dir, "tst")
+        .into_iter()
+        .filter(|script| !script.to_string_lossy().ends_with("VME.tst"));
+    for script_path in scripts {
+        let name = script_path.display();
+        let result = fs::read_to_string(&script_path)
+            .map_err(|err| err.to_string())
+            .and_then(|source| TestScript::parse(&source).map_err(|err| err.to_string()))
+            .and_then(|script| {
+                let output = script.run(&mut Emulator::new(rom.clone()));
+                match &script.compare_to {
+                    Some(compare_to) => {
+                        let expected = fs::read_to_string(dir.join(compare_to))
+                            .map_err(|err| format!("{}: {}", compare_to, err))?;
+                        script
+                            .compare(&output, &expected)
+                            .map_err(|err| format!("{}: {}", compare_to, err))
+                    }
+                    None => Ok(()),
+                }
+            });
+        match result {
+            Ok(()) => println!("{} passed", name),
+            Err(err) => println!("{} failed: {}", name, err),
+        }
+    }
+    Ok(())
+}
+
+// hack_vm watch <dir> [--test] [--interval ms]
+fn watch(args: &[String]) {
+    let dir = args.get(2).map(Path::new).filter(|dir| dir.is_dir());
+    let dir = dir.unwrap_or_else(|| {
+        println!("usage: hack_vm watch <dir> [--test] [--interval ms]");
+        std::process::exit(1);
+    });
+    let extended = has_flag(args, "--extended");
+    let tail_calls = has_flag(args, "--tail-calls");
+    // re-run the directory's .tst scripts after each translation
+    let run_tests = has_flag(args, "--test");
+    let overwrite = has_flag(args, "--overwrite-vm");
+    let interval = number_flag(args, "--interval", 500);
+
+    // polls modification times, .jack compilation and the .asm output are
+    // excluded by taking the snapshot after each build
+    let mut last = None;
+    loop {
+        let files = watched_files(dir);
+        if last.as_ref() != Some(&files) {
+            if let Err(err) = build(dir, extended, tail_calls, run_tests, overwrite) {
+                println!("{}", err);
+            }
+            println!("watching {} for changes", dir.display());
+            last = Some(watched_files(dir));
+        }
+        std::thread::sleep(Duration::from_millis(interval));
+    }
+}
+
+// the commands of a .vm/.jack file or directory, compiling .jack files first
+fn load_vm(
+    path: &Path,
+    extended: bool,
+    overwrite: bool,
+) -> Result<Vec<(String, Vec<Command>)>, String> {
+    let entries = if path.is_dir() {
+        compile_jack_files(&files_with_extension(path, "jack"), overwrite)?;
+        files_with_extension(path, "vm")
+    } else if path.extension() == Some(OsStr::new("jack")) {
+        compile_jack_files(&[path.to_path_buf()], overwrite)?
+    } else {
+        vec![path.to_path_buf()]
+    };
+    read_program(&entries, extended).map_err(|diagnostic| diagnostic.to_string())
+}
+
+fn has_sys_init(program: &[(String, Vec<Command>)]) -> bool {
+    program
+        .iter()
+        .flat_map(|(_, commands)| commands)
+        .any(|command| matches!(command, Command::Function(name, _) if name == "Sys.init"))
+}
+
+// an assembled .asm file, or a .vm/.jack file or directory translated on
+// the fly, bootstrapped when it has a Sys.init
+fn load_program(path: &Path, extended: bool, overwrite: bool) -> Result<Program, String> {
+    let name = path.display().to_string();
+    if path.extension() == Some(OsStr::new("asm")) {
+        let asm =
+            fs::read_to_string(path).map_err(|err| format!("error reading {}: {}", name, err))?;
+        return assemble(&name, &asm).map_err(|diagnostic| diagnostic.to_string());
+    }
+    let program = load_vm(path, extended, overwrite)?;
+    let asm = translate_to_string(&program, has_sys_init(&program), extended)
+        .map_err(|err| err.to_string())?;
+    assemble(&name, &asm).map_err(|diagnostic| generated_error(&name, &asm, &diagnostic))
+}
+
+// an assembler error in translated code, its line is one of the generated
+// assembly so name the VM command the `//@` source map says it came from
+fn generated_error(name: &str, asm: &str, diagnostic: &Diagnostic) -> String {
+    let origin = asm
+        .lines()
+        .take(diagnostic.line)
+        .filter_map(|line| line.strip_prefix("//@ "))
+        .last()
+        .unwrap_or("bootstrap");
+    format!(
+        "{}: line {} of the generated assembly (from `{}`): {}",
+        name, diagnostic.line, origin, diagnostic.message
+    )
+}
+
+// the number following `--flag`, exiting with an error when it isn't one
+fn number_flag<T: std::str::FromStr>(args: &[String], flag: &str, default: T) -> T {
+    flag_value(args, flag)
+        .map(|value| {
+            value.parse().unwrap_or_else(|_| {
+                println!("{} must be a number", flag);
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or(default)
+}
+
+// set RAM from the --ram file, before anything runs
+fn load_ram(args: &[String], ram: &mut [i16]) {
+    let Some(path) = flag_value(args, "--ram") else {
+        return;
+    };
+    let result = fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|source| ram_file::load(&source, ram));
+    if let Err(err) = result {
+        println!("{}: {}", path, err);
+        std::process::exit(1);
+    }
+}
+
+// write RAM to the --dump-ram file, as JSON for a .json path, limited to
+// the --dump-range ranges when given
+fn dump_ram(args: &[String], ram: &[i16]) {
+    let Some(path) = flag_value(args, "--dump-ram") else {
+        return;
+    };
+    let ranges = flag_value(args, "--dump-range")
+        .map(ram_file::parse_ranges)
+        .unwrap_or(Ok(Vec::new()))
+        .unwrap_or_else(|err| {
+            println!("--dump-range: {}", err);
+            std::process::exit(1);
+        });
+    let dump = if path.ends_with(".json") {
+        ram_file::dump_json(ram, &ranges)
+    } else {
+        ram_file::dump_text(ram, &ranges)
+    };
+    if path == "-" {
+        print!("{}", dump);
+        return;
+    }
+    match fs::write(path, dump) {
+        Ok(()) => println!("wrote {}", path),
+        Err(err) => println!("error writing {}: {}", path, err),
+    }
+}
 
-    let filepath = parse_filename(&args).unwrap_or_else(|err| {
+// hack_vm run --interpret: the VM commands on the reference interpreter,
+// with --cycles counting commands
+fn interpret(args: &[String], path: &Path, max_steps: u64) {
+    if path.extension() == Some(OsStr::new("asm")) {
+        println!("--interpret needs .vm or .jack files");
+        std::process::exit(1);
+    }
+    let program = load_vm(
+        path,
+        has_flag(args, "--extended"),
+        has_flag(args, "--overwrite-vm"),
+    )
+    .unwrap_or_else(|err| {
         println!("{}", err);
         std::process::exit(1);
     });
+    let mut interpreter = Interpreter::new(&program);
+    load_ram(args, &mut interpreter.ram);
+    let result = if has_sys_init(&program) {
+        interpreter.bootstrap()
+    } else {
+        Ok(())
+    };
+    match result.and_then(|()| interpreter.run(max_steps)) {
+        Ok(true) => println!("halted after {} commands", interpreter.steps),
+        Ok(false) => println!("stopped after {} commands", interpreter.steps),
+        Err(err) => {
+            println!("error after {} commands: {}", interpreter.steps, err);
+            dump_ram(args, &interpreter.ram);
+            std::process::exit(1);
+        }
+    }
+    if let Some(screen_path) = flag_value(args, "--screen") {
+        write_screen(Path::new(screen_path), &interpreter.ram);
+    }
+    dump_ram(args, &interpreter.ram);
+}
 
-    // match whether filepath is a single file or a folder
-    let is_dir = std::path::PathBuf::from(filepath).is_dir();
-    let is_file = std::path::PathBuf::from(filepath).is_file();
+// write the screen as PBM or PNG, by the extension of `path`
+fn write_screen(path: &Path, ram: &[i16]) {
+    let image = if path.extension() == Some(OsStr::new("pbm")) {
+        screen::to_pbm(ram)
+    } else {
+        screen::to_png(ram)
+    };
+    match fs::write(path, image) {
+        Ok(()) => println!("wrote {}", path.display()),
+        Err(err) => println!("error writing {}: {}", path.display(), err),
+    }
+}
 
-    dbg!(format!("is_dir: {}", is_dir));
-    dbg!(format!("is_file: {}", is_file));
+// out.png -> out-1000.png for the screen at cycle 1000
+fn frame_path(path: &Path, cycle: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-{}.{}", stem, cycle, extension))
+}
 
-    let file_parent = Path::new(filepath).parent().unwrap().to_str().unwrap();
-    dbg!(file_parent);
-    let filename = Path::new(filepath).file_stem().unwrap().to_str().unwrap();
-    dbg!(filename);
+// hack_vm run <file.asm|file.vm|dir> [--cycles n] [--keys file] [--screen out.png|out.pbm]
+//     [--screen-every n] [--no-progress n] [--trace file|- [--trace-vm]]
+//     [--ram file] [--dump-ram file|- [--dump-range a..b,c..d]] [--interpret]
+//     [--tui [--charset braille|blocks] [--scale n]]
+fn run(args: &[String]) {
+    let path = args.get(2).unwrap_or_else(|| {
+        println!("usage: hack_vm run <file.asm|file.vm|dir> [--cycles n] [--keys file] [--screen out.png|out.pbm] [--screen-every n]");
+        std::process::exit(1);
+    });
+    let number = |flag: &str, default: u64| number_flag(args, flag, default);
+    let max_cycles = number("--cycles", 10_000_000);
+    let screen_every = number("--screen-every", 0);
+    let no_progress = flag_value(args, "--no-progress").map(|_| number("--no-progress", 0));
+    let screen_path = flag_value(args, "--screen").map(PathBuf::from);
+    let events = match flag_value(args, "--keys") {
+        Some(keys) => fs::read_to_string(keys)
+            .map_err(|err| err.to_string())
+            .and_then(|source| screen::parse_key_script(&source))
+            .unwrap_or_else(|err| {
+                println!("{}: {}", keys, err);
+                std::process::exit(1);
+            }),
+        None => Vec::new(),
+    };
+    if has_flag(args, "--interpret") {
+        interpret(args, Path::new(path), max_cycles);
+        return;
+    }
+    let program = load_program(
+        Path::new(path),
+        has_flag(args, "--extended"),
+        has_flag(args, "--overwrite-vm"),
+    )
+    .unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(1);
+    });
 
-    if is_file {
-        let file = VmFile::new(format!("{}/{}", file_parent, filename).as_str()).unwrap();
-        dbg!(format!("{}/{}", file_parent, filename));
+    if has_flag(args, "--tui") {
+        let charset = match flag_value(args, "--charset").unwrap_or("braille") {
+            "braille" => Charset::Braille,
+            "blocks" => Charset::Blocks,
+            charset => {
+                println!("unknown charset: {}", charset);
+                std::process::exit(1);
+            }
+        };
+        let max_cycles = flag_value(args, "--cycles").map(|_| max_cycles);
+        let scale = number("--scale", 0) as usize;
+        let emulator = tui::run(&program, charset, scale, max_cycles).unwrap_or_else(|err| {
+            println!("terminal error: {}", err);
+            std::process::exit(1);
+        });
         println!(
-            "Creating Virtual Machine bytecode file: {:?}",
-            format!("{}/{}", file_parent, filename)
+            "stopped after {} cycles at ROM[{}]",
+            emulator.cycles, emulator.pc
         );
+        if let Some(path) = &screen_path {
+            write_screen(path, &emulator.ram);
+        }
+        dump_ram(args, &emulator.ram);
+        return;
+    }
 
-        let is_test = matches!(args[2].as_str(), "true");
+    // instruction or VM command traces, to a file or `-` for stdout
+    let trace_mode = if has_flag(args, "--trace-vm") {
+        TraceMode::Vm
+    } else {
+        TraceMode::Instructions
+    };
+    if trace_mode == TraceMode::Vm && program.source_map.is_empty() {
+        println!("--trace-vm needs a .vm input or assembly translated with --source-map");
+        std::process::exit(1);
+    }
+    let mut trace: Option<Box<dyn Write>> = flag_value(args, "--trace").map(|trace_path| {
+        if trace_path == "-" {
+            return Box::new(io::stdout().lock()) as Box<dyn Write>;
+        }
+        match fs::File::create(trace_path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(err) => {
+                println!("error writing {}: {}", trace_path, err);
+                std::process::exit(1);
+            }
+        }
+    });
 
-        // read the code file
-        let lines = read_lines(filepath);
+    // run up to each key event and screen frame in turn
+    let mut emulator = Emulator::new(program.rom.clone());
+    load_ram(args, &mut emulator.ram);
+    let mut events = events.into_iter().peekable();
+    let mut next_frame = screen_every;
+    let finished = loop {
+        let mut target = max_cycles;
+        if let Some((cycle, _)) = events.peek() {
+            target = target.min(*cycle);
+        }
+        if screen_every > 0 {
+            target = target.min(next_frame);
+        }
+        let cycles = target.saturating_sub(emulator.cycles);
+        // keys change RAM from outside, so a loop waiting for one isn't stuck
+        emulator.set_no_progress(no_progress.filter(|_| events.peek().is_none()));
+        let finished = match &mut trace {
+            Some(out) => run_traced(&mut emulator, &program, cycles, trace_mode, out)
+                .unwrap_or_else(|err| {
+                    println!("error writing trace: {}", err);
+                    std::process::exit(1);
+                }),
+            None => emulator.run(cycles),
+        };
+        if finished || emulator.cycles >= max_cycles {
+            break finished;
+        }
+        while let Some((_, action)) = events.next_if(|(cycle, _)| *cycle <= emulator.cycles) {
+            match action {
+                KeyAction::Press(code) => emulator.ram[KBD] = code,
+                KeyAction::Release => emulator.ram[KBD] = 0,
+                KeyAction::Screenshot => match &screen_path {
+                    Some(path) => write_screen(&frame_path(path, emulator.cycles), &emulator.ram),
+                    None => println!("screenshot at cycle {} needs --screen", emulator.cycles),
+                },
+            }
+        }
+        if screen_every > 0 && emulator.cycles >= next_frame {
+            if let Some(path) = &screen_path {
+                write_screen(&frame_path(path, emulator.cycles), &emulator.ram);
+            }
+            next_frame += screen_every;
+        }
+    };
 
-        // // initialise the file object
-        // let file = VmFile::new(format!("{}/{}", file_parent, filename).as_str()).unwrap();
+    if let Some(out) = &mut trace {
+        if let Err(err) = out.flush() {
+            println!("error writing trace: {}", err);
+            std::process::exit(1);
+        }
+    }
+    let function = tui::current_function(&program, emulator.pc)
+        .map(|function| format!(" (VM: {})", function))
+        .unwrap_or_default();
+    if !emulator.is_running() {
+        println!(
+            "ran off the end of the ROM after {} cycles",
+            emulator.cycles
+        );
+    } else if finished {
+        let stuck = if emulator.is_stuck() {
+            ", making no progress"
+        } else {
+            ""
+        };
+        println!(
+            "halted at ROM[{}]{} after {} cycles{}",
+            emulator.pc, function, emulator.cycles, stuck
+        );
+    } else {
+        println!(
+            "stopped at ROM[{}]{} after {} cycles",
+            emulator.pc, function, emulator.cycles
+        );
+    }
+    if let Some(path) = &screen_path {
+        write_screen(path, &emulator.ram);
+    }
+    dump_ram(args, &emulator.ram);
+}
 
-        let parser = Parser::new(lines);
+fn translate(args: &[String]) {
+    let filepath = parse_filename(args).unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(1);
+    });
 
-        let code_writer = CodeWriter::new(file, is_test);
-        _ = compile_vm_code(parser, code_writer, &is_test)
-    } else if is_dir {
-        let file =
-            VmFile::new(format!("{}/{}/{}", file_parent, filename, filename).as_str()).unwrap();
-        let is_test = matches!(args[2].as_str(), "true");
-        dbg!(format!("is_dir: {}", is_dir));
+    let is_test = args.get(2).map(|arg| arg.as_str()) == Some("true");
+    // opt-in mul/div/mod/shl/shr/xor commands, off by default to stay course-compliant
+    let extended = has_flag(args, "--extended");
+    // reuse the caller's frame for `call f n` directly followed by `return`
+    let tail_calls = has_flag(args, "--tail-calls");
+    // inline small leaf functions into their callers
+    let inline = has_flag(args, "--inline");
+    let inline_threshold = number_flag(args, "--inline-threshold", DEFAULT_INLINE_THRESHOLD);
+    // `//@ File.vm: command` comments for `hack_vm run --trace-vm`
+    let source_map = has_flag(args, "--source-map");
+    // hack assembly, c for a native build of the program or wat for the browser
+    let target = flag_value(args, "--target").unwrap_or("hack");
+    // replace Foo.vm files that were not compiled from the Foo.jack next to them
+    let overwrite = has_flag(args, "--overwrite-vm");
+    let extension = match target {
+        "hack" => "asm",
+        "c" => "c",
+        "wat" => "wat",
+        _ => {
+            println!("unknown target {}, expected hack, c or wat", target);
+            std::process::exit(1);
+        }
+    };
+    if target != "hack" && (tail_calls || source_map) {
+        println!("--tail-calls and --source-map only apply to --target hack");
+        std::process::exit(1);
+    }
 
-        // filter for all the .vm files in the directory
-        let vm = &OsStr::new("vm");
+    // match whether filepath is a single file or a folder
+    let path = Path::new(filepath);
+    let (default_output, jack_files) = if path.is_file() {
+        // Foo.vm -> Foo.asm, Foo.jack -> Foo.vm -> Foo.asm
+        let jack_files = if path.extension() == Some(OsStr::new("jack")) {
+            vec![path.to_path_buf()]
+        } else {
+            Vec::new()
+        };
+        (path.with_extension(extension), jack_files)
+    } else if path.is_dir() {
+        // Foo/ -> Foo/Foo.asm, compiling all .jack files to .vm first and then
+        // translating all files with a .vm extension
+        let dirname = path.canonicalize().unwrap();
+        let dirname = dirname.file_name().unwrap().to_str().unwrap();
+        (
+            path.join(format!("{}.{}", dirname, extension)),
+            files_with_extension(path, "jack"),
+        )
+    } else {
+        println!("{}: no such file or directory", filepath);
+        std::process::exit(1);
+    };
 
-        // want all files with a .vm extension
-        let entries = Vec::from_iter(
-            fs::read_dir(format!("{}/{}", file_parent, filename))
-                .unwrap()
-                .filter_map(Result::ok)
-                .map(|e| e.path())
-                .filter(|p| p.extension() == Some(vm)),
-        );
+    let output = flag_value(args, "--output")
+        .or(flag_value(args, "-o"))
+        .map(PathBuf::from)
+        .unwrap_or(default_output);
+    // checked before any .vm file is compiled, so a bad -o leaves nothing behind
+    let mut inputs = jack_files.clone();
+    inputs.extend(
+        jack_files
+            .iter()
+            .map(|jack_file| jack_file.with_extension("vm")),
+    );
+    if path.is_dir() {
+        inputs.extend(files_with_extension(path, "vm"));
+    } else if jack_files.is_empty() {
+        inputs.push(path.to_path_buf());
+    }
+    if let Err(err) = check_output_path(&output, &inputs) {
+        println!("{}", err);
+        std::process::exit(1);
+    }
+    let compiled = compile_jack(&jack_files, overwrite);
+    let entries = if path.is_dir() {
+        files_with_extension(path, "vm")
+    } else if jack_files.is_empty() {
+        vec![path.to_path_buf()]
+    } else {
+        compiled
+    };
+    println!("Creating Virtual Machine bytecode file: {:?}", output);
 
-        dbg!(&entries);
+    // create new parser for each vm file, keeping the file name for its statics
+    let mut program = read_program(&entries, extended).unwrap_or_else(|diagnostic| {
+        println!("{}", diagnostic);
+        std::process::exit(1);
+    });
 
-        let mut code_writer = CodeWriter::new(file, is_test);
-        // create new parser for each vm file
-        // pass the parser sequentially to the code_writer, also invoking setFilename on the code_writer
-        for vm_file in entries {
-            let filename = vm_file.as_path().file_stem();
-            let lines = read_lines(vm_file.as_os_str().to_str().unwrap());
-            let parser = Parser::new(lines);
-            code_writer.set_file_name(filename.unwrap().to_str().unwrap());
-            code_writer = compile_vm_code(parser, code_writer, &is_test);
+    if inline {
+        for inlined in inline_leaf_functions(&mut program, inline_threshold) {
+            println!(
+                "inlined {} into {} ({} call sites)",
+                inlined.callee, inlined.caller, inlined.call_sites
+            );
         }
     }
+
+    let result = VmFile::create(&output).and_then(|file| match target {
+        "c" => CWriter::new(file, is_test).and_then(|mut c_writer| {
+            c_writer.set_extended(extended);
+            compile_program(&program, c_writer, is_test)
+        }),
+        "wat" => WatWriter::new(file, is_test).and_then(|mut wat_writer| {
+            wat_writer.set_extended(extended);
+            compile_program(&program, wat_writer, is_test)
+        }),
+        _ => CodeWriter::new(file, is_test).and_then(|mut code_writer| {
+            code_writer.set_extended(extended);
+            code_writer.set_tail_calls(tail_calls);
+            code_writer.set_source_map(source_map);
+            compile_program(&program, code_writer, is_test)
+        }),
+    });
+    if let Err(err) = result {
+        println!("error writing {}: {}", output.display(), err);
+        std::process::exit(1);
+    }
+}
+
+fn compile_jack(jack_files: &[PathBuf], overwrite: bool) -> Vec<PathBuf> {
+    compile_jack_files(jack_files, overwrite).unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(1);
+    })
 }
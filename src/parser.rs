@@ -1,111 +1,260 @@
+use std::fmt;
+
+// standard arithmetic/logical commands from the VM specification
+const ARITHMETIC_COMMANDS: [&str; 9] = ["add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not"];
+
+// opt-in commands that are not part of the course VM language
+pub const EXTENDED_COMMANDS: [&str; 6] = ["mul", "div", "mod", "shl", "shr", "xor"];
+
+// the code part of a line, without its `//` comment or surrounding whitespace
+pub fn strip_comment(line: &str) -> &str {
+    line.split("//").next().unwrap_or_default().trim()
+}
+
+// split a line into whitespace separated words, ignoring any trailing comment.
+// Tabs and the `\r` of Windows line endings count as whitespace.
+pub fn lex(line: &str) -> Vec<&str> {
+    strip_comment(line).split_whitespace().collect()
+}
+
+// a single parsed VM command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Push(String, i16),
+    Pop(String, i16),
+    Arithmetic(String),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    Function(String, i16),
+    Call(String, i16),
+    Return,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Push(segment, index) => write!(f, "push {} {}", segment, index),
+            Command::Pop(segment, index) => write!(f, "pop {} {}", segment, index),
+            Command::Arithmetic(command) => write!(f, "{}", command),
+            Command::Label(label) => write!(f, "label {}", label),
+            Command::Goto(label) => write!(f, "goto {}", label),
+            Command::IfGoto(label) => write!(f, "if-goto {}", label),
+            Command::Function(name, nvars) => write!(f, "function {} {}", name, nvars),
+            Command::Call(name, nargs) => write!(f, "call {} {}", name, nargs),
+            Command::Return => write!(f, "return"),
+        }
+    }
+}
+
+// check the segment and index of a push or pop, the parser accepts any word
+// and number for them
+pub fn validate(command: &Command) -> Result<(), String> {
+    let (segment, index, pop) = match command {
+        Command::Push(segment, index) => (segment.as_str(), *index, false),
+        Command::Pop(segment, index) => (segment.as_str(), *index, true),
+        _ => return Ok(()),
+    };
+    let last = match segment {
+        "constant" if pop => return Err("cannot pop to constant".to_string()),
+        "pointer" => 1,
+        "temp" => 7,
+        "constant" | "argument" | "local" | "static" | "this" | "that" => i16::MAX,
+        _ => return Err(format!("unknown segment '{}'", segment)),
+    };
+    if index < 0 || index > last {
+        return Err(format!(
+            "{} index {} is out of range 0..{}",
+            segment, index, last
+        ));
+    }
+    Ok(())
+}
+
+// a line that is not a valid command and what is wrong with it, lines start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub instruction: String,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[allow(non_snake_case)]
 pub struct Parser {
     pub contents: Vec<String>,
     pub currentLine: usize,
     pub currentInstruction: String,
+    extended: bool,
 }
 
+#[allow(non_snake_case)]
 impl Parser {
     pub fn new(code_lines: Vec<String>) -> Self {
-        let mut new_parser = Parser {
+        Parser {
             contents: code_lines,
             currentLine: 0,
             currentInstruction: "".to_string(),
-        };
-        // set the initial instruction
-        // new_parser.advance();
-        new_parser
+            extended: false,
+        }
+    }
+
+    // accept the extended instruction set (mul, div, mod, shl, shr, xor)
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
     }
 
     pub fn current_instruction(&self) -> String {
         self.currentInstruction.to_owned()
     }
 
+    // true while an instruction is left, blank and comment lines don't count
     pub fn hasMoreLines(&self) -> bool {
-        self.currentLine <= self.contents.len() - 1
+        self.peek_instruction().is_some()
     }
 
-    fn increment_line(&mut self) {
-        if self.hasMoreLines() {
-            self.currentLine += 1;
-        }
-    }
     fn set_instruction(&mut self) {
-        self.currentInstruction = self.contents[self.currentLine].to_string();
+        self.currentInstruction = lex(&self.contents[self.currentLine]).join(" ");
     }
 
+    // move to the next instruction, past the end of the input this does nothing
     pub fn advance(&mut self) {
-        if self.contents[self.currentLine].starts_with("//")
-            | self.contents[self.currentLine].is_empty()
-        {
-            self.increment_line();
-            self.advance();
-        } else {
-            self.set_instruction();
-            self.increment_line();
+        while self.currentLine < self.contents.len() {
+            let is_blank = strip_comment(&self.contents[self.currentLine]).is_empty();
+            if !is_blank {
+                self.set_instruction();
+                self.currentLine += 1;
+                return;
+            }
+            self.currentLine += 1;
         }
     }
 
+    // the next instruction advance() would move to, without consuming it
+    pub fn peek_instruction(&self) -> Option<&str> {
+        self.contents
+            .iter()
+            .skip(self.currentLine)
+            .map(|line| strip_comment(line))
+            .find(|line| !line.is_empty())
+    }
+
+    fn words(&self) -> Vec<&str> {
+        lex(&self.currentInstruction)
+    }
+
     pub fn commandType(&self) -> Result<&str, &str> {
-        if self.currentInstruction.starts_with("push") {
-            return Ok("C_PUSH");
-        } else if self.currentInstruction.starts_with("pop") {
-            return Ok("C_POP");
-        } else if self.currentInstruction.starts_with("label") {
-            return Ok("C_LABEL");
-        } else if self.currentInstruction.starts_with("if-goto") {
-            return Ok("C_IFGOTO");
-        } else if self.currentInstruction.starts_with("goto") {
-            return Ok("C_GOTO");
-        } else if self.currentInstruction.starts_with("function") {
-            return Ok("C_FUNCTION");
-        } else if self.currentInstruction.starts_with("return") {
-            return Ok("C_RETURN");
-        } else if self.currentInstruction.starts_with("call") {
-            return Ok("C_CALL");
-        } else if ["add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not"].contains(
-            &self
-                .currentInstruction
-                .split_whitespace()
-                .next()
-                .unwrap_or(""),
-        ) {
-            return Ok("C_ARITHMETIC");
-        } else {
-            return Err("could not match command");
-        };
+        // whole words only, so `pushy` is not a push
+        match self.words().first().copied().unwrap_or("") {
+            "push" => Ok("C_PUSH"),
+            "pop" => Ok("C_POP"),
+            "label" => Ok("C_LABEL"),
+            "if-goto" => Ok("C_IFGOTO"),
+            "goto" => Ok("C_GOTO"),
+            "function" => Ok("C_FUNCTION"),
+            "return" => Ok("C_RETURN"),
+            "call" => Ok("C_CALL"),
+            keyword
+                if ARITHMETIC_COMMANDS.contains(&keyword)
+                    || (self.extended && EXTENDED_COMMANDS.contains(&keyword)) =>
+            {
+                Ok("C_ARITHMETIC")
+            }
+            _ => Err("could not match command"),
+        }
     }
 
     pub fn arg1(&self) -> Option<String> {
-        match self.commandType() {
-            Ok(cmd) => match cmd {
-                "C_PUSH" | "C_POP" | "C_LABEL" | "C_IFGOTO" | "C_GOTO" | "C_FUNCTION"
-                | "C_CALL" => {
-                    let parts: Vec<&str> = self.currentInstruction.split_whitespace().collect();
-                    Some(parts[1].to_string())
-                }
-                "C_ARITHMETIC" => Some(
-                    self.currentInstruction
-                        .split_whitespace()
-                        .next()
-                        .unwrap_or("")
-                        .to_string(),
-                ),
-                _ => None,
-            },
-            Err(_) => None,
+        let words = self.words();
+        match self.commandType().ok()? {
+            "C_PUSH" | "C_POP" | "C_LABEL" | "C_IFGOTO" | "C_GOTO" | "C_FUNCTION" | "C_CALL" => {
+                words.get(1).map(|word| word.to_string())
+            }
+            "C_ARITHMETIC" => words.first().map(|word| word.to_string()),
+            _ => None,
         }
     }
 
     pub fn arg2(&self) -> Option<String> {
-        match self.commandType() {
-            Ok(cmd) => match cmd {
-                "C_PUSH" | "C_POP" | "C_FUNCTION" | "C_CALL" => {
-                    let parts: Vec<&str> = self.currentInstruction.split_whitespace().collect();
-                    Some(parts[2].parse().unwrap())
-                }
-                _ => None,
-            },
-            Err(_) => None,
+        match self.commandType().ok()? {
+            "C_PUSH" | "C_POP" | "C_FUNCTION" | "C_CALL" => {
+                self.words().get(2).map(|word| word.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    // the current instruction as a Command, None if it can't be parsed
+    pub fn command(&self) -> Option<Command> {
+        self.parse_command().ok()
+    }
+
+    // the current instruction as a Command, or what is wrong with it
+    fn parse_command(&self) -> Result<Command, String> {
+        let words = self.words();
+        let Ok(command_type) = self.commandType() else {
+            return Err(format!("unknown command '{}'", self.currentInstruction));
+        };
+        let keyword = words[0];
+        let arguments = match command_type {
+            "C_PUSH" | "C_POP" | "C_FUNCTION" | "C_CALL" => 2,
+            "C_LABEL" | "C_GOTO" | "C_IFGOTO" => 1,
+            _ => 0,
+        };
+        if words.len() - 1 != arguments {
+            return Err(format!(
+                "{} takes {} argument{}, not {}",
+                keyword,
+                arguments,
+                if arguments == 1 { "" } else { "s" },
+                words.len() - 1
+            ));
         }
+        // indices and counts are never negative
+        let number = |name: &str| match words[2].parse::<i64>() {
+            Err(_) => Err(format!(
+                "{} {} '{}' is not a number",
+                keyword, name, words[2]
+            )),
+            Ok(n) if n < 0 => Err(format!("{} {} {} is negative", keyword, name, n)),
+            Ok(n) => i16::try_from(n)
+                .map_err(|_| format!("{} {} {} is above {}", keyword, name, n, i16::MAX)),
+        };
+        // arithmetic commands are their own name
+        let name = || words.get(1).unwrap_or(&keyword).to_string();
+        match command_type {
+            "C_PUSH" => Ok(Command::Push(name(), number("index")?)),
+            "C_POP" => Ok(Command::Pop(name(), number("index")?)),
+            "C_ARITHMETIC" => Ok(Command::Arithmetic(name())),
+            "C_LABEL" => Ok(Command::Label(name())),
+            "C_GOTO" => Ok(Command::Goto(name())),
+            "C_IFGOTO" => Ok(Command::IfGoto(name())),
+            "C_FUNCTION" => Ok(Command::Function(name(), number("nVars")?)),
+            "C_CALL" => Ok(Command::Call(name(), number("nArgs")?)),
+            _ => Ok(Command::Return),
+        }
+    }
+}
+
+// yields each command in turn, and an error for each line that isn't one.
+// After `next` returns, `currentLine` is the line it came from.
+impl Iterator for Parser {
+    type Item = Result<Command, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peek_instruction()?;
+        self.advance();
+        Some(self.parse_command().map_err(|message| ParseError {
+            line: self.currentLine,
+            instruction: self.current_instruction(),
+            message,
+        }))
     }
 }
@@ -0,0 +1,278 @@
+// RAM files, to set up RAM before a run and dump it after. A text file has
+// one `target = values` line per cell or range:
+//
+//     SP = 256
+//     ARG = 400
+//     RAM[16384..16416] = -1      // one value fills a range
+//     argument[0..3] = 7 8 9
+//
+// Targets are `RAM[i]`, `RAM[a..b]` (b exclusive), the registers SP, LCL,
+// ARG, THIS, THAT, R0-R15, SCREEN and KBD, and the segments temp, pointer,
+// local, argument, this and that. A JSON file is an object with the same
+// targets as keys and a number or an array of numbers for each. local,
+// argument, this and that are relative to the pointers, so they are set
+// after everything else. Statics are numbered by first use, not index, so
+// they can only be set through RAM[i]
+use crate::emulator::RAM_SIZE;
+use serde_json::{Map, Value};
+use std::ops::Range;
+
+// where a target's cells start: at an address, or at the address held in a
+// pointer register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Base {
+    Address(usize),
+    Pointer(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    base: Base,
+    cells: Range<usize>,
+}
+
+fn base(name: &str) -> Option<Base> {
+    let address = match name {
+        "RAM" => 0,
+        "SP" => 0,
+        "LCL" => 1,
+        "ARG" => 2,
+        "THIS" => 3,
+        "THAT" => 4,
+        "SCREEN" => 16384,
+        "KBD" => 24576,
+        "pointer" => 3,
+        "temp" => 5,
+        "local" => return Some(Base::Pointer(1)),
+        "argument" => return Some(Base::Pointer(2)),
+        "this" => return Some(Base::Pointer(3)),
+        "that" => return Some(Base::Pointer(4)),
+        _ => {
+            let register: usize = name.strip_prefix('R')?.parse().ok()?;
+            return (register < 16).then_some(Base::Address(register));
+        }
+    };
+    Some(Base::Address(address))
+}
+
+// `name`, `name[i]` or `name[a..b]`
+fn parse_target(target: &str) -> Result<Target, String> {
+    let target = target.trim();
+    let (name, cells) = match target.split_once('[') {
+        Some((name, index)) => {
+            let index = index
+                .strip_suffix(']')
+                .ok_or_else(|| format!("expected ']' in '{}'", target))?;
+            let number = |n: &str| {
+                n.trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid index '{}'", n))
+            };
+            let cells = match index.split_once("..") {
+                Some((start, end)) => number(start)?..number(end)?,
+                None => number(index).map(|i| i..i + 1)?,
+            };
+            (name.trim(), cells)
+        }
+        None => (target, 0..1),
+    };
+    if cells.is_empty() {
+        return Err(format!("empty range in '{}'", target));
+    }
+    let base = base(name).ok_or_else(|| format!("unknown target '{}'", name))?;
+    Ok(Target { base, cells })
+}
+
+fn parse_value(value: &str) -> Result<i16, String> {
+    // 16-bit words, written signed or unsigned
+    match value.parse::<i32>() {
+        Ok(n) if (-32768..=65535).contains(&n) => Ok(n as i16),
+        _ => Err(format!("invalid value '{}'", value)),
+    }
+}
+
+// the RAM addresses a target covers, once the pointers it may depend on are
+// set
+fn addresses(target: &Target, ram: &[i16]) -> Range<usize> {
+    let start = match target.base {
+        Base::Address(address) => address,
+        Base::Pointer(register) => usize::from(ram[register] as u16),
+    };
+    start.saturating_add(target.cells.start)..start.saturating_add(target.cells.end)
+}
+
+fn apply(assignments: &[(Target, Vec<i16>)], ram: &mut [i16]) -> Result<(), String> {
+    for relative in [false, true] {
+        for (target, values) in assignments {
+            if matches!(target.base, Base::Pointer(_)) != relative {
+                continue;
+            }
+            let addresses = addresses(target, ram);
+            if addresses.end > RAM_SIZE.min(ram.len()) {
+                return Err(format!(
+                    "RAM[{}..{}] is outside RAM",
+                    addresses.start, addresses.end
+                ));
+            }
+            for (i, address) in addresses.enumerate() {
+                ram[address] = if values.len() == 1 {
+                    values[0]
+                } else {
+                    values[i]
+                };
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_count(target: &Target, values: &[i16]) -> Result<(), String> {
+    if values.len() != 1 && values.len() != target.cells.len() {
+        return Err(format!(
+            "{} values for {} cells",
+            values.len(),
+            target.cells.len()
+        ));
+    }
+    Ok(())
+}
+
+fn parse_text(source: &str) -> Result<Vec<(Target, Vec<i16>)>, String> {
+    let mut assignments = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", i + 1, message);
+        let line = line.split("//").next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (target, values) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected '<target> = <values>'".to_string()))?;
+        let target = parse_target(target).map_err(error)?;
+        let values = values
+            .split_whitespace()
+            .map(parse_value)
+            .collect::<Result<Vec<i16>, String>>()
+            .map_err(error)?;
+        check_count(&target, &values).map_err(error)?;
+        assignments.push((target, values));
+    }
+    Ok(assignments)
+}
+
+fn parse_json(source: &str) -> Result<Vec<(Target, Vec<i16>)>, String> {
+    let object: Map<String, Value> = serde_json::from_str(source).map_err(|err| err.to_string())?;
+    let mut assignments = Vec::new();
+    for (key, value) in object {
+        let error = |message: String| format!("'{}': {}", key, message);
+        let target = parse_target(&key).map_err(error)?;
+        let values = match &value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        let values = values
+            .iter()
+            .map(|value| match value.as_i64() {
+                Some(n) => parse_value(&n.to_string()),
+                None => Err(format!("invalid value '{}'", value)),
+            })
+            .collect::<Result<Vec<i16>, String>>()
+            .map_err(error)?;
+        check_count(&target, &values).map_err(error)?;
+        assignments.push((target, values));
+    }
+    Ok(assignments)
+}
+
+// set RAM from a text or JSON RAM file, told apart by a leading `{`
+pub fn load(source: &str, ram: &mut [i16]) -> Result<(), String> {
+    let assignments = if source.trim_start().starts_with('{') {
+        parse_json(source)?
+    } else {
+        parse_text(source)?
+    };
+    apply(&assignments, ram)
+}
+
+// the ranges to dump when none are asked for: runs of nonzero words after
+// the pointers, in lines of 16, with short runs of zeros kept inside them
+fn nonzero_ranges(ram: &[i16]) -> Vec<Range<usize>> {
+    const GAP: usize = 8;
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for address in (5..ram.len()).filter(|address| ram[*address] != 0) {
+        match runs.last_mut() {
+            Some(run) if address - run.end < GAP => run.end = address + 1,
+            _ => runs.push(address..address + 1),
+        }
+    }
+    runs.into_iter()
+        .flat_map(|run| {
+            run.clone()
+                .step_by(16)
+                .map(move |start| start..(start + 16).min(run.end))
+        })
+        .collect()
+}
+
+// the targets and values of a dump: the given ranges of RAM, or the
+// pointers and every nonzero run
+fn dump(ram: &[i16], ranges: &[Range<usize>]) -> Vec<(String, Vec<i16>)> {
+    let mut entries = Vec::new();
+    let ranges = if ranges.is_empty() {
+        for (i, name) in ["SP", "LCL", "ARG", "THIS", "THAT"].iter().enumerate() {
+            entries.push((name.to_string(), vec![ram[i]]));
+        }
+        nonzero_ranges(ram)
+    } else {
+        ranges.to_vec()
+    };
+    for range in ranges {
+        let target = if range.len() == 1 {
+            format!("RAM[{}]", range.start)
+        } else {
+            format!("RAM[{}..{}]", range.start, range.end)
+        };
+        entries.push((target, ram[range].to_vec()));
+    }
+    entries
+}
+
+// a dump as a RAM file that load() reads back
+pub fn dump_text(ram: &[i16], ranges: &[Range<usize>]) -> String {
+    dump(ram, ranges)
+        .into_iter()
+        .map(|(target, values)| {
+            let values: Vec<String> = values.iter().map(i16::to_string).collect();
+            format!("{} = {}\n", target, values.join(" "))
+        })
+        .collect()
+}
+
+// the JSON form, a target a line in address order
+pub fn dump_json(ram: &[i16], ranges: &[Range<usize>]) -> String {
+    let entries: Vec<String> = dump(ram, ranges)
+        .into_iter()
+        .map(|(target, values)| {
+            let value = match values[..] {
+                [value] => Value::from(value),
+                _ => Value::from(values),
+            };
+            format!("  {}: {}", Value::from(target), value)
+        })
+        .collect();
+    format!("{{\n{}\n}}\n", entries.join(",\n"))
+}
+
+// `a..b` ranges separated by commas, for --dump-range
+pub fn parse_ranges(ranges: &str) -> Result<Vec<Range<usize>>, String> {
+    ranges
+        .split(',')
+        .map(|range| {
+            let target = parse_target(&format!("RAM[{}]", range))?;
+            if target.cells.end > RAM_SIZE {
+                return Err(format!("{} is outside RAM", range));
+            }
+            Ok(target.cells)
+        })
+        .collect()
+}
@@ -0,0 +1,171 @@
+// The Hack screen and keyboard: the 512x256 screen is mapped at RAM[16384],
+// 32 words per row with the least significant bit leftmost, and the code of
+// the key being pressed is at RAM[24576]
+pub const SCREEN: usize = 16384;
+pub const KBD: usize = 24576;
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+
+// true when the pixel at x, y is black
+pub fn pixel(ram: &[i16], x: usize, y: usize) -> bool {
+    let word = ram[SCREEN + y * WIDTH / 16 + x / 16] as u16;
+    word & (1 << (x % 16)) != 0
+}
+
+// binary PBM (P4): rows packed 8 pixels a byte, most significant bit first,
+// 1 is black
+pub fn to_pbm(ram: &[i16]) -> Vec<u8> {
+    let mut image = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    for y in 0..HEIGHT {
+        for x in (0..WIDTH).step_by(8) {
+            let byte = (0..8).fold(0, |byte, bit| byte << 1 | u8::from(pixel(ram, x + bit, y)));
+            image.push(byte);
+        }
+    }
+    image
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// 1-bit grayscale PNG, white background. The image data is zlib with
+// uncompressed deflate blocks, which keeps the encoder small
+pub fn to_png(ram: &[i16]) -> Vec<u8> {
+    // each row: filter type 0, then the PBM row inverted (PNG 1 is white)
+    let pbm = to_pbm(ram);
+    let rows = &pbm[pbm.len() - WIDTH / 8 * HEIGHT..];
+    let mut raw = Vec::with_capacity(rows.len() + HEIGHT);
+    for row in rows.chunks(WIDTH / 8) {
+        raw.push(0);
+        raw.extend(row.iter().map(|byte| !byte));
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = raw.chunks(0xFFFF).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push(u8::from(i == blocks.len() - 1));
+        let length = block.len() as u16;
+        zlib.extend(length.to_le_bytes());
+        zlib.extend((!length).to_le_bytes());
+        zlib.extend(*block);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    // bit depth 1, grayscale, deflate, no filtering, no interlace
+    header.extend([1, 0, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &zlib);
+    png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// the keyboard code of a key: a single printable character, a name from the
+// course's key table (case insensitive) or a number
+pub fn key_code(key: &str) -> Option<i16> {
+    let named = [
+        "newline",
+        "backspace",
+        "left",
+        "up",
+        "right",
+        "down",
+        "home",
+        "end",
+        "pageup",
+        "pagedown",
+        "insert",
+        "delete",
+        "esc",
+    ];
+    let lower = key.to_ascii_lowercase();
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if (' '..='~').contains(&c) => return Some(c as i16),
+        _ => {}
+    }
+    if let Some(i) = named.iter().position(|name| *name == lower) {
+        return Some(128 + i as i16);
+    }
+    if lower == "space" {
+        return Some(32);
+    }
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<i16>().ok()) {
+        return Some(140 + n).filter(|_| (1..=12).contains(&n));
+    }
+    key.parse().ok()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    Press(i16),
+    Release,
+    Screenshot,
+}
+
+// `<cycle> <action>` lines: a key to hold down, `release` or `screenshot`.
+// Events are returned in cycle order
+pub fn parse_key_script(source: &str) -> Result<Vec<(u64, KeyAction)>, String> {
+    let mut events = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let words: Vec<&str> = line
+            .split("//")
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let error = |message: &str| format!("line {}: {}", i + 1, message);
+        let (cycle, action) = match words[..] {
+            [] => continue,
+            [cycle, action] => (cycle, action),
+            _ => return Err(error("expected '<cycle> <key|release|screenshot>'")),
+        };
+        let cycle = cycle
+            .parse()
+            .map_err(|_| error(&format!("invalid cycle '{}'", cycle)))?;
+        let action = match action {
+            "release" => KeyAction::Release,
+            "screenshot" => KeyAction::Screenshot,
+            key => KeyAction::Press(
+                key_code(key).ok_or_else(|| error(&format!("unknown key '{}'", key)))?,
+            ),
+        };
+        events.push((cycle, action));
+    }
+    events.sort_by_key(|(cycle, _)| *cycle);
+    Ok(events)
+}
@@ -0,0 +1,421 @@
+// The part of the course's CPU emulator test scripts (.tst) used by
+// test_files: `load`, `output-file`, `compare-to`, `output-list`, `set RAM[n] v`,
+// `ticktock`, `repeat n { ... }` and `output`. Running a script gives the
+// text the course emulator would write to its .out file.
+use crate::emulator::{Emulator, RAM_SIZE};
+use std::fmt;
+
+// one `output-list` entry such as `RAM[256]%D1.6.1`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub address: usize,
+    // D, X or B
+    pub format: char,
+    pub left: usize,
+    pub width: usize,
+    pub right: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Set(usize, i16),
+    Ticktock,
+    Repeat(u64, Vec<Step>),
+    Output,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestScript {
+    pub load: Option<String>,
+    pub output_file: Option<String>,
+    pub compare_to: Option<String>,
+    pub output_list: Vec<Column>,
+    pub steps: Vec<Step>,
+}
+
+// a script line that couldn't be understood, lines start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+// words and the `,` `;` `{` `}` punctuation, each with its line
+fn tokenize(source: &str) -> Vec<(usize, String)> {
+    let mut tokens = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or_default();
+        let mut word = String::new();
+        for c in line.chars() {
+            if c.is_whitespace() || ",;{}".contains(c) {
+                if !word.is_empty() {
+                    tokens.push((i + 1, std::mem::take(&mut word)));
+                }
+                if !c.is_whitespace() {
+                    tokens.push((i + 1, c.to_string()));
+                }
+            } else {
+                word.push(c);
+            }
+        }
+        if !word.is_empty() {
+            tokens.push((i + 1, word));
+        }
+    }
+    tokens
+}
+
+// `RAM[n]`, the only kind of variable test_files use
+fn ram_address(name: &str) -> Option<usize> {
+    let address = name.strip_prefix("RAM[")?.strip_suffix(']')?.parse().ok()?;
+    Some(address).filter(|address| *address < RAM_SIZE)
+}
+
+fn column(spec: &str) -> Option<Column> {
+    let (name, format) = spec.split_once('%')?;
+    let mut chars = format.chars();
+    let kind = chars.next().filter(|kind| "DXB".contains(*kind))?;
+    let sizes: Vec<usize> = chars
+        .as_str()
+        .split('.')
+        .map(|size| size.parse().ok())
+        .collect::<Option<_>>()?;
+    match sizes[..] {
+        [left, width, right] => Some(Column {
+            name: name.to_string(),
+            address: ram_address(name)?,
+            format: kind,
+            left,
+            width,
+            right,
+        }),
+        _ => None,
+    }
+}
+
+struct ScriptParser {
+    tokens: Vec<(usize, String)>,
+    position: usize,
+}
+
+impl ScriptParser {
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map_or(1, |(line, _)| *line)
+    }
+
+    fn error(&self, message: &str) -> ScriptError {
+        ScriptError {
+            line: self.line(),
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .get(self.position)
+            .map(|(_, token)| token.as_str())
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position)?.1.clone();
+        self.position += 1;
+        Some(token)
+    }
+
+    // the words of a statement, up to and including its `,` or `;`
+    fn words(&mut self) -> Result<Vec<String>, ScriptError> {
+        let mut words = Vec::new();
+        loop {
+            match self.next() {
+                Some(token) if token == "," || token == ";" => return Ok(words),
+                Some(token) if token == "{" || token == "}" => {
+                    return Err(self.error(&format!("unexpected '{}'", token)))
+                }
+                Some(token) => words.push(token),
+                None => return Err(self.error("missing ',' or ';'")),
+            }
+        }
+    }
+
+    // statements until the end of the script, or the `}` closing a repeat
+    fn statements(
+        &mut self,
+        script: &mut TestScript,
+        nested: bool,
+    ) -> Result<Vec<Step>, ScriptError> {
+        let mut steps = Vec::new();
+        loop {
+            match self.peek() {
+                None if nested => return Err(self.error("missing '}'")),
+                None => return Ok(steps),
+                Some("}") if nested => {
+                    self.position += 1;
+                    return Ok(steps);
+                }
+                Some("repeat") => {
+                    self.position += 1;
+                    let count = self.next().and_then(|count| count.parse().ok());
+                    let count = count.ok_or_else(|| self.error("repeat needs a count"))?;
+                    if self.next().as_deref() != Some("{") {
+                        return Err(self.error("missing '{' after repeat"));
+                    }
+                    let body = self.statements(script, true)?;
+                    steps.push(Step::Repeat(count, body));
+                }
+                Some(_) => {
+                    let line = self.line();
+                    let words = self.words()?;
+                    let error = |message: String| ScriptError { line, message };
+                    let words: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+                    match words[..] {
+                        ["load", file] => script.load = Some(file.to_string()),
+                        ["output-file", file] => script.output_file = Some(file.to_string()),
+                        ["compare-to", file] => script.compare_to = Some(file.to_string()),
+                        ["output-list", ref specs @ ..] => {
+                            for spec in specs {
+                                let column = column(spec).ok_or_else(|| {
+                                    error(format!("unsupported output '{}'", spec))
+                                })?;
+                                script.output_list.push(column);
+                            }
+                        }
+                        ["set", name, value] => {
+                            let address = ram_address(name)
+                                .ok_or_else(|| error(format!("unsupported variable '{}'", name)))?;
+                            let value = value
+                                .parse()
+                                .map_err(|_| error(format!("invalid value '{}'", value)))?;
+                            steps.push(Step::Set(address, value));
+                        }
+                        ["ticktock"] => steps.push(Step::Ticktock),
+                        ["output"] => steps.push(Step::Output),
+                        _ => {
+                            return Err(error(format!("unsupported command '{}'", words.join(" "))))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl TestScript {
+    pub fn parse(source: &str) -> Result<TestScript, ScriptError> {
+        let mut parser = ScriptParser {
+            tokens: tokenize(source),
+            position: 0,
+        };
+        let mut script = TestScript::default();
+        script.steps = parser.statements(&mut script, false)?;
+        Ok(script)
+    }
+
+    fn header(&self) -> String {
+        let mut line = "|".to_string();
+        for column in &self.output_list {
+            let total = column.left + column.width + column.right;
+            let name: String = column.name.chars().take(total).collect();
+            let before = (total - name.len()) / 2;
+            line += &format!(
+                "{:before$}{}{:after$}|",
+                "",
+                name,
+                "",
+                after = total - name.len() - before
+            );
+        }
+        line
+    }
+
+    fn row(&self, emulator: &Emulator) -> String {
+        let mut line = "|".to_string();
+        for column in &self.output_list {
+            let value = emulator.ram[column.address];
+            let value = match column.format {
+                'X' => format!("{:0width$X}", value as u16, width = column.width),
+                'B' => format!("{:0width$b}", value as u16, width = column.width),
+                _ => value.to_string(),
+            };
+            line += &format!(
+                "{:left$}{:>width$}{:right$}|",
+                "",
+                value,
+                "",
+                left = column.left,
+                width = column.width,
+                right = column.right
+            );
+        }
+        line
+    }
+
+    fn run_steps(&self, steps: &[Step], emulator: &mut Emulator, output: &mut Vec<String>) {
+        for step in steps {
+            match step {
+                Step::Set(address, value) => emulator.ram[*address] = *value,
+                // once halted the program only spins in place, so generous
+                // `repeat` counts cost nothing
+                Step::Ticktock if emulator.is_running() && !emulator.is_halted() => emulator.step(),
+                Step::Ticktock => {}
+                Step::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.run_steps(body, emulator, output);
+                    }
+                }
+                Step::Output => output.push(self.row(emulator)),
+            }
+        }
+    }
+
+    // run the script against an emulator with the program loaded, returning
+    // the lines of its output: the header, then a row for each `output`
+    pub fn run(&self, emulator: &mut Emulator) -> Vec<String> {
+        let mut output = vec![self.header()];
+        self.run_steps(&self.steps, emulator, &mut output);
+        output
+    }
+
+    // compare_output, with the first output row that differs shown as a
+    // table of the expected and actual value of each column and its RAM
+    // address
+    pub fn compare(&self, output: &[String], expected: &str) -> Result<(), ScriptError> {
+        let error = match compare_output(output, expected) {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        let expected: Vec<&str> = expected
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        // the header, or a row missing on one side, stays a one-line message
+        let row = error.line - 1;
+        let (Some(expected_row), Some(actual_row)) = (expected.get(row), output.get(row)) else {
+            return Err(error);
+        };
+        let (expected_cells, actual_cells) = (cells(expected_row), cells(actual_row));
+        if row == 0
+            || expected_cells.len() != self.output_list.len()
+            || actual_cells.len() != self.output_list.len()
+        {
+            return Err(error);
+        }
+
+        let differing = (1..expected.len().max(output.len()))
+            .filter(|&i| {
+                let actual = output.get(i).map_or("", |line| line.as_str());
+                !line_matches(expected.get(i).unwrap_or(&""), actual)
+            })
+            .count();
+        let mut table = vec![[
+            "column".to_string(),
+            "address".to_string(),
+            "expected".to_string(),
+            "actual".to_string(),
+        ]];
+        for (column, (expected, actual)) in self
+            .output_list
+            .iter()
+            .zip(expected_cells.iter().zip(&actual_cells))
+        {
+            table.push([
+                column.name.clone(),
+                column.address.to_string(),
+                expected.to_string(),
+                actual.to_string(),
+            ]);
+        }
+        let widths: Vec<usize> = (0..4)
+            .map(|i| table.iter().map(|row| row[i].len()).max().unwrap_or(0))
+            .collect();
+        let rows = expected.len().max(output.len()) - 1;
+        let mut message = format!(
+            "output row {} is the first to differ ({} of {} rows differ)",
+            row, differing, rows
+        );
+        for (i, cells) in table.iter().enumerate() {
+            let marker = match i {
+                0 => "",
+                _ if cell_matches(&cells[2], &cells[3]) => "",
+                _ => "  <",
+            };
+            message += &format!(
+                "\n    {:<w0$}  {:>w1$}  {:>w2$}  {:>w3$}{}",
+                cells[0],
+                cells[1],
+                cells[2],
+                cells[3],
+                marker,
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3]
+            );
+        }
+        Err(ScriptError {
+            line: error.line,
+            message,
+        })
+    }
+}
+
+// the cells of a `|a|b|` line
+fn cells(line: &str) -> Vec<&str> {
+    line.trim()
+        .trim_matches('|')
+        .split('|')
+        .map(|cell| cell.trim())
+        .collect()
+}
+
+fn is_wildcard(cell: &str) -> bool {
+    !cell.is_empty() && cell.chars().all(|c| c == '*')
+}
+
+fn cell_matches(expected: &str, actual: &str) -> bool {
+    is_wildcard(expected) || expected == actual
+}
+
+fn line_matches(expected: &str, actual: &str) -> bool {
+    cells(expected).len() == cells(actual).len()
+        && cells(expected)
+            .iter()
+            .zip(cells(actual))
+            .all(|(expected, actual)| cell_matches(expected, actual))
+}
+
+// compare output with the .cmp file cell by cell, `*` cells match anything.
+// Returns the first line that differs, numbered from 1
+pub fn compare_output(output: &[String], expected: &str) -> Result<(), ScriptError> {
+    let expected: Vec<&str> = expected
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    for (i, line) in expected.iter().enumerate() {
+        let actual = output.get(i).map_or("", |line| line.as_str());
+        if !line_matches(line, actual) {
+            return Err(ScriptError {
+                line: i + 1,
+                message: format!("expected {} but got {}", line.trim(), actual),
+            });
+        }
+    }
+    if output.len() > expected.len() {
+        return Err(ScriptError {
+            line: expected.len() + 1,
+            message: format!("unexpected output {}", output[expected.len()]),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+// Execution traces for `hack_vm run --trace`, meant to be diffed between
+// translator versions. Instruction traces have a line per instruction; VM
+// traces have a line per VM command, from the program's source map, with
+// the stack and segment pointers as the command starts. VM traces leave out
+// cycle counts and ROM addresses, so they only differ where behavior does.
+use crate::assembler::{instruction_text, Program};
+use crate::emulator::{Emulator, RAM_SIZE};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceMode {
+    Instructions,
+    Vm,
+}
+
+// `cycle pc instruction A D` and the RAM cell written, if any
+fn instruction_line(emulator: &Emulator, pc: u16, old_a: i16) -> String {
+    let instruction = emulator.rom[usize::from(pc)];
+    let mut line = format!(
+        "{} {} {} A={} D={}",
+        emulator.cycles,
+        pc,
+        instruction_text(instruction),
+        emulator.a,
+        emulator.d
+    );
+    let writes_memory = instruction & 0x8000 != 0 && instruction & 0b001_000 != 0;
+    if writes_memory {
+        let address = usize::from(old_a as u16) % RAM_SIZE;
+        line += &format!(" RAM[{}]={}", address, emulator.ram[address]);
+    }
+    line
+}
+
+// `step command SP LCL ARG THIS THAT`
+fn vm_line(emulator: &Emulator, step: u64, command: &str) -> String {
+    let ram = &emulator.ram;
+    format!(
+        "{} {} SP={} LCL={} ARG={} THIS={} THAT={}",
+        step, command, ram[0], ram[1], ram[2], ram[3], ram[4]
+    )
+}
+
+// run like Emulator::run, writing a trace line for each instruction or VM
+// command. Returns false when it ran out of cycles
+pub fn run_traced(
+    emulator: &mut Emulator,
+    program: &Program,
+    max_cycles: u64,
+    mode: TraceMode,
+    out: &mut impl Write,
+) -> io::Result<bool> {
+    // commands without code of their own, like labels, share an address
+    // with the next one
+    let mut commands: HashMap<u16, Vec<&str>> = HashMap::new();
+    for (address, command) in &program.source_map {
+        commands.entry(*address).or_default().push(command);
+    }
+    if mode == TraceMode::Vm && commands.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the program has no source map, translate it with --source-map",
+        ));
+    }
+    let limit = emulator.cycles + max_cycles;
+    let mut steps = 0;
+    while emulator.is_running() && !emulator.is_halted() {
+        if emulator.cycles >= limit {
+            return Ok(false);
+        }
+        let pc = emulator.pc;
+        match mode {
+            TraceMode::Instructions => {
+                let old_a = emulator.a;
+                emulator.step();
+                writeln!(out, "{}", instruction_line(emulator, pc, old_a))?;
+            }
+            TraceMode::Vm => {
+                for command in commands.get(&pc).into_iter().flatten() {
+                    steps += 1;
+                    writeln!(out, "{}", vm_line(emulator, steps, command))?;
+                }
+                emulator.step();
+            }
+        }
+    }
+    Ok(true)
+}
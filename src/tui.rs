@@ -0,0 +1,218 @@
+// `hack_vm run --tui`: the screen drawn in the terminal with braille or
+// half-block characters, terminal keys fed to the keyboard register
+use crate::assembler::Program;
+use crate::emulator::Emulator;
+use crate::screen::{pixel, HEIGHT, KBD, WIDTH};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    // 2x4 pixels a character
+    Braille,
+    // 1x2 pixels a character
+    Blocks,
+}
+
+// terminals only report presses, so a key counts as held until this long
+// after its last press or auto-repeat
+const KEY_HOLD: Duration = Duration::from_millis(150);
+const FRAME: Duration = Duration::from_millis(33);
+// cycles run between checks of the clock
+const BATCH: u64 = 10_000;
+
+// a dot is black when any pixel of the `scale` x `scale` block it covers is
+fn dot(ram: &[i16], x: usize, y: usize, scale: usize) -> bool {
+    (0..scale).any(|dy| (0..scale).any(|dx| pixel(ram, x * scale + dx, y * scale + dy)))
+}
+
+// the screen as lines of text, shrunk by `scale` in both directions
+pub fn render(ram: &[i16], charset: Charset, scale: usize) -> Vec<String> {
+    let (width, height) = (WIDTH / scale, HEIGHT / scale);
+    let (cell_width, cell_height) = match charset {
+        Charset::Braille => (2, 4),
+        Charset::Blocks => (1, 2),
+    };
+    let black = |x: usize, y: usize| x < width && y < height && dot(ram, x, y, scale);
+    (0..height.div_ceil(cell_height))
+        .map(|row| {
+            (0..width.div_ceil(cell_width))
+                .map(|column| {
+                    let (x, y) = (column * cell_width, row * cell_height);
+                    match charset {
+                        Charset::Braille => {
+                            // dots 1-3 and 7 down the left column, 4-6 and 8 down the right
+                            let bits = [
+                                (0, 0, 0x01),
+                                (0, 1, 0x02),
+                                (0, 2, 0x04),
+                                (1, 0, 0x08),
+                                (1, 1, 0x10),
+                                (1, 2, 0x20),
+                                (0, 3, 0x40),
+                                (1, 3, 0x80),
+                            ];
+                            let pattern = bits
+                                .iter()
+                                .filter(|(dx, dy, _)| black(x + dx, y + dy))
+                                .fold(0, |pattern, (_, _, bit)| pattern | bit);
+                            char::from_u32(0x2800 + pattern).unwrap_or(' ')
+                        }
+                        Charset::Blocks => match (black(x, y), black(x, y + 1)) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        },
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// the Hack keyboard code of a terminal key
+pub fn key_code(code: KeyCode) -> Option<i16> {
+    match code {
+        KeyCode::Char(c) if (' '..='~').contains(&c) => Some(c as i16),
+        KeyCode::Enter => Some(128),
+        KeyCode::Backspace => Some(129),
+        KeyCode::Left => Some(130),
+        KeyCode::Up => Some(131),
+        KeyCode::Right => Some(132),
+        KeyCode::Down => Some(133),
+        KeyCode::Home => Some(134),
+        KeyCode::End => Some(135),
+        KeyCode::PageUp => Some(136),
+        KeyCode::PageDown => Some(137),
+        KeyCode::Insert => Some(138),
+        KeyCode::Delete => Some(139),
+        KeyCode::Esc => Some(140),
+        KeyCode::F(n) if (1..=12).contains(&n) => Some(140 + i16::from(n)),
+        _ => None,
+    }
+}
+
+// the VM function the code at `pc` was translated from: the last
+// `Class.name` label before it, skipping `f$label` and return labels
+pub fn current_function(program: &Program, pc: u16) -> Option<&str> {
+    program.label_before(pc, |label| label.contains('.') && !label.contains('$'))
+}
+
+// the smallest scale that fits the screen in `columns` x `rows` characters
+pub fn fit_scale(charset: Charset, columns: usize, rows: usize) -> usize {
+    let (cell_width, cell_height) = match charset {
+        Charset::Braille => (2, 4),
+        Charset::Blocks => (1, 2),
+    };
+    (1..)
+        .find(|scale| WIDTH / scale / cell_width <= columns && HEIGHT / scale / cell_height <= rows)
+        .unwrap_or(1)
+}
+
+// puts the terminal back however run() ends
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        _ = terminal::disable_raw_mode();
+    }
+}
+
+fn draw(lines: &[String], status: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    queue!(stdout, cursor::MoveTo(0, 0))?;
+    for line in lines {
+        write!(stdout, "{}\r\n", line)?;
+    }
+    queue!(stdout, terminal::Clear(terminal::ClearType::CurrentLine))?;
+    write!(stdout, "{}", status)?;
+    stdout.flush()
+}
+
+// run until ctrl-c, or `max_cycles` when given, redrawing about 30 times a
+// second. `scale` 0 fits the screen to the terminal
+pub fn run(
+    program: &Program,
+    charset: Charset,
+    scale: usize,
+    max_cycles: Option<u64>,
+) -> io::Result<Emulator> {
+    let mut emulator = Emulator::new(program.rom.clone());
+    let _terminal = RawTerminal::enter()?;
+    let (columns, rows) = terminal::size()?;
+    let scale = match scale {
+        0 => fit_scale(
+            charset,
+            usize::from(columns),
+            usize::from(rows).saturating_sub(1),
+        ),
+        scale => scale,
+    };
+
+    let mut released_at = Instant::now();
+    let mut rate_since = (Instant::now(), 0);
+    let mut rate = 0.0;
+    loop {
+        let frame_end = Instant::now() + FRAME;
+        let mut running = emulator.is_running() && !emulator.is_halted();
+        while running && Instant::now() < frame_end {
+            let batch = max_cycles.map_or(BATCH, |max| BATCH.min(max - emulator.cycles));
+            running = !emulator.run(batch) && max_cycles != Some(emulator.cycles);
+        }
+
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Release {
+                    continue;
+                }
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    return Ok(emulator);
+                }
+                if let Some(code) = key_code(key.code) {
+                    emulator.ram[KBD] = code;
+                    released_at = Instant::now() + KEY_HOLD;
+                }
+            }
+        }
+        if Instant::now() > released_at {
+            emulator.ram[KBD] = 0;
+        }
+
+        let elapsed = rate_since.0.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            rate = (emulator.cycles - rate_since.1) as f64 / elapsed.as_secs_f64() / 1e6;
+            rate_since = (Instant::now(), emulator.cycles);
+        }
+        let state = if running {
+            "running".to_string()
+        } else if emulator.is_halted() {
+            format!("halted at ROM[{}]", emulator.pc)
+        } else {
+            "stopped".to_string()
+        };
+        let status = format!(
+            "{} cycles  {:.1} MHz  {}  in {}  (ctrl-c to quit)",
+            emulator.cycles,
+            rate,
+            state,
+            current_function(program, emulator.pc).unwrap_or("?")
+        );
+        draw(&render(&emulator.ram, charset, scale), &status)?;
+        if !running {
+            // keep showing the last frame until ctrl-c
+            std::thread::sleep(FRAME);
+        }
+    }
+}
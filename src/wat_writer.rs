@@ -0,0 +1,468 @@
+// WebAssembly backend: a VM program as a WebAssembly text module, to run
+// programs in the browser. RAM is the module's linear memory, one 16-bit
+// word per cell, laid out as on the Hack machine with statics numbered from
+// RAM[16] by first use. Each VM function is a wasm function that keeps its
+// frame in RAM like the Hack code does, pushing 0 for the return address as
+// the interpreter does, so a run leaves RAM as the interpreter would.
+//
+// Labels split a function into blocks nested in a loop: a jump forward
+// breaks out to its block, a jump back goes through a br_table at the top
+// of the loop. A function that ends without returning falls through into
+// the next one, as in the Hack code.
+//
+// The module exports `memory`, `run(limit: i64) -> i32` and `steps() ->
+// i64`. run counts VM commands the way the interpreter does and returns 1
+// once the program halts (`label X` `goto X`, Sys.init returning or the end
+// of the code) or 2 once `limit` commands have run, -1 for no limit. div
+// and mod by zero trap
+use crate::backend::Backend;
+use crate::compiler::VmFile;
+use crate::parser::EXTENDED_COMMANDS;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind, Write};
+
+const PRELUDE: &str = r#"(module
+  (memory (export "memory") 1)
+  (global $state (mut i32) (i32.const 0))
+  (global $count (mut i64) (i64.const 0))
+  (global $limit (mut i64) (i64.const -1))
+
+  (func $get (param $address i32) (result i32)
+    (i32.load16_s (i32.shl (i32.and (local.get $address) (i32.const 0x7FFF)) (i32.const 1))))
+  (func $set (param $address i32) (param $value i32)
+    (i32.store16
+      (i32.shl (i32.and (local.get $address) (i32.const 0x7FFF)) (i32.const 1))
+      (local.get $value)))
+  (func $push (param $value i32)
+    (call $set (call $get (i32.const 0)) (local.get $value))
+    (call $set (i32.const 0) (i32.add (call $get (i32.const 0)) (i32.const 1))))
+  (func $pop (result i32)
+    (call $set (i32.const 0) (i32.sub (call $get (i32.const 0)) (i32.const 1)))
+    (call $get (call $get (i32.const 0))))
+  ;; counts a command, or stops the run once `limit` have run
+  (func $stop (result i32)
+    (if (i64.eq (global.get $count) (global.get $limit))
+      (then (global.set $state (i32.const 2)) (return (i32.const 1))))
+    (global.set $count (i64.add (global.get $count) (i64.const 1)))
+    (i32.const 0))
+  (func (export "steps") (result i64)
+    (global.get $count))
+"#;
+
+const STEP: &str = "(if (call $stop) (then (return)))";
+
+// a line of a function's code, jumps are resolved once the function's
+// labels are all known
+enum Code {
+    Line(String),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+}
+
+struct Function {
+    // the wasm name without the `$`: `top`, or `f_` and the VM name
+    name: String,
+    code: Vec<Code>,
+}
+
+pub struct WatWriter {
+    output_file: VmFile,
+    // the body of run(), the bootstrap or the test-mode pointers
+    run: Vec<String>,
+    // `top` for the commands before the first function, then one per VM
+    // function
+    functions: Vec<Function>,
+    filename: Option<String>,
+    extended: bool,
+    is_test: bool,
+    statics: HashMap<String, i16>,
+    calls: Vec<String>,
+    // the label just written, `goto` to it is the halt loop
+    last_label: Option<String>,
+}
+
+// the cell at `address`, an i32 expression
+fn get(address: &str) -> String {
+    format!("(call $get {})", address)
+}
+
+impl WatWriter {
+    // like CodeWriter::new, the bootstrap is left out for test programs
+    pub fn new(file: VmFile, is_test: bool) -> io::Result<Self> {
+        let mut wat_writer = WatWriter {
+            output_file: file,
+            run: Vec::new(),
+            functions: vec![Function {
+                name: "top".to_string(),
+                code: Vec::new(),
+            }],
+            filename: None,
+            extended: false,
+            is_test,
+            statics: HashMap::new(),
+            calls: Vec::new(),
+            last_label: None,
+        };
+        if !is_test {
+            // SP = 256 and call Sys.init, without counting it as a command
+            let mut bootstrap = vec!["(call $set (i32.const 0) (i32.const 256))".to_string()];
+            bootstrap.extend(wat_writer.frame("Sys.init", 0));
+            wat_writer.run.extend(bootstrap);
+        }
+        Ok(wat_writer)
+    }
+
+    // allow the extended instruction set (mul, div, mod, shl, shr, xor)
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
+    }
+
+    fn code(&mut self, code: Code) {
+        let function = self.functions.last_mut().expect("top is always there");
+        function.code.push(code);
+    }
+
+    fn line(&mut self, line: String) {
+        self.code(Code::Line(line));
+    }
+
+    // a command's code, after its VM text as a comment
+    fn command(&mut self, command: &str, lines: &[String]) {
+        self.line(format!(";; {}", command));
+        self.line(STEP.to_string());
+        for line in lines {
+            self.line(line.clone());
+        }
+        self.last_label = None;
+    }
+
+    // the RAM address of `segment index`
+    fn address(&mut self, segment: &str, index: i16) -> io::Result<String> {
+        let pointer = |register: usize| {
+            format!(
+                "(i32.add {} (i32.const {}))",
+                get(&format!("(i32.const {})", register)),
+                index
+            )
+        };
+        Ok(match segment {
+            "local" => pointer(1),
+            "argument" => pointer(2),
+            "this" => pointer(3),
+            "that" => pointer(4),
+            "pointer" if (0..2).contains(&index) => format!("(i32.const {})", 3 + index),
+            "temp" if (0..8).contains(&index) => format!("(i32.const {})", 5 + index),
+            "static" => {
+                let name = format!("{}.{}", self.filename.as_deref().unwrap_or(""), index);
+                let next = 16 + self.statics.len() as i16;
+                format!("(i32.const {})", self.statics.entry(name).or_insert(next))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid segment {} {}", segment, index),
+                ))
+            }
+        })
+    }
+
+    // push the return address and the caller's frame, then call
+    fn frame(&mut self, function_name: &str, nargs: i16) -> Vec<String> {
+        self.calls.push(function_name.to_string());
+        let mut lines = vec!["(call $push (i32.const 0))".to_string()];
+        for register in 1..=4 {
+            lines.push(format!(
+                "(call $push {})",
+                get(&format!("(i32.const {})", register))
+            ));
+        }
+        lines.push(format!(
+            "(call $set (i32.const 2) (i32.sub {} (i32.const {})))",
+            get("(i32.const 0)"),
+            5 + i32::from(nargs)
+        ));
+        lines.push(format!(
+            "(call $set (i32.const 1) {})",
+            get("(i32.const 0)")
+        ));
+        lines.push(format!("(call $f_{})", function_name));
+        lines
+    }
+
+    // a function as wasm, `next` is the function it falls through to
+    fn write_function_code(&mut self, function: &Function, next: Option<&str>) -> io::Result<()> {
+        let labels: Vec<&str> = function
+            .code
+            .iter()
+            .filter_map(|code| match code {
+                Code::Label(label) => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+        let mut blocks = HashMap::new();
+        for (i, label) in labels.iter().enumerate() {
+            if blocks.insert(*label, i + 1).is_some() {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("label {} defined twice in {}", label, function.name),
+                ));
+            }
+        }
+        let jump = |label: &str, block: usize| match blocks.get(label) {
+            // forward, out to the end of the label's block
+            Some(target) if *target > block => Ok(format!("(br $l_{})", label)),
+            Some(target) => Ok(format!(
+                "(local.set $block (i32.const {})) (br $dispatch)",
+                target
+            )),
+            None => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("goto to undefined label {} in {}", label, function.name),
+            )),
+        };
+
+        let file = &mut self.output_file.file;
+        writeln!(file, "\n  (func ${}", function.name)?;
+        writeln!(
+            file,
+            "    (local $y i32) (local $frame i32) (local $block i32)"
+        )?;
+        let indent = if labels.is_empty() { "    " } else { "      " };
+        if !labels.is_empty() {
+            writeln!(file, "    (loop $dispatch")?;
+            for label in labels.iter().rev() {
+                writeln!(file, "    (block $l_{}", label)?;
+            }
+            let targets: Vec<String> = labels.iter().map(|label| format!("$l_{}", label)).collect();
+            writeln!(
+                file,
+                "    (block $entry (br_table $entry {} (local.get $block)))",
+                targets.join(" ")
+            )?;
+        }
+        let mut block = 0;
+        for code in &function.code {
+            match code {
+                Code::Line(line) => writeln!(file, "{}{}", indent, line)?,
+                Code::Label(_) => {
+                    block += 1;
+                    writeln!(file, "    )")?;
+                }
+                Code::Goto(label) => writeln!(file, "{}{}", indent, jump(label, block)?)?,
+                Code::IfGoto(label) => writeln!(
+                    file,
+                    "{}(if (call $pop) (then {}))",
+                    indent,
+                    jump(label, block)?
+                )?,
+            }
+        }
+        if !labels.is_empty() {
+            writeln!(file, "    )")?;
+        }
+        match next {
+            Some(next) => writeln!(file, "    (call ${}))", next),
+            None => writeln!(file, "    (global.set $state (i32.const 1)))"),
+        }
+    }
+}
+
+impl Backend for WatWriter {
+    fn set_file_name(&mut self, filename: &str) {
+        self.filename = Some(filename.to_string());
+    }
+
+    // the pointers CodeWriter sets up for test programs
+    fn init_stack(&mut self) -> io::Result<()> {
+        for (register, value) in [256, 456, 756, 1056, 1356].iter().enumerate() {
+            self.run.push(format!(
+                "(call $set (i32.const {}) (i32.const {}))",
+                register, value
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_push(&mut self, segment: &str, index: i16) -> io::Result<()> {
+        let value = match segment {
+            "constant" => format!("(i32.const {})", index),
+            segment => get(&self.address(segment, index)?),
+        };
+        self.command(
+            &format!("push {} {}", segment, index),
+            &[format!("(call $push {})", value)],
+        );
+        Ok(())
+    }
+
+    fn write_pop(&mut self, segment: &str, index: i16) -> io::Result<()> {
+        let address = self.address(segment, index)?;
+        self.command(
+            &format!("pop {} {}", segment, index),
+            &[format!("(call $set {} (call $pop))", address)],
+        );
+        Ok(())
+    }
+
+    fn write_arithmetic(&mut self, command: &str) -> io::Result<()> {
+        let unary = match command {
+            "neg" => Some("(call $push (i32.sub (i32.const 0) (call $pop)))"),
+            "not" => Some("(call $push (i32.xor (call $pop) (i32.const -1)))"),
+            _ => None,
+        };
+        if let Some(code) = unary {
+            self.command(command, &[code.to_string()]);
+            return Ok(());
+        }
+        // x and y come sign-extended from memory and go back through store16,
+        // which wraps the result to 16 bits
+        let value = match command {
+            "add" => "(i32.add X Y)",
+            "sub" => "(i32.sub X Y)",
+            "eq" => "(i32.sub (i32.const 0) (i32.eq X Y))",
+            "gt" => "(i32.sub (i32.const 0) (i32.gt_s X Y))",
+            "lt" => "(i32.sub (i32.const 0) (i32.lt_s X Y))",
+            "and" => "(i32.and X Y)",
+            "or" => "(i32.or X Y)",
+            command if !self.extended || !EXTENDED_COMMANDS.contains(&command) => {
+                return Err(io::Error::new(ErrorKind::InvalidInput, command.to_string()))
+            }
+            "mul" => "(i32.mul X Y)",
+            "div" => "(i32.div_s X Y)",
+            "mod" => "(i32.rem_s X Y)",
+            "shl" => "(i32.shl X (i32.and Y (i32.const 15)))",
+            "shr" => "(i32.shr_s X (i32.and Y (i32.const 15)))",
+            _ => "(i32.xor X Y)",
+        };
+        let value = value
+            .replace('X', "(call $pop)")
+            .replace('Y', "(local.get $y)");
+        self.command(
+            command,
+            &[
+                "(local.set $y (call $pop))".to_string(),
+                format!("(call $push {})", value),
+            ],
+        );
+        Ok(())
+    }
+
+    fn write_label(&mut self, label: &str) -> io::Result<()> {
+        self.code(Code::Label(label.to_string()));
+        self.command(&format!("label {}", label), &[]);
+        self.last_label = Some(label.to_string());
+        Ok(())
+    }
+
+    fn write_goto(&mut self, label: &str) -> io::Result<()> {
+        if self.last_label.as_deref() == Some(label) {
+            self.line(format!(";; goto {}", label));
+            self.line("(global.set $state (i32.const 1)) (return)".to_string());
+            self.last_label = None;
+            return Ok(());
+        }
+        self.command(&format!("goto {}", label), &[]);
+        self.code(Code::Goto(label.to_string()));
+        Ok(())
+    }
+
+    fn write_ifgoto(&mut self, label: &str) -> io::Result<()> {
+        self.command(&format!("if-goto {}", label), &[]);
+        self.code(Code::IfGoto(label.to_string()));
+        Ok(())
+    }
+
+    fn write_function(&mut self, function_name: &str, nvars: i16) -> io::Result<()> {
+        let name = format!("f_{}", function_name);
+        if self.functions.iter().any(|function| function.name == name) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("function {} defined twice", function_name),
+            ));
+        }
+        self.functions.push(Function {
+            name,
+            code: Vec::new(),
+        });
+        let locals = vec!["(call $push (i32.const 0))".to_string(); nvars.max(0) as usize];
+        self.command(&format!("function {} {}", function_name, nvars), &locals);
+        Ok(())
+    }
+
+    fn write_call(&mut self, function_name: &str, nargs: i16) -> io::Result<()> {
+        let mut lines = self.frame(function_name, nargs);
+        // unwind once the callee has halted or stopped
+        lines.push("(if (global.get $state) (then (return)))".to_string());
+        self.command(&format!("call {} {}", function_name, nargs), &lines);
+        Ok(())
+    }
+
+    fn write_return(&mut self) -> io::Result<()> {
+        let pointer = |register: usize, offset: i16| {
+            format!(
+                "(call $set (i32.const {}) {})",
+                register,
+                get(&format!(
+                    "(i32.sub (local.get $frame) (i32.const {}))",
+                    offset
+                ))
+            )
+        };
+        self.command(
+            "return",
+            &[
+                format!("(local.set $frame {})", get("(i32.const 1)")),
+                format!("(call $set {} (call $pop))", get("(i32.const 2)")),
+                format!(
+                    "(call $set (i32.const 0) (i32.add {} (i32.const 1)))",
+                    get("(i32.const 2)")
+                ),
+                pointer(4, 1),
+                pointer(3, 2),
+                pointer(2, 3),
+                pointer(1, 4),
+                "(return)".to_string(),
+            ],
+        );
+        Ok(())
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        let defined: HashSet<&str> = self.functions.iter().map(|f| f.name.as_str()).collect();
+        let defined = |name: &String| defined.contains(format!("f_{}", name).as_str());
+        if let Some(missing) = self.calls.iter().find(|name| !defined(name)) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("call to undefined function {}", missing),
+            ));
+        }
+        write!(self.output_file.file, "{}", PRELUDE)?;
+        let functions = std::mem::take(&mut self.functions);
+        for (i, function) in functions.iter().enumerate() {
+            let next = functions.get(i + 1).map(|next| next.name.as_str());
+            self.write_function_code(function, next)?;
+        }
+
+        let file = &mut self.output_file.file;
+        writeln!(
+            file,
+            "\n  (func (export \"run\") (param $limit i64) (result i32)"
+        )?;
+        writeln!(file, "    (global.set $limit (local.get $limit))")?;
+        writeln!(file, "    (global.set $count (i64.const 0))")?;
+        writeln!(file, "    (global.set $state (i32.const 0))")?;
+        for line in &self.run {
+            writeln!(file, "    {}", line)?;
+        }
+        if self.is_test {
+            writeln!(file, "    (call $top)")?;
+        }
+        writeln!(
+            file,
+            "    (if (i32.eqz (global.get $state)) (then (global.set $state (i32.const 1))))"
+        )?;
+        writeln!(file, "    (global.get $state)))")?;
+        self.output_file.commit()
+    }
+}
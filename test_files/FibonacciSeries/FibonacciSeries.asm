@@ -138,7 +138,8 @@ M=D
 M=M+1
 //if-goto
 @SP
-M=M-1
+AM=M-1
+D=M
 @COMPUTE_ELEMENT
 D;JNE
 //goto
@@ -83,41 +83,60 @@ A=M
 M=D
 @SP
 M=M+1
+//lt
 @SP
-M=M-1
+AM=M-1
+D=M
+@R13
+M=D
 @SP
-A=M
+A=M-1
+D=M
+@XNEG_0
+D;JLT
+@R13
+D=M
+@SAME_SIGN_0
+D;JGE
+D=0
+@CONTINUE_0
+0;JMP
+(XNEG_0)
+@R13
+D=M
+@SAME_SIGN_0
+D;JLT
+D=-1
+@CONTINUE_0
+0;JMP
+(SAME_SIGN_0)
+@R13
 D=M
 @SP
 A=M-1
-D=D-M
+D=M-D
 @TRUE_0
-D;JGT
-@SP
-M=M-1
-A=M
-M=0
+D;JLT
+D=0
 @CONTINUE_0
 0;JMP
 (TRUE_0)
-@SP
-M=M-1
-A=M
-M=-1
+D=-1
 (CONTINUE_0)
 @SP
-M=M+1
+A=M-1
+M=D
 //if-goto
 @SP
 AM=M-1
 D=M
-@IF_TRUE
+@Main.fibonacci$IF_TRUE
 D;JNE
 //goto
-@IF_FALSE
+@Main.fibonacci$IF_FALSE
 0; JMP
 //label
-(IF_TRUE)
+(Main.fibonacci$IF_TRUE)
 //push argument
 @0
 D=A
@@ -196,7 +215,7 @@ M=D
 A=M
 0; JMP
 //label
-(IF_FALSE)
+(Main.fibonacci$IF_FALSE)
 //push argument
 @0
 D=A
@@ -518,7 +537,7 @@ M=D
 //label
 (Main.fibonacci$ret.3)
 //label
-(WHILE)
+(Sys.init$WHILE)
 //goto
-@WHILE
+@Sys.init$WHILE
 0; JMP
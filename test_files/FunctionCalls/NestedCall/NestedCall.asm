@@ -1,7 +1,71 @@
-//function
+@256
+D=A
+@0
+M=D
+//push returnAddr
+@Sys.init$ret.0
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+//push lcl
+@LCL
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+//push arg
+@ARG
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+//push this
+@THIS
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+//push that
+@THAT
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+//arg=sp-5-nargs
+@SP
+D=M
+@5
+D=D-A
+@0
+D=D-A
+@ARG
+M=D
+//lcl=sp
+@SP
+D=M
+@LCL
+M=D
+//goto
 @Sys.init
+0; JMP
+//label
+(Sys.init$ret.0)
+//function
+//label
+(Sys.init)
 //push returnAddr
-@Sys.main$ret.0
+@Sys.main$ret.1
 D=A
 @SP
 A=M
@@ -58,7 +122,7 @@ M=D
 @Sys.main
 0; JMP
 //label
-(Sys.main$ret.0)
+(Sys.main$ret.1)
 //pop temp
 // decrement stack pointer
 @SP
@@ -80,12 +144,13 @@ D=M
 A=M
 M=D
 //label
-(LOOP)
+(Sys.init$LOOP)
 //goto
-@LOOP
+@Sys.init$LOOP
 0; JMP
 //function
-@Sys.main
+//label
+(Sys.main)
 // push constant
 @123
 D=A
@@ -95,7 +160,7 @@ M=D
 @SP
 M=M+1
 //push returnAddr
-@Sys.add12$ret.1
+@Sys.add12$ret.2
 D=A
 @SP
 A=M
@@ -152,7 +217,7 @@ M=D
 @Sys.add12
 0; JMP
 //label
-(Sys.add12$ret.1)
+(Sys.add12$ret.2)
 //pop temp
 // decrement stack pointer
 @SP
@@ -248,7 +313,8 @@ M=D
 A=M
 0; JMP
 //function
-@Sys.add12
+//label
+(Sys.add12)
 //nvars
 // push constant
 @0
@@ -1,5 +1,6 @@
 //function
-@SimpleFunction.test
+//label
+(SimpleFunction.test)
 //nvars
 // push constant
 @0
@@ -0,0 +1,158 @@
+// Golden-file suite over test_files. Every program is translated and
+// assembled; a directory with a CPU emulator script (`Foo.tst` loading
+// `Foo.asm`) also has its .asm compared with the checked-in one and the
+// script run against its .cmp file.
+//
+//     cargo test --test golden                # check
+//     cargo test --test golden -- --bless     # rewrite the .asm snapshots
+use hack_vm::assembler::assemble;
+use hack_vm::code_writer::CodeWriter;
+use hack_vm::compiler::{compile_program, files_with_extension, read_program, VmFile};
+use hack_vm::emulator::Emulator;
+use hack_vm::test_script::TestScript;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const TEST_FILES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_files");
+
+// a program directory has a Sys.vm (bootstrapped) or a .tst script
+// (scripts set up the stack themselves); other .vm files stand alone and
+// are translated in test mode
+struct Program {
+    name: PathBuf,
+    vm_files: Vec<PathBuf>,
+    bootstrap: bool,
+    test: bool,
+    scripts: Vec<PathBuf>,
+}
+
+fn programs(dir: &Path, programs: &mut Vec<Program>) {
+    let vm_files = files_with_extension(dir, "vm");
+    // `FooVME.tst` scripts are for the VM emulator and load the .vm files
+    let scripts: Vec<PathBuf> = files_with_extension(dir, "tst")
+        .into_iter()
+        .filter(|script| !script.to_string_lossy().ends_with("VME.tst"))
+        .collect();
+    let bootstrap = vm_files
+        .iter()
+        .any(|file| file.file_name() == Some(OsStr::new("Sys.vm")));
+    if bootstrap || !scripts.is_empty() {
+        programs.push(Program {
+            name: dir.to_path_buf(),
+            vm_files,
+            bootstrap,
+            test: false,
+            scripts,
+        });
+    } else {
+        for vm_file in vm_files {
+            programs.push(Program {
+                name: vm_file.clone(),
+                vm_files: vec![vm_file],
+                bootstrap: false,
+                test: true,
+                scripts: Vec::new(),
+            });
+        }
+    }
+    let mut dirs: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    for dir in dirs {
+        self::programs(&dir, programs);
+    }
+}
+
+fn translate(program: &Program) -> Result<String, String> {
+    let commands = read_program(&program.vm_files, false).map_err(|err| err.to_string())?;
+    let path = std::env::temp_dir().join(format!("hack_vm_golden_{}.asm", std::process::id()));
+    let file = VmFile::create(&path).map_err(|err| err.to_string())?;
+    let code_writer = CodeWriter::new(file, !program.bootstrap).map_err(|err| err.to_string())?;
+    compile_program(&commands, code_writer, program.test).map_err(|err| err.to_string())?;
+    let asm = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+    _ = fs::remove_file(&path);
+    Ok(asm)
+}
+
+// compare with the snapshot, or replace it when blessing
+fn check_snapshot(asm: &str, snapshot: &Path, bless: bool) -> Result<(), String> {
+    let expected = fs::read_to_string(snapshot).unwrap_or_default();
+    if expected == asm {
+        return Ok(());
+    }
+    if bless {
+        fs::write(snapshot, asm).map_err(|err| err.to_string())?;
+        println!("blessed {}", snapshot.display());
+        return Ok(());
+    }
+    let line = expected
+        .lines()
+        .zip(asm.lines())
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or(expected.lines().count().min(asm.lines().count()));
+    Err(format!(
+        "{} differs at line {}: expected {:?}, got {:?} (run with --bless to update)",
+        snapshot.display(),
+        line + 1,
+        expected.lines().nth(line).unwrap_or(""),
+        asm.lines().nth(line).unwrap_or("")
+    ))
+}
+
+fn check(program: &Program, bless: bool) -> Result<(), String> {
+    let asm = translate(program)?;
+    let rom = assemble("test.asm", &asm)
+        .map_err(|err| err.to_string())?
+        .rom;
+    for script_path in &program.scripts {
+        let script = TestScript::parse(&fs::read_to_string(script_path).unwrap())
+            .map_err(|err| format!("{}: {}", script_path.display(), err))?;
+        let load = script.load.as_ref().ok_or("script loads no program")?;
+        check_snapshot(&asm, &program.name.join(load), bless)?;
+
+        let mut emulator = Emulator::new(rom.clone());
+        let output = script.run(&mut emulator);
+        if let Some(compare_to) = &script.compare_to {
+            let cmp_path = program.name.join(compare_to);
+            let expected = fs::read_to_string(&cmp_path).map_err(|err| err.to_string())?;
+            script
+                .compare(&output, &expected)
+                .map_err(|err| format!("{}: {}", cmp_path.display(), err))?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let bless = std::env::args().any(|arg| arg == "--bless");
+    let mut all = Vec::new();
+    programs(Path::new(TEST_FILES), &mut all);
+
+    let mut failures = 0;
+    for program in &all {
+        let name = program
+            .name
+            .strip_prefix(TEST_FILES)
+            .unwrap_or(&program.name);
+        match check(program, bless) {
+            Ok(()) => println!("golden {} ... ok", name.display()),
+            Err(err) => {
+                println!("golden {} ... FAILED\n    {}", name.display(), err);
+                failures += 1;
+            }
+        }
+    }
+    println!(
+        "\ngolden result: {} passed; {} failed",
+        all.len() - failures,
+        failures
+    );
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,367 @@
+// Property tests for the translator: random well-formed VM programs are
+// translated, assembled and run on the emulator, then compared with the
+// reference interpreter running the same commands.
+use hack_vm::assembler::assemble;
+use hack_vm::code_writer::CodeWriter;
+use hack_vm::compiler::{compile_program, read_numbered_commands, VmFile};
+use hack_vm::emulator::Emulator;
+use hack_vm::interpreter::Interpreter;
+use hack_vm::parser::{Command, Parser};
+use proptest::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Sys.init points THIS and THAT here, so this/that writes land in a heap
+// area that is compared as well
+const THIS_BASE: i16 = 3000;
+const THAT_BASE: i16 = 3050;
+const HEAP_END: usize = 3100;
+
+const MAX_CYCLES: u64 = 5_000_000;
+
+// one step of a generated function body. The builder turns these into
+// commands, pushing operands when the stack is too shallow, so any sequence
+// of ops makes a well-formed program
+#[derive(Debug, Clone)]
+enum Op {
+    // a value anywhere in the 16-bit range, `push constant` then `neg` if negative
+    Constant(i16),
+    Push(usize, i16),
+    Pop(usize, i16),
+    Unary(usize),
+    Binary(usize),
+    // `div` and `mod` by a nonzero value, shifts by 0..15
+    Divide(bool, i16),
+    Shift(bool, i16),
+    // a forward `if-goto` over the following ops, up to the matching EndIf
+    If,
+    EndIf,
+    // call an earlier function, so there's no recursion
+    Call(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    nargs: i16,
+    nvars: i16,
+    body: Vec<Op>,
+    // end with `call f n` + `return`, which --tail-calls turns into a jump
+    tail_call: Option<usize>,
+}
+
+const SEGMENTS: [&str; 6] = ["temp", "static", "this", "that", "local", "argument"];
+const UNARY: [&str; 2] = ["neg", "not"];
+const BINARY: [&str; 7] = ["add", "sub", "eq", "gt", "lt", "and", "or"];
+const EXTENDED: [&str; 2] = ["mul", "xor"];
+
+fn constant() -> impl Strategy<Value = i16> {
+    // bias towards the edges of the range, where overflow bugs live
+    prop_oneof![
+        any::<i16>(),
+        -3i16..=3,
+        32760i16..=32767,
+        -32768i16..=-32760,
+    ]
+}
+
+fn op(extended: bool) -> impl Strategy<Value = Op> {
+    let binary = if extended {
+        BINARY.len() + EXTENDED.len()
+    } else {
+        BINARY.len()
+    };
+    let ops = prop_oneof![
+        4 => constant().prop_map(Op::Constant),
+        3 => (0..SEGMENTS.len(), 0i16..8).prop_map(|(s, i)| Op::Push(s, i)),
+        3 => (0..SEGMENTS.len(), 0i16..8).prop_map(|(s, i)| Op::Pop(s, i)),
+        1 => (0..UNARY.len()).prop_map(Op::Unary),
+        4 => (0..binary).prop_map(Op::Binary),
+        1 => Just(Op::If),
+        1 => Just(Op::EndIf),
+        1 => (0usize..8).prop_map(Op::Call),
+    ];
+    let extended_ops = prop_oneof![
+        (
+            any::<bool>(),
+            constant().prop_filter("nonzero", |v| *v != 0)
+        )
+            .prop_map(|(m, v)| Op::Divide(m, v)),
+        (any::<bool>(), 0i16..16).prop_map(|(r, v)| Op::Shift(r, v)),
+    ];
+    if extended {
+        prop_oneof![10 => ops, 1 => extended_ops].boxed()
+    } else {
+        ops.boxed()
+    }
+}
+
+fn function(extended: bool) -> impl Strategy<Value = Function> {
+    (
+        0i16..4,
+        0i16..4,
+        prop::collection::vec(op(extended), 0..24),
+        prop::option::of(0usize..8),
+    )
+        .prop_map(|(nargs, nvars, body, tail_call)| Function {
+            nargs,
+            nvars,
+            body,
+            tail_call,
+        })
+}
+
+// Main.f0 .. Main.fn followed by Sys.init, which is built like any other function
+fn program(extended: bool) -> impl Strategy<Value = Vec<Function>> {
+    prop::collection::vec(function(extended), 1..6)
+}
+
+struct Builder<'a> {
+    commands: Vec<Command>,
+    functions: &'a [Function],
+    // functions this one may call: those before it
+    callable: usize,
+    nargs: i16,
+    nvars: i16,
+    depth: usize,
+    // stack depth at each open `if`, with its label
+    open: Vec<(usize, String)>,
+    labels: usize,
+}
+
+impl Builder<'_> {
+    fn emit(&mut self, command: Command) {
+        self.commands.push(command);
+    }
+
+    fn push_constant(&mut self, value: i16) {
+        // constants are 0..32767, anything else is built with neg
+        if value == i16::MIN {
+            self.emit(Command::Push("constant".to_string(), i16::MAX));
+            self.emit(Command::Arithmetic("neg".to_string()));
+            self.emit(Command::Push("constant".to_string(), 1));
+            self.emit(Command::Arithmetic("sub".to_string()));
+        } else if value < 0 {
+            self.emit(Command::Push("constant".to_string(), -value));
+            self.emit(Command::Arithmetic("neg".to_string()));
+        } else {
+            self.emit(Command::Push("constant".to_string(), value));
+        }
+        self.depth += 1;
+    }
+
+    // make sure at least `n` values are on the stack
+    fn ensure(&mut self, n: usize) {
+        while self.depth < n {
+            self.push_constant(self.depth as i16 + 7);
+        }
+    }
+
+    // segment and index, with local and argument indices kept in range
+    fn segment(&self, segment: usize, index: i16) -> Option<(String, i16)> {
+        let count = match SEGMENTS[segment] {
+            "local" => self.nvars,
+            "argument" => self.nargs,
+            _ => 8,
+        };
+        if count == 0 {
+            return None;
+        }
+        let index = index % count;
+        Some((SEGMENTS[segment].to_string(), index))
+    }
+
+    fn call(&mut self, function: usize) {
+        let nargs = self.functions[function].nargs;
+        self.ensure(nargs as usize);
+        self.emit(Command::Call(format!("Main.f{}", function), nargs));
+        self.depth = self.depth + 1 - nargs as usize;
+    }
+
+    fn close_if(&mut self) {
+        if let Some((depth, label)) = self.open.pop() {
+            // both paths must reach the label with the same stack depth
+            self.ensure(depth);
+            while self.depth > depth {
+                self.emit(Command::Pop("temp".to_string(), 7));
+                self.depth -= 1;
+            }
+            self.emit(Command::Label(label));
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        match op {
+            Op::Constant(value) => self.push_constant(*value),
+            Op::Push(segment, index) => {
+                if let Some((segment, index)) = self.segment(*segment, *index) {
+                    self.emit(Command::Push(segment, index));
+                    self.depth += 1;
+                }
+            }
+            Op::Pop(segment, index) => {
+                if let Some((segment, index)) = self.segment(*segment, *index) {
+                    self.ensure(1);
+                    self.emit(Command::Pop(segment, index));
+                    self.depth -= 1;
+                }
+            }
+            Op::Unary(command) => {
+                self.ensure(1);
+                self.emit(Command::Arithmetic(UNARY[*command].to_string()));
+            }
+            Op::Binary(command) => {
+                self.ensure(2);
+                let command = BINARY.iter().chain(EXTENDED.iter()).nth(*command);
+                self.emit(Command::Arithmetic(command.unwrap().to_string()));
+                self.depth -= 1;
+            }
+            Op::Divide(remainder, divisor) => {
+                self.ensure(1);
+                self.push_constant(*divisor);
+                let command = if *remainder { "mod" } else { "div" };
+                self.emit(Command::Arithmetic(command.to_string()));
+                self.depth -= 1;
+            }
+            Op::Shift(right, amount) => {
+                self.ensure(1);
+                self.push_constant(*amount);
+                let command = if *right { "shr" } else { "shl" };
+                self.emit(Command::Arithmetic(command.to_string()));
+                self.depth -= 1;
+            }
+            Op::If => {
+                self.ensure(1);
+                // the same label name in every function, they are scoped
+                let label = format!("SKIP{}", self.labels);
+                self.labels += 1;
+                self.emit(Command::IfGoto(label.clone()));
+                self.depth -= 1;
+                self.open.push((self.depth, label));
+            }
+            Op::EndIf => self.close_if(),
+            Op::Call(function) if self.callable > 0 => self.call(function % self.callable),
+            Op::Call(_) => {}
+        }
+    }
+}
+
+fn build(functions: &[Function]) -> Vec<(String, Vec<Command>)> {
+    let mut main = Vec::new();
+    let mut sys = Vec::new();
+    for (i, function) in functions.iter().enumerate() {
+        let is_sys = i == functions.len() - 1;
+        let mut builder = Builder {
+            commands: Vec::new(),
+            functions,
+            callable: i,
+            nargs: if is_sys { 0 } else { function.nargs },
+            nvars: function.nvars,
+            depth: 0,
+            open: Vec::new(),
+            labels: 0,
+        };
+        if is_sys {
+            builder.emit(Command::Function("Sys.init".to_string(), function.nvars));
+            for (pointer, base) in [(0, THIS_BASE), (1, THAT_BASE)] {
+                builder.emit(Command::Push("constant".to_string(), base));
+                builder.emit(Command::Pop("pointer".to_string(), pointer));
+            }
+        } else {
+            builder.emit(Command::Function(format!("Main.f{}", i), function.nvars));
+        }
+        for op in &function.body {
+            builder.op(op);
+        }
+        while !builder.open.is_empty() {
+            builder.close_if();
+        }
+        if is_sys {
+            builder.emit(Command::Label("END".to_string()));
+            builder.emit(Command::Goto("END".to_string()));
+            sys = builder.commands;
+        } else {
+            match function.tail_call {
+                Some(callee) if i > 0 => builder.call(callee % i),
+                _ => builder.ensure(1),
+            }
+            builder.emit(Command::Return);
+            main.extend(builder.commands);
+        }
+    }
+    vec![("Main".to_string(), main), ("Sys".to_string(), sys)]
+}
+
+fn translate(program: &[(String, Vec<Command>)], extended: bool, tail_calls: bool) -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "hack_vm_property_{}_{}.asm",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut code_writer = CodeWriter::new(VmFile::create(&path).unwrap(), false).unwrap();
+    code_writer.set_extended(extended);
+    code_writer.set_tail_calls(tail_calls);
+    compile_program(program, code_writer, false).unwrap();
+    let asm = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    asm
+}
+
+// the RAM both must agree on: pointers and temp, statics, the stack below SP
+// (the return address of Sys.init's frame aside) and the heap
+fn compared(ram: &[i16]) -> Vec<(usize, i16)> {
+    let sp = ram[0] as usize;
+    (0..13)
+        .chain(16..256)
+        .chain(257..sp)
+        .chain(THIS_BASE as usize..HEAP_END)
+        .map(|address| (address, ram[address]))
+        .collect()
+}
+
+fn check(functions: &[Function], extended: bool, tail_calls: bool) -> Result<(), TestCaseError> {
+    let program = build(functions);
+    let asm = translate(&program, extended, tail_calls);
+    let rom = assemble("Prog.asm", &asm).unwrap().rom;
+    let mut emulator = Emulator::new(rom);
+    prop_assert!(emulator.run(MAX_CYCLES), "emulator did not halt");
+
+    let mut interpreter = Interpreter::new(&program);
+    interpreter.bootstrap().unwrap();
+    prop_assert!(
+        interpreter.run(MAX_CYCLES).unwrap(),
+        "interpreter did not halt"
+    );
+
+    prop_assert_eq!(compared(&emulator.ram), compared(&interpreter.ram));
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn translated_programs_match_the_interpreter(functions in program(false)) {
+        check(&functions, false, false)?;
+    }
+
+    #[test]
+    fn extended_commands_match_the_interpreter(functions in program(true)) {
+        check(&functions, true, false)?;
+    }
+
+    #[test]
+    fn tail_calls_match_the_interpreter(functions in program(false)) {
+        check(&functions, false, true)?;
+    }
+
+    // arbitrary bytes must come back as commands or errors, never a panic
+    #[test]
+    fn parser_never_panics_on_bytes(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+        let source = String::from_utf8_lossy(&bytes);
+        let lines = source.lines().map(|line| line.to_string()).collect();
+        let mut parser = Parser::new(lines);
+        parser.set_extended(true);
+        for _ in parser.by_ref() {}
+        _ = read_numbered_commands("Fuzz.vm", &source, true);
+    }
+}